@@ -23,9 +23,10 @@ use mime_guess::mime;
 use simple_logger::SimpleLogger;
 use tokio::runtime;
 
-use grammers_client::session::Session;
+use grammers_client::session::FileSession;
 use grammers_client::types::Media::{Contact, Document, Photo, Sticker};
 use grammers_client::types::{Downloadable, Media};
+use std::sync::Arc;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -42,8 +43,9 @@ async fn async_main() -> Result<()> {
     let chat_name = env::args().nth(1).expect("chat name missing");
 
     println!("Connecting to Telegram...");
+    let session = Arc::new(FileSession::load_file_or_create(SESSION_FILE)?);
     let client = Client::connect(Config {
-        session: Session::load_file_or_create(SESSION_FILE)?,
+        session: session.clone(),
         api_id,
         api_hash: api_hash.clone(),
         params: Default::default(),
@@ -76,7 +78,8 @@ async fn async_main() -> Result<()> {
             Err(e) => panic!("{}", e),
         };
         println!("Signed in!");
-        match client.session().save_to_file(SESSION_FILE) {
+        client.sync_update_state();
+        match session.save_to_file(SESSION_FILE) {
             Ok(_) => {}
             Err(e) => {
                 println!("NOTE: failed to save the session, will sign out when done: {e}");
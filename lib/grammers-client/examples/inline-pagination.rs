@@ -23,11 +23,12 @@
 //! in decimal, so the numbers can't get too large).
 
 use futures_util::future::{select, Either};
-use grammers_client::session::Session;
+use grammers_client::session::FileSession;
 use grammers_client::{button, reply_markup, Client, Config, InputMessage, Update};
 use simple_logger::SimpleLogger;
 use std::env;
 use std::pin::pin;
+use std::sync::Arc;
 use tokio::{runtime, task};
 
 type Result = std::result::Result<(), Box<dyn std::error::Error>>;
@@ -116,8 +117,9 @@ async fn async_main() -> Result {
     let token = env::args().nth(1).expect("token missing");
 
     println!("Connecting to Telegram...");
+    let session = Arc::new(FileSession::load_file_or_create(SESSION_FILE)?);
     let client = Client::connect(Config {
-        session: Session::load_file_or_create(SESSION_FILE)?,
+        session: session.clone(),
         api_id,
         api_hash: api_hash.clone(),
         params: Default::default(),
@@ -128,7 +130,8 @@ async fn async_main() -> Result {
     if !client.is_authorized().await? {
         println!("Signing in...");
         client.bot_sign_in(&token).await?;
-        client.session().save_to_file(SESSION_FILE)?;
+        client.sync_update_state();
+        session.save_to_file(SESSION_FILE)?;
         println!("Signed in!");
     }
 
@@ -154,7 +157,8 @@ async fn async_main() -> Result {
     }
 
     println!("Saving session file...");
-    client.session().save_to_file(SESSION_FILE)?;
+    client.sync_update_state();
+    session.save_to_file(SESSION_FILE)?;
     Ok(())
 }
 
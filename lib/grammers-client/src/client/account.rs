@@ -0,0 +1,324 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use super::Client;
+use crate::types::Authorization;
+use crate::utils;
+use grammers_crypto::two_factor_auth::{calculate_password_hash, check_p_and_g, extend_salt1};
+pub use grammers_mtsender::InvocationError;
+use grammers_tl_types as tl;
+use std::fmt;
+
+/// The error type which is returned when managing the cloud password fails.
+#[derive(Debug)]
+pub enum PasswordError {
+    /// The current password was incorrect.
+    InvalidPassword,
+    /// The change will only take effect once the recovery email is confirmed with
+    /// [`Client::confirm_password_email`], using the code of this length which was sent to it.
+    EmailUnconfirmed {
+        code_length: u32,
+    },
+    /// The code to confirm the recovery email was incorrect.
+    InvalidCode,
+    /// Telegram sent parameters to derive the new password from which failed validation, so it
+    /// could not be set safely.
+    InvalidParameters,
+    Other(InvocationError),
+}
+
+impl fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use PasswordError::*;
+        match self {
+            InvalidPassword => write!(f, "invalid password"),
+            EmailUnconfirmed { code_length } => write!(
+                f,
+                "password error: recovery email must be confirmed with a code of length {code_length}"
+            ),
+            InvalidCode => write!(f, "password error: invalid code"),
+            InvalidParameters => write!(f, "password error: invalid parameters from telegram"),
+            Other(e) => write!(f, "password error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for PasswordError {}
+
+impl From<InvocationError> for PasswordError {
+    fn from(error: InvocationError) -> Self {
+        match error {
+            InvocationError::Rpc(e) if e.is("PASSWORD_HASH_INVALID") => Self::InvalidPassword,
+            InvocationError::Rpc(e) if e.is("EMAIL_UNCONFIRMED") => Self::EmailUnconfirmed {
+                code_length: e.value.unwrap_or(0),
+            },
+            InvocationError::Rpc(e) if e.is("CODE_INVALID") => Self::InvalidCode,
+            error => Self::Other(error),
+        }
+    }
+}
+
+/// Build the settings to set `new_password` as the cloud password, using the `new_algo` sent by
+/// Telegram in `password_info`.
+pub(crate) fn new_password_settings(
+    password_info: &tl::types::account::Password,
+    new_password: impl AsRef<[u8]>,
+    hint: Option<&str>,
+) -> Result<tl::types::account::PasswordInputSettings, PasswordError> {
+    let (salt1, salt2, p, g) = utils::extract_password_parameters(&password_info.new_algo);
+    if !check_p_and_g(p, g) {
+        return Err(PasswordError::InvalidParameters);
+    }
+
+    let salt1 = extend_salt1(salt1);
+    let new_password_hash = calculate_password_hash(&salt1, salt2, p, g, new_password);
+
+    Ok(tl::types::account::PasswordInputSettings {
+        new_algo: Some(
+            tl::types::PasswordKdfAlgoSha256Sha256Pbkdf2Hmacsha512iter100000Sha256ModPow {
+                salt1,
+                salt2: salt2.clone(),
+                g: *g,
+                p: p.clone(),
+            }
+            .into(),
+        ),
+        new_password_hash: Some(new_password_hash.to_vec()),
+        hint: Some(hint.unwrap_or_default().to_string()),
+        email: None,
+        new_secure_settings: None,
+    })
+}
+
+/// Method implementations related to managing the account of the logged-in user.
+impl Client {
+    /// Set a new cloud password for the account, which will be required to sign in on top of the
+    /// login code.
+    ///
+    /// If the account already has a password, the `current_password` must be given in order to
+    /// change it. This is also the way to change the hint alone, by setting the same password.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// client.set_password(None, "hunter2", Some("the usual")).await?;
+    ///
+    /// // ...later on...
+    /// client.set_password(Some("hunter2".as_bytes()), "correct horse", None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_password(
+        &self,
+        current_password: Option<&[u8]>,
+        new_password: impl AsRef<[u8]>,
+        hint: Option<&str>,
+    ) -> Result<(), PasswordError> {
+        self.update_password_settings(current_password, |password_info| {
+            new_password_settings(password_info, new_password, hint)
+        })
+        .await
+    }
+
+    /// Remove the cloud password from the account, along with its hint and recovery email.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// client.remove_password("hunter2").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn remove_password(
+        &self,
+        current_password: impl AsRef<[u8]>,
+    ) -> Result<(), PasswordError> {
+        self.update_password_settings(Some(current_password.as_ref()), |_| {
+            Ok(tl::types::account::PasswordInputSettings {
+                new_algo: Some(tl::enums::PasswordKdfAlgo::Unknown),
+                new_password_hash: Some(Vec::new()),
+                hint: Some(String::new()),
+                email: None,
+                new_secure_settings: None,
+            })
+        })
+        .await
+    }
+
+    /// Set the email which can be used to recover the cloud password if it's forgotten.
+    ///
+    /// Telegram will send a code to the email, so this will usually fail with
+    /// [`PasswordError::EmailUnconfirmed`], and the email has to be confirmed with
+    /// [`Client::confirm_password_email`] before it's used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use grammers_client::PasswordError;
+    ///
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// fn ask_code(length: u32) -> String {
+    ///     unimplemented!()
+    /// }
+    ///
+    /// match client.set_password_recovery_email("hunter2", "me@example.com").await {
+    ///     Ok(()) => {}
+    ///     Err(PasswordError::EmailUnconfirmed { code_length }) => {
+    ///         client.confirm_password_email(&ask_code(code_length)).await?;
+    ///     }
+    ///     Err(e) => return Err(e.into()),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_password_recovery_email(
+        &self,
+        current_password: impl AsRef<[u8]>,
+        email: &str,
+    ) -> Result<(), PasswordError> {
+        self.update_password_settings(Some(current_password.as_ref()), |_| {
+            Ok(tl::types::account::PasswordInputSettings {
+                new_algo: None,
+                new_password_hash: None,
+                hint: None,
+                email: Some(email.to_string()),
+                new_secure_settings: None,
+            })
+        })
+        .await
+    }
+
+    /// Confirm the recovery email with the code that Telegram sent to it.
+    pub async fn confirm_password_email(&self, code: &str) -> Result<(), PasswordError> {
+        self.invoke(&tl::functions::account::ConfirmPasswordEmail {
+            code: code.to_string(),
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Send the code to confirm the recovery email again.
+    pub async fn resend_password_email(&self) -> Result<(), InvocationError> {
+        self.invoke(&tl::functions::account::ResendPasswordEmail {})
+            .await?;
+        Ok(())
+    }
+
+    /// Cancel the change of the recovery email which is waiting for confirmation.
+    pub async fn cancel_password_email(&self) -> Result<(), InvocationError> {
+        self.invoke(&tl::functions::account::CancelPasswordEmail {})
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the active sessions where the account is logged in, including the one used by
+    /// this client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// for authorization in client.get_authorizations().await? {
+    ///     println!(
+    ///         "{} on {} ({}), last active from {}",
+    ///         authorization.app_name(),
+    ///         authorization.device_model(),
+    ///         authorization.platform(),
+    ///         authorization.ip(),
+    ///     );
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_authorizations(&self) -> Result<Vec<Authorization>, InvocationError> {
+        let tl::enums::account::Authorizations::Authorizations(authorizations) = self
+            .invoke(&tl::functions::account::GetAuthorizations {})
+            .await?;
+
+        Ok(authorizations
+            .authorizations
+            .into_iter()
+            .map(Authorization::from_raw)
+            .collect())
+    }
+
+    /// Terminate one of the other sessions of the account, logging it out.
+    ///
+    /// The session used by this client cannot be terminated this way. Use
+    /// [`Client::sign_out`] instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// for authorization in client.get_authorizations().await? {
+    ///     if !authorization.is_current() && !authorization.is_official_app() {
+    ///         client.terminate_authorization(&authorization).await?;
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn terminate_authorization(
+        &self,
+        authorization: &Authorization,
+    ) -> Result<(), InvocationError> {
+        self.invoke(&tl::functions::account::ResetAuthorization {
+            hash: authorization.hash(),
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Terminate every session of the account except for the one used by this client.
+    pub async fn terminate_other_authorizations(&self) -> Result<(), InvocationError> {
+        self.invoke(&tl::functions::auth::ResetAuthorizations {})
+            .await?;
+        Ok(())
+    }
+
+    /// Set after how many days of inactivity the sessions of the account are terminated
+    /// automatically.
+    pub async fn set_authorization_ttl(&self, days: i32) -> Result<(), InvocationError> {
+        self.invoke(&tl::functions::account::SetAuthorizationTtl {
+            authorization_ttl_days: days,
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Fetch the current password information, and use it to build the new settings which are
+    /// applied after proving that `current_password` is correct (if the account has a password).
+    async fn update_password_settings(
+        &self,
+        current_password: Option<&[u8]>,
+        new_settings: impl FnOnce(
+            &tl::types::account::Password,
+        )
+            -> Result<tl::types::account::PasswordInputSettings, PasswordError>,
+    ) -> Result<(), PasswordError> {
+        let password_info = self.get_password_information().await?.password;
+        let new_settings = new_settings(&password_info)?.into();
+
+        let password = match current_password {
+            Some(current_password) if password_info.has_password => {
+                self.input_check_password(password_info, current_password)
+                    .await?
+            }
+            _ => tl::enums::InputCheckPasswordSrp::InputCheckPasswordEmpty,
+        };
+
+        self.invoke(&tl::functions::account::UpdatePasswordSettings {
+            password,
+            new_settings,
+        })
+        .await?;
+        Ok(())
+    }
+}
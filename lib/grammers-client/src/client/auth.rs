@@ -5,14 +5,24 @@
 // <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
+use super::account::new_password_settings;
 use super::net::connect_sender;
 use super::Client;
-use crate::types::{LoginToken, PasswordToken, TermsOfService, User};
+use crate::types::{LoginToken, PasswordToken, QrLoginToken, SentCodeType, TermsOfService, User};
 use crate::utils;
+use futures_util::future::{select, Either};
 use grammers_crypto::two_factor_auth::{calculate_2fa, check_p_and_g};
+use grammers_mtproto::authentication;
 pub use grammers_mtsender::{AuthorizationError, InvocationError};
-use grammers_tl_types as tl;
+use grammers_mtsender::{ReadError, RpcError};
+use grammers_tl_types::{self as tl, Identifiable};
 use std::fmt;
+use std::pin::pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+
+/// How many times to follow `auth.loginTokenMigrateTo` before giving up on a QR login.
+const MAX_LOGIN_TOKEN_MIGRATIONS: usize = 3;
 
 /// The error type which is returned when signing in fails.
 #[derive(Debug)]
@@ -24,6 +34,16 @@ pub enum SignInError {
     PasswordRequired(PasswordToken),
     InvalidCode,
     InvalidPassword,
+    /// Generating an authorization key for the datacenter of the account failed, when the login
+    /// had to continue there.
+    ///
+    /// This variant is new since the 0.7 releases, so exhaustive matches need to handle it.
+    AuthKeyGen(authentication::Error),
+    /// Telegram sent parameters to derive the new password from which failed validation, so it
+    /// could not be set safely.
+    ///
+    /// This variant is new since the 0.7 releases, so exhaustive matches need to handle it.
+    InvalidPasswordParameters,
     Other(InvocationError),
 }
 
@@ -33,13 +53,17 @@ impl fmt::Display for SignInError {
         match self {
             SignUpRequired {
                 terms_of_service: tos,
-            } => write!(
-                f,
-                "sign in error: sign up with official client required: {tos:?}"
-            ),
+            } => write!(f, "sign in error: sign up required: {tos:?}"),
             PasswordRequired(_password) => write!(f, "2fa password required"),
             InvalidCode => write!(f, "sign in error: invalid code"),
             InvalidPassword => write!(f, "invalid password"),
+            AuthKeyGen(e) => write!(f, "sign in error: {e}"),
+            InvalidPasswordParameters => {
+                write!(
+                    f,
+                    "sign in error: invalid password parameters from telegram"
+                )
+            }
             Other(e) => write!(f, "sign in error: {e}"),
         }
     }
@@ -47,6 +71,28 @@ impl fmt::Display for SignInError {
 
 impl std::error::Error for SignInError {}
 
+/// The state of a login through a QR code.
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum QrLogin {
+    /// The token should be shown as a QR code for the user to scan, and then waited on with
+    /// [`Client::wait_qr_login`].
+    Token(QrLoginToken),
+    /// The token was accepted, and the user is now logged in.
+    Success(User),
+}
+
+/// The result of asking Telegram for a new login code.
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum LoginOutcome {
+    /// A new login code was sent, and this token replaces the previous one to sign in with it.
+    Token(LoginToken),
+    /// Telegram completed the login right away instead of sending a new login code, and the
+    /// client is now signed in as this user.
+    SignedIn(User),
+}
+
 /// Method implementations related with the authentication of the user into the API.
 ///
 /// Most requests to the API require the user to have authorized their key, stored in the session,
@@ -75,6 +121,7 @@ impl Client {
         match self.invoke(&tl::functions::updates::GetState {}).await {
             Ok(_) => Ok(true),
             Err(InvocationError::Rpc(e)) if e.code == 401 => Ok(false),
+            Err(InvocationError::Read(ReadError::AuthKeyLost)) => Ok(false),
             Err(err) => Err(err),
         }
     }
@@ -112,6 +159,16 @@ impl Client {
         Ok(user)
     }
 
+    /// Replace the connection to the home datacenter with a new one to `dc_id`, which becomes
+    /// the new home datacenter.
+    async fn migrate_to(&self, dc_id: i32) -> Result<(), AuthorizationError> {
+        let (sender, request_tx) = connect_sender(dc_id, false, &self.0.config).await?;
+        *self.0.conn.sender.lock().await = sender;
+        *self.0.conn.request_tx.write().unwrap() = request_tx;
+        self.0.state.write().unwrap().dc_id = dc_id;
+        Ok(())
+    }
+
     /// Signs in to the bot account associated with this token.
     ///
     /// This is the method you need to call to use the client under a bot account.
@@ -156,14 +213,7 @@ impl Client {
         let result = match self.invoke(&request).await {
             Ok(x) => x,
             Err(InvocationError::Rpc(err)) if err.code == 303 => {
-                let dc_id = err.value.unwrap() as i32;
-                let (sender, request_tx) = connect_sender(dc_id, &self.0.config).await?;
-                {
-                    *self.0.conn.sender.lock().await = sender;
-                    *self.0.conn.request_tx.write().unwrap() = request_tx;
-                    let mut state = self.0.state.write().unwrap();
-                    state.dc_id = dc_id;
-                }
+                self.migrate_to(err.value.unwrap() as i32).await?;
                 self.invoke(&request).await?
             }
             Err(e) => return Err(e.into()),
@@ -180,7 +230,8 @@ impl Client {
     }
 
     /// Requests the login code for the account associated to the given phone
-    /// number via another Telegram application or SMS.
+    /// number via another Telegram application, SMS, or one of the other ways
+    /// described by the returned token's [`sent_code_type`](LoginToken::sent_code_type).
     ///
     /// This is the method you need to call before being able to sign in to a user account.
     /// After you obtain the code and it's inside your program (e.g. ask the user to enter it
@@ -236,14 +287,7 @@ impl Client {
                 //
                 // Just connect and generate a new authorization key with it
                 // before trying again.
-                let dc_id = err.value.unwrap() as i32;
-                let (sender, request_tx) = connect_sender(dc_id, &self.0.config).await?;
-                {
-                    *self.0.conn.sender.lock().await = sender;
-                    *self.0.conn.request_tx.write().unwrap() = request_tx;
-                    let mut state = self.0.state.write().unwrap();
-                    state.dc_id = dc_id;
-                }
+                self.migrate_to(err.value.unwrap() as i32).await?;
                 match self.invoke(&request).await? {
                     SC::Code(code) => code,
                     SC::Success(_) => panic!("should not have logged in yet"),
@@ -252,10 +296,146 @@ impl Client {
             Err(e) => return Err(e.into()),
         };
 
-        Ok(LoginToken {
-            phone: phone.to_string(),
-            phone_code_hash: sent_code.phone_code_hash,
-        })
+        Ok(LoginToken::new(phone.to_string(), sent_code))
+    }
+
+    /// Requests the login code to be sent again, in the way given by the token's
+    /// [`next_type`](LoginToken::next_type), such as through SMS or a phone call.
+    ///
+    /// The returned token replaces the given one, and must be used to sign in instead. Telegram
+    /// may also complete the login without sending a new code, in which case the signed in user
+    /// is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// use grammers_client::LoginOutcome;
+    ///
+    /// # const PHONE: &str = "";
+    /// let mut token = client.request_login_code(PHONE).await?;
+    /// if token.next_type().is_some() {
+    ///     // The code did not arrive, so try again some other way.
+    ///     token = match client.resend_code(&token).await? {
+    ///         LoginOutcome::Token(token) => token,
+    ///         LoginOutcome::SignedIn(_user) => return Ok(()),
+    ///     };
+    /// }
+    /// println!("The code was sent via {:?}", token.sent_code_type());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn resend_code(&self, token: &LoginToken) -> Result<LoginOutcome, SignInError> {
+        let request = tl::functions::auth::ResendCode {
+            phone_number: token.phone.clone(),
+            phone_code_hash: token.phone_code_hash.clone(),
+            reason: None,
+        };
+
+        let sent_code = self.invoke(&request).await.map_err(SignInError::Other)?;
+        self.next_login_token(token, sent_code).await
+    }
+
+    /// Sends a code to verify the `email` which will be used as the login email of the account,
+    /// when Telegram requires one to be set up before sending the login code, as indicated by
+    /// [`SentCodeType::SetUpEmailRequired`].
+    ///
+    /// Returns the length of the code, which must be given to [`Client::verify_login_email`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// use grammers_client::LoginOutcome;
+    /// use grammers_client::types::SentCodeType;
+    ///
+    /// # const PHONE: &str = "";
+    /// fn ask_user(question: &str) -> String {
+    ///     unimplemented!()
+    /// }
+    ///
+    /// let mut token = client.request_login_code(PHONE).await?;
+    /// if let SentCodeType::SetUpEmailRequired = token.sent_code_type() {
+    ///     client.send_login_email_code(&token, &ask_user("Email:")).await?;
+    ///     token = match client.verify_login_email(&token, &ask_user("Email code:")).await? {
+    ///         LoginOutcome::Token(token) => token,
+    ///         LoginOutcome::SignedIn(_user) => return Ok(()),
+    ///     };
+    /// }
+    /// let user = client.sign_in(&token, &ask_user("Login code:")).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_login_email_code(
+        &self,
+        token: &LoginToken,
+        email: &str,
+    ) -> Result<i32, InvocationError> {
+        let tl::enums::account::SentEmailCode::Code(sent_code) = self
+            .invoke(&tl::functions::account::SendVerifyEmailCode {
+                purpose: token.email_verify_purpose(),
+                email: email.to_string(),
+            })
+            .await?;
+        Ok(sent_code.length)
+    }
+
+    /// Verifies the login email with the code sent by [`Client::send_login_email_code`].
+    ///
+    /// Telegram then sends the login code, and the returned token replaces the given one, and
+    /// must be used to sign in instead, unless it completed the login right away.
+    pub async fn verify_login_email(
+        &self,
+        token: &LoginToken,
+        code: &str,
+    ) -> Result<LoginOutcome, SignInError> {
+        let request = tl::functions::account::VerifyEmail {
+            purpose: token.email_verify_purpose(),
+            verification: tl::types::EmailVerificationCode {
+                code: code.to_string(),
+            }
+            .into(),
+        };
+
+        match self.invoke(&request).await {
+            Ok(tl::enums::account::EmailVerified::Login(verified)) => {
+                self.next_login_token(token, verified.sent_code).await
+            }
+            // The email was set up, but no new code was sent, so the old one is still needed.
+            Ok(tl::enums::account::EmailVerified::Verified(_)) => {
+                Ok(LoginOutcome::Token(token.clone()))
+            }
+            Err(err) if err.is("EMAIL_CODE_INVALID") || err.is("CODE_INVALID") => {
+                Err(SignInError::InvalidCode)
+            }
+            Err(error) => Err(SignInError::Other(error)),
+        }
+    }
+
+    /// Build the token to replace `token` with after Telegram sent a new login code, or complete
+    /// the login if it decided not to need one.
+    async fn next_login_token(
+        &self,
+        token: &LoginToken,
+        sent_code: tl::enums::auth::SentCode,
+    ) -> Result<LoginOutcome, SignInError> {
+        match sent_code {
+            tl::enums::auth::SentCode::Code(code) => Ok(LoginOutcome::Token(LoginToken::new(
+                token.phone.clone(),
+                code,
+            ))),
+            tl::enums::auth::SentCode::Success(success) => match success.authorization {
+                tl::enums::auth::Authorization::Authorization(x) => {
+                    let user = self.complete_login(x).await.map_err(SignInError::Other)?;
+                    Ok(LoginOutcome::SignedIn(user))
+                }
+                tl::enums::auth::Authorization::SignUpRequired(x) => {
+                    Err(SignInError::SignUpRequired {
+                        terms_of_service: x.terms_of_service.map(TermsOfService::from_raw),
+                    })
+                }
+            },
+        }
     }
 
     /// Signs in to the user account.
@@ -284,7 +464,9 @@ impl Client {
     /// let user = match client.sign_in(&token, &code).await {
     ///     Ok(user) => user,
     ///     Err(SignInError::PasswordRequired(_token)) => panic!("Please provide a password"),
-    ///     Err(SignInError::SignUpRequired { terms_of_service: tos }) => panic!("Sign up required"),
+    ///     Err(SignInError::SignUpRequired { terms_of_service: _ }) => {
+    ///         client.sign_up(&token, "First", "Last").await?
+    ///     }
     ///     Err(err) => {
     ///         println!("Failed to sign in as a user :(\n{}", err);
     ///         return Err(err.into());
@@ -300,12 +482,26 @@ impl Client {
     /// # }
     /// ```
     pub async fn sign_in(&self, token: &LoginToken, code: &str) -> Result<User, SignInError> {
+        // Codes sent to the login email verify the email rather than the phone.
+        let (phone_code, email_verification) = match token.sent_code_type {
+            SentCodeType::Email { .. } => (
+                None,
+                Some(
+                    tl::types::EmailVerificationCode {
+                        code: code.to_string(),
+                    }
+                    .into(),
+                ),
+            ),
+            _ => (Some(code.to_string()), None),
+        };
+
         match self
             .invoke(&tl::functions::auth::SignIn {
                 phone_number: token.phone.clone(),
                 phone_code_hash: token.phone_code_hash.clone(),
-                phone_code: Some(code.to_string()),
-                email_verification: None,
+                phone_code,
+                email_verification,
             })
             .await
         {
@@ -324,14 +520,250 @@ impl Client {
                     Err(e) => Err(SignInError::Other(e)),
                 }
             }
+            Err(err) if err.is("PHONE_CODE_*") || err.is("EMAIL_CODE_INVALID") => {
+                Err(SignInError::InvalidCode)
+            }
+            Err(error) => Err(SignInError::Other(error)),
+        }
+    }
+
+    /// Signs up a new user account, for a phone number which did not have one yet.
+    ///
+    /// This should only be used after [`Client::sign_in`] fails with
+    /// [`SignInError::SignUpRequired`], with the same login token. Those terms of service, if
+    /// any, should be shown to the user before signing up, and accepted with
+    /// [`Client::accept_terms_of_service`] after.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use grammers_client::SignInError;
+    ///
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// # const PHONE: &str = "";
+    /// # let code = "";
+    /// fn agrees_to(terms: &str) -> bool {
+    ///     unimplemented!()
+    /// }
+    ///
+    /// let token = client.request_login_code(PHONE).await?;
+    /// let user = match client.sign_in(&token, &code).await {
+    ///     Err(SignInError::SignUpRequired { terms_of_service }) => {
+    ///         if let Some(tos) = &terms_of_service {
+    ///             assert!(agrees_to(tos.text()));
+    ///         }
+    ///         let user = client.sign_up(&token, "First", "Last").await?;
+    ///         if let Some(tos) = &terms_of_service {
+    ///             client.accept_terms_of_service(tos).await?;
+    ///         }
+    ///         user
+    ///     }
+    ///     result => result?,
+    /// };
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn sign_up(
+        &self,
+        token: &LoginToken,
+        first_name: &str,
+        last_name: &str,
+    ) -> Result<User, SignInError> {
+        match self
+            .invoke(&tl::functions::auth::SignUp {
+                no_joined_notifications: false,
+                phone_number: token.phone.clone(),
+                phone_code_hash: token.phone_code_hash.clone(),
+                first_name: first_name.to_string(),
+                last_name: last_name.to_string(),
+            })
+            .await
+        {
+            Ok(tl::enums::auth::Authorization::Authorization(x)) => {
+                self.complete_login(x).await.map_err(SignInError::Other)
+            }
+            Ok(tl::enums::auth::Authorization::SignUpRequired(x)) => {
+                Err(SignInError::SignUpRequired {
+                    terms_of_service: x.terms_of_service.map(TermsOfService::from_raw),
+                })
+            }
             Err(err) if err.is("PHONE_CODE_*") => Err(SignInError::InvalidCode),
             Err(error) => Err(SignInError::Other(error)),
         }
     }
 
-    /// Extract information needed for the two-factor authentication
-    /// It's called automatically when we get SESSION_PASSWORD_NEEDED error during sign in.
-    async fn get_password_information(&self) -> Result<PasswordToken, InvocationError> {
+    /// Accepts the terms of service which were shown to the user when signing up.
+    pub async fn accept_terms_of_service(
+        &self,
+        terms_of_service: &TermsOfService,
+    ) -> Result<(), InvocationError> {
+        self.invoke(&tl::functions::help::AcceptTermsOfService {
+            id: terms_of_service.raw.id.clone(),
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Requests a token to log in to a user account by scanning a QR code from another
+    /// application where the account is already logged in, such as Telegram's official apps.
+    ///
+    /// Unless the account is logged in already, the returned [`QrLogin::Token`] must be shown
+    /// to the user as a QR code encoding its [URL](QrLoginToken::url). Then, the login will
+    /// be completed by [`Client::wait_qr_login`] as soon as the code is scanned.
+    ///
+    /// Sessions of the users in `except_ids` will not be able to accept the token. This can be
+    /// used to prevent logging in to the same account twice.
+    ///
+    /// It is recommended to save the [`Client::session()`] on successful login, and if saving
+    /// fails, it is recommended to [`Client::sign_out`]. If the session cannot be saved, then the
+    /// authorization will be "lost" in the list of logged-in clients, since it is unaccessible.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use grammers_client::QrLogin;
+    ///
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// fn show_qr_code(url: &str) {
+    ///     unimplemented!()
+    /// }
+    ///
+    /// let mut login = client.request_qr_login(&[]).await?;
+    /// let user = loop {
+    ///     match login {
+    ///         QrLogin::Token(token) => {
+    ///             show_qr_code(&token.url());
+    ///             login = client.wait_qr_login(token).await?;
+    ///         }
+    ///         QrLogin::Success(user) => break user,
+    ///     }
+    /// };
+    ///
+    /// println!("Signed in as {}!", user.full_name());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn request_qr_login(&self, except_ids: &[i64]) -> Result<QrLogin, SignInError> {
+        self.export_login_token(except_ids.to_vec()).await
+    }
+
+    /// Waits until the QR code of the token is scanned from another application, or until the
+    /// token expires, whichever happens first.
+    ///
+    /// If the token was accepted, the login is completed and [`QrLogin::Success`] is returned.
+    /// If it expired instead, a new [`QrLogin::Token`] is returned, whose QR code should replace
+    /// the previous one. If the account is on a different datacenter, the client will migrate
+    /// to it before completing the login.
+    ///
+    /// Updates received while waiting are queued as usual.
+    ///
+    /// See [`Client::request_qr_login`] for an example.
+    pub async fn wait_qr_login(&self, token: QrLoginToken) -> Result<QrLogin, SignInError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is before epoch")
+            .as_secs() as i64;
+        let expires_in = Duration::from_secs((token.expires as i64 - now).max(0) as u64);
+        let mut expired = pin!(sleep(expires_in));
+
+        while !self.take_login_token_update() {
+            let step = pin!(async { self.step().await });
+            match select(expired.as_mut(), step).await {
+                Either::Left(_) => break,
+                Either::Right((step, _)) => {
+                    step.map_err(|e| SignInError::Other(InvocationError::Read(e)))?
+                }
+            }
+        }
+
+        // The token was either accepted or has expired, and exporting it again tells which.
+        self.export_login_token(token.except_ids).await
+    }
+
+    /// Remove the update signaling that a login token was accepted from the queue, returning
+    /// whether it was found.
+    fn take_login_token_update(&self) -> bool {
+        let updates = &mut self.0.state.write().unwrap().updates;
+        match updates
+            .iter()
+            .position(|(update, _, _)| matches!(update, tl::enums::Update::LoginToken))
+        {
+            Some(i) => {
+                updates.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn export_login_token(&self, except_ids: Vec<i64>) -> Result<QrLogin, SignInError> {
+        use tl::enums::auth::LoginToken as LT;
+
+        let mut result = self
+            .invoke(&tl::functions::auth::ExportLoginToken {
+                api_id: self.0.config.api_id,
+                api_hash: self.0.config.api_hash.clone(),
+                except_ids: except_ids.clone(),
+            })
+            .await;
+
+        // The account lives in a different datacenter, where the token must be imported to
+        // complete the login. Telegram should only ask once, so it's not followed forever.
+        for _ in 0..MAX_LOGIN_TOKEN_MIGRATIONS {
+            let Ok(LT::MigrateTo(migrate)) = result else {
+                break;
+            };
+            match self.migrate_to(migrate.dc_id).await {
+                Ok(()) => {}
+                Err(AuthorizationError::Invoke(e)) => return Err(SignInError::Other(e)),
+                Err(AuthorizationError::Gen(e)) => return Err(SignInError::AuthKeyGen(e)),
+            }
+            result = self
+                .invoke(&tl::functions::auth::ImportLoginToken {
+                    token: migrate.token,
+                })
+                .await;
+        }
+
+        match result {
+            Ok(LT::Token(token)) => Ok(QrLogin::Token(QrLoginToken {
+                token: token.token,
+                expires: token.expires,
+                except_ids,
+            })),
+            Ok(LT::MigrateTo(migrate)) => Err(SignInError::Other(InvocationError::Rpc(RpcError {
+                code: 303,
+                name: "LOGIN_TOKEN_MIGRATE".to_string(),
+                value: Some(migrate.dc_id as u32),
+                caused_by: Some(tl::functions::auth::ImportLoginToken::CONSTRUCTOR_ID),
+            }))),
+            Ok(LT::Success(success)) => match success.authorization {
+                tl::enums::auth::Authorization::Authorization(x) => self
+                    .complete_login(x)
+                    .await
+                    .map(QrLogin::Success)
+                    .map_err(SignInError::Other),
+                tl::enums::auth::Authorization::SignUpRequired(x) => {
+                    Err(SignInError::SignUpRequired {
+                        terms_of_service: x.terms_of_service.map(TermsOfService::from_raw),
+                    })
+                }
+            },
+            Err(err) if err.is("SESSION_PASSWORD_NEEDED") => {
+                match self.get_password_information().await {
+                    Ok(token) => Err(SignInError::PasswordRequired(token)),
+                    Err(e) => Err(SignInError::Other(e)),
+                }
+            }
+            Err(error) => Err(SignInError::Other(error)),
+        }
+    }
+
+    /// Fetch the information about the cloud password of the account, such as its hint, or
+    /// whether it has one at all.
+    ///
+    /// This can be used before signing in, or to manage the password once signed in.
+    pub async fn get_password_information(&self) -> Result<PasswordToken, InvocationError> {
         let request = tl::functions::account::GetPassword {};
 
         let password: tl::types::account::Password = self.invoke(&request).await?.into();
@@ -339,6 +771,44 @@ impl Client {
         Ok(PasswordToken::new(password))
     }
 
+    /// Prove that the given password is the current password of the account, using the
+    /// parameters from `password_info`.
+    ///
+    /// If Telegram sent incorrect parameters, they are fetched again.
+    pub(crate) async fn input_check_password(
+        &self,
+        mut password_info: tl::types::account::Password,
+        password: impl AsRef<[u8]>,
+    ) -> Result<tl::enums::InputCheckPasswordSrp, InvocationError> {
+        let current_algo = password_info.current_algo.unwrap();
+        let mut params = utils::extract_password_parameters(&current_algo);
+
+        // Telegram sent us incorrect parameters, trying to get them again
+        if !check_p_and_g(params.2, params.3) {
+            password_info = self.get_password_information().await?.password;
+            params =
+                utils::extract_password_parameters(password_info.current_algo.as_ref().unwrap());
+            if !check_p_and_g(params.2, params.3) {
+                panic!("Failed to get correct password information from Telegram")
+            }
+        }
+
+        let (salt1, salt2, p, g) = params;
+
+        let g_b = password_info.srp_b.unwrap();
+        let a: Vec<u8> = password_info.secure_random;
+
+        let (m1, g_a) = calculate_2fa(salt1, salt2, p, g, g_b, a, password);
+
+        Ok(tl::enums::InputCheckPasswordSrp::Srp(
+            tl::types::InputCheckPasswordSrp {
+                srp_id: password_info.srp_id.unwrap(),
+                a: g_a.to_vec(),
+                m1: m1.to_vec(),
+            },
+        ))
+    }
+
     /// Sign in using two-factor authentication (user password).
     ///
     /// [`PasswordToken`] can be obtained from [`SignInError::PasswordRequired`] error after the
@@ -382,45 +852,88 @@ impl Client {
         password_token: PasswordToken,
         password: impl AsRef<[u8]>,
     ) -> Result<User, SignInError> {
-        let mut password_info = password_token.password;
-        let current_algo = password_info.current_algo.unwrap();
-        let mut params = utils::extract_password_parameters(&current_algo);
-
-        // Telegram sent us incorrect parameters, trying to get them again
-        if !check_p_and_g(params.2, params.3) {
-            password_info = self
-                .get_password_information()
+        let check_password = tl::functions::auth::CheckPassword {
+            password: self
+                .input_check_password(password_token.password, password)
                 .await
-                .map_err(SignInError::Other)?
-                .password;
-            params =
-                utils::extract_password_parameters(password_info.current_algo.as_ref().unwrap());
-            if !check_p_and_g(params.2, params.3) {
-                panic!("Failed to get correct password information from Telegram")
+                .map_err(SignInError::Other)?,
+        };
+
+        match self.invoke(&check_password).await {
+            Ok(tl::enums::auth::Authorization::Authorization(x)) => {
+                self.complete_login(x).await.map_err(SignInError::Other)
             }
+            Ok(tl::enums::auth::Authorization::SignUpRequired(_x)) => panic!("Unexpected result"),
+            Err(err) if err.is("PASSWORD_HASH_INVALID") => Err(SignInError::InvalidPassword),
+            Err(error) => Err(SignInError::Other(error)),
         }
+    }
 
-        let (salt1, salt2, p, g) = params;
-
-        let g_b = password_info.srp_b.unwrap();
-        let a: Vec<u8> = password_info.secure_random;
+    /// Request a code to recover the cloud password, sent to its recovery email, for when the
+    /// password is required to sign in but it has been forgotten.
+    ///
+    /// Returns the pattern of the email where the code was sent to, so that the user knows where
+    /// to look for it.
+    pub async fn request_password_recovery(&self) -> Result<String, InvocationError> {
+        let tl::enums::auth::PasswordRecovery::Recovery(recovery) = self
+            .invoke(&tl::functions::auth::RequestPasswordRecovery {})
+            .await?;
+        Ok(recovery.email_pattern)
+    }
 
-        let (m1, g_a) = calculate_2fa(salt1, salt2, p, g, g_b, a, password);
+    /// Sign in using the code sent by [`Client::request_password_recovery`] instead of the
+    /// forgotten password.
+    ///
+    /// The password is removed, unless a `new_password` (with an optional `hint`) is given to
+    /// replace it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// fn ask_code(email_pattern: &str) -> String {
+    ///     unimplemented!()
+    /// }
+    ///
+    /// let email_pattern = client.request_password_recovery().await?;
+    /// let code = ask_code(&email_pattern);
+    /// let user = client
+    ///     .recover_password(&code, Some("correct horse".as_bytes()), None)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn recover_password(
+        &self,
+        code: &str,
+        new_password: Option<&[u8]>,
+        hint: Option<&str>,
+    ) -> Result<User, SignInError> {
+        let new_settings = match new_password {
+            Some(new_password) => {
+                let password_info = self
+                    .get_password_information()
+                    .await
+                    .map_err(SignInError::Other)?
+                    .password;
+                let new_settings = new_password_settings(&password_info, new_password, hint)
+                    .map_err(|_| SignInError::InvalidPasswordParameters)?;
+                Some(new_settings.into())
+            }
+            None => None,
+        };
 
-        let check_password = tl::functions::auth::CheckPassword {
-            password: tl::enums::InputCheckPasswordSrp::Srp(tl::types::InputCheckPasswordSrp {
-                srp_id: password_info.srp_id.unwrap(),
-                a: g_a.to_vec(),
-                m1: m1.to_vec(),
-            }),
+        let request = tl::functions::auth::RecoverPassword {
+            code: code.to_string(),
+            new_settings,
         };
 
-        match self.invoke(&check_password).await {
+        match self.invoke(&request).await {
             Ok(tl::enums::auth::Authorization::Authorization(x)) => {
                 self.complete_login(x).await.map_err(SignInError::Other)
             }
             Ok(tl::enums::auth::Authorization::SignUpRequired(_x)) => panic!("Unexpected result"),
-            Err(err) if err.is("PASSWORD_HASH_INVALID") => Err(SignInError::InvalidPassword),
+            Err(err) if err.is("CODE_INVALID") => Err(SignInError::InvalidCode),
             Err(error) => Err(SignInError::Other(error)),
         }
     }
@@ -450,14 +963,16 @@ impl Client {
         self.invoke(&tl::functions::auth::LogOut {}).await
     }
 
-    /// Synchronize all state to the session file and provide mutable access to it.
+    /// Synchronize all state to the session and provide access to it.
     ///
-    /// You can use this to temporarily access the session and save it wherever you want to.
+    /// Storages which are not persisted automatically, such as the [`FileSession`], should be
+    /// saved through the handle given in the [`Config`] after calling this method.
     ///
-    /// Panics if the type parameter does not match the actual session type.
-    pub fn session(&self) -> &grammers_session::Session {
+    /// [`FileSession`]: grammers_session::FileSession
+    /// [`Config`]: crate::Config
+    pub fn session(&self) -> &dyn grammers_session::Session {
         self.sync_update_state();
-        &self.0.config.session
+        self.0.config.session.as_ref()
     }
 
     /// Calls [`Client::sign_out`] and disconnects.
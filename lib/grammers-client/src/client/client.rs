@@ -5,17 +5,19 @@
 // <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
-use grammers_mtproto::{mtp, transport};
-use grammers_mtsender::{self as sender, ReconnectionPolicy, Sender};
+use grammers_mtproto::mtp::{self, Recorder, Recording};
+use grammers_mtproto::transport;
+use grammers_mtsender::{self as sender, Connector, MtProxy, ReconnectionPolicy, Sender};
 use grammers_session::{ChatHashCache, MessageBox, Session};
 use grammers_tl_types as tl;
 use sender::Enqueuer;
 use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::net::SocketAddr;
+use std::num::NonZeroUsize;
 use std::sync::atomic::AtomicU32;
 use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::sync::{Mutex as AsyncMutex, RwLock as AsyncRwLock};
 
 /// When no locale is found, use this one instead.
@@ -27,7 +29,13 @@ const DEFAULT_LOCALE: &str = "en";
 pub struct Config {
     /// Session storage where data should persist, such as authorization key, server address,
     /// and other required information by the client.
-    pub session: Session,
+    ///
+    /// Any type implementing the [`Session`] trait may be used. The library provides a
+    /// [`FileSession`] and a [`MemorySession`].
+    ///
+    /// [`FileSession`]: grammers_session::FileSession
+    /// [`MemorySession`]: grammers_session::MemorySession
+    pub session: Arc<dyn Session>,
 
     /// Developer's API ID, required to interact with the Telegram's API.
     ///
@@ -57,10 +65,26 @@ pub struct InitParams {
     /// By default, updates sent while the client was offline are ignored.
     pub catch_up: bool,
     /// Server address to connect to. By default, the library will connect to the address stored
-    /// in the session file (or a default production address if no such address exists). This
+    /// in the session (or a default production address if no such address exists). This
     /// field can be used to override said address, and is most commonly used to connect to one
     /// of Telegram's test servers instead.
     pub server_addr: Option<SocketAddr>,
+    /// Should the client prefer IPv6 addresses when connecting to a datacenter?
+    ///
+    /// The addresses are only known after the first connection is made, so the initial
+    /// connection to a datacenter will always use IPv4. By default, IPv4 is preferred.
+    pub use_ipv6: bool,
+    /// If present, enables [perfect forward secrecy] with temporary authorization keys that are
+    /// valid for the given duration.
+    ///
+    /// The permanent authorization key stored in the session is then only used to bind these
+    /// temporary keys, so that a leaked key cannot be used to decrypt past traffic. Temporary
+    /// keys are generated again some time before they expire, which takes a few extra requests.
+    ///
+    /// By default, the permanent authorization key is used to encrypt all messages.
+    ///
+    /// [perfect forward secrecy]: https://core.telegram.org/api/pfs
+    pub temp_key_lifetime: Option<Duration>,
     /// The threshold below which the library should automatically sleep on flood-wait and slow
     /// mode wait errors (inclusive). For instance, if an
     /// `RpcError { name: "FLOOD_WAIT", value: Some(17) }` (flood, must wait 17 seconds) occurs
@@ -94,19 +118,94 @@ pub struct InitParams {
     ///
     /// When the limit is `Some`, a buffer to hold that many updates will be pre-allocated.
     pub update_queue_limit: Option<usize>,
+    /// How many requests may be waiting for a response from each connection at any given time.
+    ///
+    /// Invoking more requests when this limit is reached will wait until some of the previous
+    /// ones are answered, which prevents a burst of requests from using an unbounded amount of
+    /// memory. The current amount can be queried with [`Client::request_queue_depth`].
+    ///
+    /// Requests made internally by the library, such as pings, are not counted.
+    ///
+    /// By default, there is no limit.
+    pub request_queue_limit: Option<NonZeroUsize>,
+    /// How long to wait for the response to a request before giving up on it.
+    ///
+    /// When a request takes longer than this, [`Client::invoke`] fails with
+    /// [`InvocationError::Timeout`]. If the request was not sent yet, it won't be sent at all,
+    /// and if its response arrives later, it will be ignored. The time spent sleeping on flood
+    /// waits counts towards this timeout. A different timeout can be used for a single request
+    /// with [`Client::invoke_with_timeout`].
+    ///
+    /// By default, requests wait for their response forever.
+    ///
+    /// [`InvocationError::Timeout`]: crate::InvocationError::Timeout
+    pub request_timeout: Option<Duration>,
     /// URL of the proxy to use. Requires the `proxy` feature to be enabled.
     ///
-    /// The scheme must be `socks5`. Username and password are optional.
+    /// The scheme must be either `socks5` or `http`. Username and password are optional. HTTP
+    /// proxies are used through the `CONNECT` method, with basic authentication if a username
+    /// is provided.
     ///
-    /// Both a host and port must be provided. If a domain is used for the host, domain, its address will be looked up,
+    /// Both a host and port must be provided, except for HTTP proxies, where the port defaults
+    /// to 80. If a domain is used for the host, domain, its address will be looked up,
     /// and the first IP address found will be used. If a different IP address should be used, consider resolving the
     /// host manually and selecting an IP address of your choice.
     #[cfg(feature = "proxy")]
     pub proxy_url: Option<String>,
+    /// Should the connections to Telegram be [obfuscated], so that they are harder to detect and
+    /// block through deep packet inspection?
+    ///
+    /// Connections made through a `mtproxy` are always obfuscated. By default, connections made
+    /// directly to Telegram are not.
+    ///
+    /// [obfuscated]: crate::transport::Obfuscated
+    pub obfuscated_transport: bool,
+    /// [MTProxy] server to connect through, instead of connecting to Telegram directly.
+    ///
+    /// All connections are [obfuscated] when using a MTProxy. Secrets prefixed with `dd` use the
+    /// padded intermediate transport, and those prefixed with `ee` also disguise the connection
+    /// as TLS traffic. If present, this takes precedence over the `proxy_url`.
+    ///
+    /// [MTProxy]: grammers_mtsender::MtProxy
+    /// [obfuscated]: crate::transport::Obfuscated
+    pub mtproxy: Option<MtProxy>,
+    /// Custom [`Connector`] used to open the streams to every datacenter, instead of connecting
+    /// to them over TCP. This can be used to run the connections over Unix sockets, in-memory
+    /// pipes, or any other tunnel.
+    ///
+    /// If present, this takes precedence over both the `proxy_url` and the `mtproxy`.
+    ///
+    /// [`Connector`]: grammers_mtsender::Connector
+    pub connector: Option<Arc<dyn Connector>>,
+    /// [`Recorder`] notified of every request sent and every response received through the
+    /// connection to the home datacenter, such as a [`Recording`] to be saved to a file and
+    /// replayed later in tests.
+    ///
+    /// Connections to other datacenters, used to transfer files, are not recorded.
+    ///
+    /// Requests carrying credentials, such as the login code or the password, are recorded
+    /// without their parameters. Results and updates are recorded as-is, and may still contain
+    /// private data such as the message with the login code, so recordings must be kept as
+    /// secret as the session.
+    ///
+    /// [`Recorder`]: crate::Recorder
+    /// [`Recording`]: crate::Recording
+    pub recorder: Option<Arc<dyn Recorder>>,
+    /// [`Recording`] to answer requests with instead of connecting to Telegram, so that code
+    /// using the client can be tested offline and deterministically. Updates are received as
+    /// they were recorded.
+    ///
+    /// Each recorded result answers the first request of the same type, even if their
+    /// parameters differ, and is only used once. Requests with no result left in the recording
+    /// fail with [`InvocationError::Dropped`].
+    ///
+    /// [`Recording`]: crate::Recording
+    /// [`InvocationError::Dropped`]: crate::InvocationError::Dropped
+    pub replay: Option<Arc<Recording>>,
 
     /// specify the reconnection policy which will be used by client to determine whether to re-connect on failure or not.
     ///
-    ///it can be one of the 2 default implementation [`NoReconnect`] and [`FixedReconnect`];
+    ///it can be one of the 3 default implementation [`NoReconnect`], [`FixedReconnect`] and [`ExponentialReconnect`];
     ///
     /// **OR** your own custom implementation of trait [`ReconnectionPolicy`].
     ///
@@ -114,6 +213,7 @@ pub struct InitParams {
     ///
     /// [`NoReconnect`]: grammers_mtsender::NoReconnect
     /// [`FixedReconnect`]: grammers_mtsender::FixedReconnect
+    /// [`ExponentialReconnect`]: grammers_mtsender::ExponentialReconnect
     /// [`ReconnectionPolicy`]: grammers_mtsender::ReconnectionPolicy
     pub reconnection_policy: &'static dyn ReconnectionPolicy,
 }
@@ -135,13 +235,20 @@ pub(crate) struct ClientState {
     // When did we last warn the user that the update queue filled up?
     // This is used to avoid spamming the log.
     pub(crate) last_update_limit_warn: Option<Instant>,
-    pub(crate) updates: VecDeque<(tl::enums::Update, Arc<crate::types::ChatMap>)>,
+    // Along with each update, whether it was caused by a request made by this client.
+    pub(crate) updates: VecDeque<(tl::enums::Update, Arc<crate::types::ChatMap>, bool)>,
 }
 
+/// The transport used by connections, which depends on whether a MTProxy is used.
+pub(crate) type DynTransport = Box<dyn transport::Transport + Send + Sync>;
+
 pub(crate) struct Connection {
-    pub(crate) sender: AsyncMutex<Sender<transport::Full, mtp::Encrypted>>,
+    pub(crate) sender: AsyncMutex<Sender<DynTransport, mtp::Encrypted>>,
     pub(crate) request_tx: RwLock<Enqueuer>,
     pub(crate) step_counter: AtomicU32,
+    // Incremented every time the authorization key is replaced after being lost.
+    // This is used to avoid generating more than one key when concurrent requests fail.
+    pub(crate) auth_key_generation: AtomicU32,
 }
 
 /// A client capable of connecting to Telegram and invoking requests.
@@ -151,10 +258,11 @@ pub(crate) struct Connection {
 /// This structure owns all the necessary connections to Telegram, and has implementations for the
 /// most basic methods, such as connecting, signing in, or processing network events.
 ///
-/// On drop, all state is synchronized to the session. The [`Session`] must be explicitly saved
-/// to disk with [`Session::save_to_file`] for persistence
+/// On drop, all state is synchronized to the session. Some storages, such as the [`FileSession`],
+/// must be explicitly saved to disk with [`FileSession::save_to_file`] for persistence.
 ///
-/// [`Session`]: grammers_session::Session
+/// [`FileSession`]: grammers_session::FileSession
+/// [`FileSession::save_to_file`]: grammers_session::FileSession::save_to_file
 #[derive(Clone)]
 pub struct Client(pub(crate) Arc<ClientInner>);
 
@@ -185,10 +293,19 @@ impl Default for InitParams {
             lang_code,
             catch_up: false,
             server_addr: None,
+            use_ipv6: false,
+            temp_key_lifetime: None,
             flood_sleep_threshold: 60,
             update_queue_limit: Some(100),
+            request_queue_limit: None,
+            request_timeout: None,
             #[cfg(feature = "proxy")]
             proxy_url: None,
+            obfuscated_transport: false,
+            mtproxy: None,
+            connector: None,
+            recorder: None,
+            replay: None,
             reconnection_policy: &grammers_mtsender::NoReconnect,
         }
     }
@@ -5,6 +5,7 @@
 // <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
+pub mod account;
 pub mod auth;
 pub mod bots;
 pub mod chats;
@@ -16,6 +17,7 @@ pub mod messages;
 pub mod net;
 pub mod updates;
 
-pub use auth::SignInError;
+pub use account::PasswordError;
+pub use auth::{LoginOutcome, QrLogin, SignInError};
 pub(crate) use client::ClientInner;
 pub use client::{Client, Config, InitParams};
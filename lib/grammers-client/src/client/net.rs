@@ -5,29 +5,49 @@
 // <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
-use super::client::{ClientState, Connection};
+use super::client::{ClientState, Connection, DynTransport};
 use super::{Client, ClientInner, Config};
 use crate::utils;
 use grammers_mtproto::mtp;
 use grammers_mtproto::transport;
-use grammers_mtsender::{self as sender, AuthorizationError, InvocationError, RpcError, Sender};
-use grammers_session::{ChatHashCache, MessageBox};
+use grammers_mtsender::{
+    self as sender, AuthorizationError, InvocationError, ReadError, RpcError, Sender, UpdatesLike,
+};
+use grammers_session::{ChatHashCache, DcOption, MessageBox};
 use grammers_tl_types::{self as tl, Deserializable};
-use log::{debug, info};
+use log::{debug, info, warn};
 use sender::Enqueuer;
 use std::collections::{HashMap, VecDeque};
-use std::net::{Ipv4Addr, SocketAddr};
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, RwLock};
-use tokio::sync::oneshot::error::TryRecvError;
+use std::time::Duration;
+use tokio::sync::oneshot::{self, error::TryRecvError};
 use tokio::sync::{Mutex as AsyncMutex, RwLock as AsyncRwLock};
 
+/// Wait for the invocation to complete, or fail with [`InvocationError::Timeout`] if it takes
+/// longer than `timeout`. Timing out drops the invocation, which cancels the request.
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    invocation: impl Future<Output = Result<T, InvocationError>>,
+) -> Result<T, InvocationError> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, invocation)
+            .await
+            .unwrap_or(Err(InvocationError::Timeout)),
+        None => invocation.await,
+    }
+}
+
 /// Socket addresses to Telegram datacenters, where the index into this array
 /// represents the data center ID.
 ///
 /// The addresses were obtained from the `static` addresses through a call to
-/// `functions::help::GetConfig`.
-const DC_ADDRESSES: [(Ipv4Addr, u16); 6] = [
+/// `functions::help::GetConfig`. They are only used until the session knows
+/// the up-to-date addresses.
+const STATIC_DC_ADDRESSES: [(Ipv4Addr, u16); 6] = [
     (Ipv4Addr::new(0, 0, 0, 0), 0),
     (Ipv4Addr::new(149, 154, 175, 53), 443),
     (Ipv4Addr::new(149, 154, 167, 51), 443),
@@ -38,84 +58,233 @@ const DC_ADDRESSES: [(Ipv4Addr, u16); 6] = [
 
 const DEFAULT_DC: i32 = 2;
 
+/// Determine the address to use when connecting to the given datacenter.
+///
+/// Fails if the session knows no address for it, and it's not one of the static datacenters.
+fn dc_addr(dc_id: i32, media: bool, config: &Config) -> Result<SocketAddr, io::Error> {
+    if let Some(addr) = config.params.server_addr {
+        return Ok(addr);
+    }
+
+    let options = config.session.get_dc_options();
+    if let Some(option) = DcOption::select(&options, dc_id, config.params.use_ipv6, media) {
+        return Ok(option.addr);
+    }
+
+    usize::try_from(dc_id)
+        .ok()
+        .filter(|&index| index > 0)
+        .and_then(|index| STATIC_DC_ADDRESSES.get(index))
+        .map(|&addr| addr.into())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no known address for dc {dc_id}"),
+            )
+        })
+}
+
+fn dc_option(option: tl::enums::DcOption, test: bool) -> Option<DcOption> {
+    let tl::enums::DcOption::Option(option) = option;
+    let ip = option.ip_address.parse::<IpAddr>().ok()?;
+    Some(DcOption {
+        id: option.id,
+        addr: SocketAddr::new(ip, option.port as u16),
+        media_only: option.media_only,
+        cdn: option.cdn,
+        test,
+        r#static: option.r#static,
+        this_port_only: option.this_port_only,
+        tcpo_only: option.tcpo_only,
+        secret: option.secret,
+    })
+}
+
+/// Whether the error means that the server no longer knows the authorization key used.
+fn is_auth_key_lost(error: &AuthorizationError) -> bool {
+    match error {
+        AuthorizationError::Invoke(InvocationError::Read(ReadError::AuthKeyLost)) => true,
+        // Binding a temporary key fails this way when the permanent key is unknown.
+        AuthorizationError::Invoke(InvocationError::Rpc(e)) => e.is("ENCRYPTED_MESSAGE_INVALID"),
+        _ => false,
+    }
+}
+
 pub(crate) async fn connect_sender(
     dc_id: i32,
+    media: bool,
     config: &Config,
-) -> Result<(Sender<transport::Full, mtp::Encrypted>, Enqueuer), AuthorizationError> {
-    let transport = transport::Full::new();
+) -> Result<(Sender<DynTransport, mtp::Encrypted>, Enqueuer), AuthorizationError> {
+    let had_auth_key = config.session.dc_auth_key(dc_id).is_some();
+    match try_connect_sender(dc_id, media, config).await {
+        // The server may forget the key while the client is offline, in which case the only
+        // way forward is to generate a new one. The account must be signed in to again.
+        Err(err) if had_auth_key && is_auth_key_lost(&err) => {
+            warn!("authorization key for dc {dc_id} was lost; generating a new one");
+            config.session.remove_dc(dc_id);
+            if !media {
+                config.session.clear_user();
+            }
+            try_connect_sender(dc_id, media, config).await
+        }
+        result => result,
+    }
+}
 
-    let addr: SocketAddr = if let Some(ip) = config.params.server_addr {
-        ip
-    } else {
-        DC_ADDRESSES[dc_id as usize].into()
+async fn try_connect_sender(
+    dc_id: i32,
+    media: bool,
+    config: &Config,
+) -> Result<(Sender<DynTransport, mtp::Encrypted>, Enqueuer), AuthorizationError> {
+    let addr = dc_addr(dc_id, media, config)?;
+    let connector = config.params.connector.clone();
+    let mtproxy = config
+        .params
+        .mtproxy
+        .as_ref()
+        .filter(|_| connector.is_none());
+    // Obfuscated connections identify media datacenters by their negative identifier.
+    let obfuscated_dc_id = if media { -dc_id } else { dc_id } as i16;
+    let transport: DynTransport = match mtproxy {
+        Some(mtproxy) => Box::new(mtproxy.transport(obfuscated_dc_id)),
+        None if config.params.obfuscated_transport => Box::new(transport::Obfuscated::new(
+            transport::Intermediate::new(),
+            obfuscated_dc_id,
+        )),
+        None => Box::new(transport::Full::new()),
     };
 
-    let (mut sender, request_tx) = if let Some(auth_key) = config.session.dc_auth_key(dc_id) {
+    let (mut sender, request_tx) = if let Some(recording) = &config.params.replay {
+        info!(
+            "replaying a recording instead of connecting to dc {}",
+            dc_id
+        );
+        sender::replay(transport, recording, config.params.reconnection_policy)
+    } else if let Some(auth_key) = config.session.dc_auth_key(dc_id) {
         info!(
             "creating a new sender with existing auth key to dc {} {:?}",
             dc_id, addr
         );
 
-        #[cfg(feature = "proxy")]
-        if let Some(url) = config.params.proxy_url.as_ref() {
-            sender::connect_via_proxy_with_auth(
+        if let Some(connector) = connector {
+            sender::connect_via_connector_with_auth(
                 transport,
                 addr,
                 auth_key,
-                url,
+                connector,
+                config.params.reconnection_policy,
+            )
+            .await?
+        } else if let Some(mtproxy) = mtproxy {
+            sender::connect_via_mtproxy_with_auth(
+                transport,
+                auth_key,
+                mtproxy,
                 config.params.reconnection_policy,
             )
             .await?
         } else {
+            #[cfg(feature = "proxy")]
+            if let Some(url) = config.params.proxy_url.as_ref() {
+                sender::connect_via_proxy_with_auth(
+                    transport,
+                    addr,
+                    auth_key,
+                    url,
+                    config.params.reconnection_policy,
+                )
+                .await?
+            } else {
+                sender::connect_with_auth(
+                    transport,
+                    addr,
+                    auth_key,
+                    config.params.reconnection_policy,
+                )
+                .await?
+            }
+
+            #[cfg(not(feature = "proxy"))]
             sender::connect_with_auth(transport, addr, auth_key, config.params.reconnection_policy)
                 .await?
         }
-
-        #[cfg(not(feature = "proxy"))]
-        sender::connect_with_auth(transport, addr, auth_key, config.params.reconnection_policy)
-            .await?
     } else {
         info!(
             "creating a new sender and auth key in dc {} {:?}",
             dc_id, addr
         );
 
-        #[cfg(feature = "proxy")]
-        let (sender, tx) = if let Some(url) = config.params.proxy_url.as_ref() {
-            sender::connect_via_proxy(transport, addr, url, config.params.reconnection_policy)
+        let (sender, tx) = if let Some(connector) = connector {
+            sender::connect_via_connector(
+                transport,
+                addr,
+                connector,
+                config.params.reconnection_policy,
+            )
+            .await?
+        } else if let Some(mtproxy) = mtproxy {
+            sender::connect_via_mtproxy(transport, mtproxy, config.params.reconnection_policy)
                 .await?
         } else {
+            #[cfg(feature = "proxy")]
+            if let Some(url) = config.params.proxy_url.as_ref() {
+                sender::connect_via_proxy(transport, addr, url, config.params.reconnection_policy)
+                    .await?
+            } else {
+                sender::connect(transport, addr, config.params.reconnection_policy).await?
+            }
+
+            #[cfg(not(feature = "proxy"))]
             sender::connect(transport, addr, config.params.reconnection_policy).await?
         };
 
-        #[cfg(not(feature = "proxy"))]
-        let (sender, tx) =
-            sender::connect(transport, addr, config.params.reconnection_policy).await?;
-
         config.session.insert_dc(dc_id, addr, sender.auth_key());
         (sender, tx)
     };
 
-    // TODO handle -404 (we had a previously-valid authkey, but server no longer knows about it)
-    // TODO all up-to-date server addresses should be stored in the session for future initial connections
-    let _remote_config = sender
-        .invoke(&tl::functions::InvokeWithLayer {
-            layer: tl::LAYER,
-            query: tl::functions::InitConnection {
-                api_id: config.api_id,
-                device_model: config.params.device_model.clone(),
-                system_version: config.params.system_version.clone(),
-                app_version: config.params.app_version.clone(),
-                system_lang_code: config.params.system_lang_code.clone(),
-                lang_pack: "".into(),
-                lang_code: config.params.lang_code.clone(),
-                proxy: None,
-                params: None,
-                query: tl::functions::help::GetConfig {},
-            },
-        })
-        .await?;
+    if !media {
+        sender.set_recorder(config.params.recorder.clone());
+    }
+
+    let init_request = tl::functions::InvokeWithLayer {
+        layer: tl::LAYER,
+        query: tl::functions::InitConnection {
+            api_id: config.api_id,
+            device_model: config.params.device_model.clone(),
+            system_version: config.params.system_version.clone(),
+            app_version: config.params.app_version.clone(),
+            system_lang_code: config.params.system_lang_code.clone(),
+            lang_pack: "".into(),
+            lang_code: config.params.lang_code.clone(),
+            proxy: None,
+            params: None,
+            query: tl::functions::help::GetConfig {},
+        },
+    };
 
+    if let Some(lifetime) = config.params.temp_key_lifetime {
+        info!("enabling perfect forward secrecy in dc {}", dc_id);
+        sender.enable_pfs(dc_id, lifetime, &init_request).await?;
+    }
+
+    let remote_config = sender.invoke(&init_request).await?;
+    let tl::enums::Config::Config(remote_config) =
+        tl::enums::Config::from_bytes(&remote_config).map_err(InvocationError::from)?;
+
+    // Store all up-to-date server addresses for future connections (including initial ones).
+    let options = remote_config
+        .dc_options
+        .into_iter()
+        .filter_map(|option| dc_option(option, remote_config.test_mode))
+        .collect::<Vec<_>>();
+    if !options.is_empty() {
+        config.session.set_dc_options(&options);
+    }
+
+    let request_tx = match config.params.request_queue_limit {
+        Some(limit) => request_tx.bounded(limit),
+        None => request_tx,
+    };
     Ok((sender, request_tx))
 }
 
@@ -132,7 +301,8 @@ impl Client {
     ///
     /// ```
     /// use grammers_client::{Client, Config};
-    /// use grammers_session::Session;
+    /// use grammers_session::FileSession;
+    /// use std::sync::Arc;
     ///
     /// // Note: these are example values and are not actually valid.
     /// //       Obtain your own with the developer's phone at https://my.telegram.org.
@@ -141,7 +311,7 @@ impl Client {
     ///
     /// # async fn f() -> Result<(), Box<dyn std::error::Error>> {
     /// let client = Client::connect(Config {
-    ///     session: Session::load_file_or_create("hello-world.session")?,
+    ///     session: Arc::new(FileSession::load_file_or_create("hello-world.session")?),
     ///     api_id: API_ID,
     ///     api_hash: API_HASH.to_string(),
     ///     params: Default::default(),
@@ -150,12 +320,8 @@ impl Client {
     /// # }
     /// ```
     pub async fn connect(mut config: Config) -> Result<Self, AuthorizationError> {
-        let dc_id = config
-            .session
-            .get_user()
-            .map(|u| u.dc)
-            .unwrap_or(DEFAULT_DC);
-        let (sender, request_tx) = connect_sender(dc_id, &config).await?;
+        let dc_id = config.session.home_dc_id().unwrap_or(DEFAULT_DC);
+        let (sender, request_tx) = connect_sender(dc_id, false, &config).await?;
         let message_box = if config.params.catch_up {
             if let Some(state) = config.session.get_state() {
                 MessageBox::load(state)
@@ -181,11 +347,12 @@ impl Client {
         }
 
         let self_user = config.session.get_user();
+        let mut chat_hashes = ChatHashCache::new(self_user.map(|u| (u.id, u.bot)));
+        chat_hashes.load(config.session.get_peers());
 
         // Don't bother getting pristine update state if we're not logged in.
         let should_get_state = message_box.is_empty() && config.session.signed_in();
 
-        // TODO Sender doesn't have a way to handle backpressure yet
         let client = Self(Arc::new(ClientInner {
             id: utils::generate_random_id(),
             config,
@@ -193,7 +360,7 @@ impl Client {
             state: RwLock::new(ClientState {
                 dc_id,
                 message_box,
-                chat_hashes: ChatHashCache::new(self_user.map(|u| (u.id, u.bot))),
+                chat_hashes,
                 last_update_limit_warn: None,
                 updates,
             }),
@@ -241,18 +408,131 @@ impl Client {
     /// # Ok(())
     /// # }
     /// ```
+    ///
+    /// If the server no longer knows about the authorization key, a new key is generated and
+    /// stored in the session, and the request fails with [`ReadError::AuthKeyLost`]. The user
+    /// will need to sign in again. Requests that were still waiting on the previous key fail
+    /// with [`InvocationError::Dropped`].
+    ///
+    /// If [`InitParams::request_timeout`] is set and the response does not arrive in time, the
+    /// request fails with [`InvocationError::Timeout`].
+    ///
+    /// Dropping the returned future before it completes cancels the request. If it was not sent
+    /// yet, it won't be, and if it was, its response will be ignored when it arrives.
+    ///
+    /// [`ReadError::AuthKeyLost`]: sender::ReadError::AuthKeyLost
+    /// [`InitParams::request_timeout`]: crate::InitParams::request_timeout
     pub async fn invoke<R: tl::RemoteCall>(
         &self,
         request: &R,
     ) -> Result<R::Return, InvocationError> {
-        self.0
+        with_timeout(
+            self.0.config.params.request_timeout,
+            self.do_invoke(request, true),
+        )
+        .await
+    }
+
+    /// Like [`Client::invoke`], but the request is not sent again if the connection is lost
+    /// after it was sent, even if the [`InitParams::reconnection_policy`] reconnects.
+    ///
+    /// The server may have executed the request even if its response never arrived, so sending
+    /// it again could execute it twice. Use this method for requests where that is not
+    /// acceptable. When this happens, the request fails with [`InvocationError::Dropped`], and
+    /// it's up to the caller to check whether it took effect.
+    ///
+    /// [`InitParams::reconnection_policy`]: crate::InitParams::reconnection_policy
+    pub async fn invoke_at_most_once<R: tl::RemoteCall>(
+        &self,
+        request: &R,
+    ) -> Result<R::Return, InvocationError> {
+        with_timeout(
+            self.0.config.params.request_timeout,
+            self.do_invoke(request, false),
+        )
+        .await
+    }
+
+    /// Like [`Client::invoke`], but giving up on the request with [`InvocationError::Timeout`]
+    /// if its response does not arrive within the given `timeout`, instead of using the default
+    /// one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn f(client: grammers_client::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// use grammers_tl_types as tl;
+    /// use std::time::Duration;
+    ///
+    /// let request = tl::functions::Ping { ping_id: 0 };
+    /// dbg!(client.invoke_with_timeout(&request, Duration::from_secs(5)).await?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn invoke_with_timeout<R: tl::RemoteCall>(
+        &self,
+        request: &R,
+        timeout: Duration,
+    ) -> Result<R::Return, InvocationError> {
+        with_timeout(Some(timeout), self.do_invoke(request, true)).await
+    }
+
+    async fn do_invoke<R: tl::RemoteCall>(
+        &self,
+        request: &R,
+        resend: bool,
+    ) -> Result<R::Return, InvocationError> {
+        let generation = self.0.conn.auth_key_generation.load(Ordering::SeqCst);
+        match self
+            .0
             .conn
             .invoke(
                 request,
                 self.0.config.params.flood_sleep_threshold,
+                resend,
                 |updates| self.process_socket_updates(updates),
             )
             .await
+        {
+            Err(InvocationError::Read(ReadError::AuthKeyLost)) => {
+                self.recover_auth_key(generation).await;
+                Err(InvocationError::Read(ReadError::AuthKeyLost))
+            }
+            result => result,
+        }
+    }
+
+    /// Replace the authorization key of the main connection with a new one, and forget the user
+    /// that was logged in with the previous key.
+    ///
+    /// Nothing is done if the key was already replaced after `generation`.
+    async fn recover_auth_key(&self, generation: u32) {
+        let mut sender = self.0.conn.sender.lock().await;
+        if self.0.conn.auth_key_generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+
+        let dc_id = self.0.state.read().unwrap().dc_id;
+        warn!("authorization key for dc {dc_id} was lost; generating a new one");
+        self.0.config.session.remove_dc(dc_id);
+        self.0.config.session.clear_user();
+
+        match connect_sender(dc_id, false, &self.0.config).await {
+            Ok((new_sender, request_tx)) => {
+                // Whoever is waiting on the old connection must not be left hanging.
+                sender.drop_requests();
+                *sender = new_sender;
+                *self.0.conn.request_tx.write().unwrap() = request_tx;
+                self.0
+                    .conn
+                    .auth_key_generation
+                    .fetch_add(1, Ordering::SeqCst);
+            }
+            Err(err) => {
+                // The next request will fail with the lost key again and retry.
+                warn!("failed to generate a new authorization key: {err}");
+            }
+        }
     }
 
     async fn export_authorization(
@@ -273,7 +553,7 @@ impl Client {
     async fn connect_sender(&self, dc_id: i32) -> Result<Arc<Connection>, InvocationError> {
         let mut mutex = self.0.downloader_map.write().await;
         debug!("Connecting new datacenter {}", dc_id);
-        match connect_sender(dc_id, &self.0.config).await {
+        match connect_sender(dc_id, true, &self.0.config).await {
             Ok((new_sender, new_tx)) => {
                 let new_downloader = Arc::new(Connection::new(new_sender, new_tx));
 
@@ -286,7 +566,12 @@ impl Client {
                     bytes: authorization.bytes,
                 };
                 new_downloader
-                    .invoke(&request, self.0.config.params.flood_sleep_threshold, drop)
+                    .invoke(
+                        &request,
+                        self.0.config.params.flood_sleep_threshold,
+                        true,
+                        drop,
+                    )
                     .await?;
 
                 mutex.insert(dc_id, new_downloader.clone());
@@ -315,9 +600,35 @@ impl Client {
             None => self.connect_sender(dc_id).await?,
             Some(fd) => fd,
         };
-        downloader
-            .invoke(request, self.0.config.params.flood_sleep_threshold, drop)
-            .await
+        let result = with_timeout(
+            self.0.config.params.request_timeout,
+            downloader.invoke(
+                request,
+                self.0.config.params.flood_sleep_threshold,
+                true,
+                drop,
+            ),
+        )
+        .await;
+
+        if let Err(InvocationError::Read(ReadError::AuthKeyLost)) = result {
+            // The next request will connect again and generate a new key.
+            warn!("authorization key for dc {dc_id} was lost; dropping its connection");
+            self.0.downloader_map.write().await.remove(&dc_id);
+            self.0.config.session.remove_dc(dc_id);
+        }
+        result
+    }
+
+    /// Returns how many requests sent through the main connection are still waiting for a
+    /// response, including those which have not been sent yet.
+    ///
+    /// If [`InitParams::request_queue_limit`] is set, invoking more requests waits while this is
+    /// at said limit, so it won't exceed it.
+    ///
+    /// [`InitParams::request_queue_limit`]: crate::InitParams::request_queue_limit
+    pub fn request_queue_depth(&self) -> usize {
+        self.0.conn.request_tx.read().unwrap().depth()
     }
 
     /// Perform a single network step.
@@ -337,9 +648,18 @@ impl Client {
     /// # }
     /// ```
     pub async fn step(&self) -> Result<(), sender::ReadError> {
-        let updates = self.0.conn.step().await?;
-        self.process_socket_updates(updates);
-        Ok(())
+        let generation = self.0.conn.auth_key_generation.load(Ordering::SeqCst);
+        match self.0.conn.step().await {
+            Ok(updates) => {
+                self.process_socket_updates(updates);
+                Ok(())
+            }
+            Err(ReadError::AuthKeyLost) => {
+                self.recover_auth_key(generation).await;
+                Err(ReadError::AuthKeyLost)
+            }
+            Err(err) => Err(err),
+        }
     }
 
     /// Run the client by repeatedly calling [`Client::step`] until a graceful disconnection
@@ -363,23 +683,25 @@ impl Client {
 }
 
 impl Connection {
-    fn new(sender: Sender<transport::Full, mtp::Encrypted>, request_tx: Enqueuer) -> Self {
+    fn new(sender: Sender<DynTransport, mtp::Encrypted>, request_tx: Enqueuer) -> Self {
         Self {
             sender: AsyncMutex::new(sender),
             request_tx: RwLock::new(request_tx),
             step_counter: AtomicU32::new(0),
+            auth_key_generation: AtomicU32::new(0),
         }
     }
 
-    pub(crate) async fn invoke<R: tl::RemoteCall, F: Fn(Vec<tl::enums::Updates>)>(
+    pub(crate) async fn invoke<R: tl::RemoteCall, F: Fn(Vec<UpdatesLike>)>(
         &self,
         request: &R,
         flood_sleep_threshold: u32,
+        resend: bool,
         on_updates: F,
     ) -> Result<R::Return, InvocationError> {
         let mut slept_flood = false;
 
-        let mut rx = { self.request_tx.read().unwrap().enqueue(request) };
+        let mut rx = self.enqueue(request, resend, &on_updates).await?;
         loop {
             match rx.try_recv() {
                 Ok(response) => match response {
@@ -399,7 +721,7 @@ impl Connection {
                         );
                         tokio::time::sleep(delay).await;
                         slept_flood = true;
-                        rx = self.request_tx.read().unwrap().enqueue(request);
+                        rx = self.enqueue(request, resend, &on_updates).await?;
                         continue;
                     }
                     Err(e) => break Err(e),
@@ -407,14 +729,28 @@ impl Connection {
                 Err(TryRecvError::Empty) => {
                     on_updates(self.step().await?);
                 }
-                Err(TryRecvError::Closed) => {
-                    panic!("request channel dropped before receiving a result")
-                }
+                Err(TryRecvError::Closed) => break Err(InvocationError::Dropped),
+            }
+        }
+    }
+
+    /// Enqueue the request, driving the network while the queue is full so that it can drain.
+    async fn enqueue<R: tl::RemoteCall, F: Fn(Vec<UpdatesLike>)>(
+        &self,
+        request: &R,
+        resend: bool,
+        on_updates: &F,
+    ) -> Result<oneshot::Receiver<Result<Vec<u8>, InvocationError>>, InvocationError> {
+        loop {
+            let request_tx = self.request_tx.read().unwrap().clone().resend(resend);
+            if let Some(rx) = request_tx.try_enqueue(request) {
+                break Ok(rx);
             }
+            on_updates(self.step().await?);
         }
     }
 
-    async fn step(&self) -> Result<Vec<tl::enums::Updates>, sender::ReadError> {
+    async fn step(&self) -> Result<Vec<UpdatesLike>, sender::ReadError> {
         let ticket_number = self.step_counter.load(Ordering::SeqCst);
         let mut sender = self.sender.lock().await;
         match self.step_counter.compare_exchange(
@@ -11,6 +11,7 @@
 use super::Client;
 use crate::types::{ChatMap, Update};
 use futures_util::future::{select, Either};
+use grammers_mtsender::UpdatesLike;
 pub use grammers_mtsender::{AuthorizationError, InvocationError};
 use grammers_session::channel_id;
 pub use grammers_session::{PrematureEndReason, UpdateState};
@@ -47,9 +48,12 @@ impl Client {
     /// ```
     pub async fn next_update(&self) -> Result<Update, InvocationError> {
         loop {
-            let (update, chats) = self.next_raw_update().await?;
+            let (update, chats, own) = self.next_queued_update().await?;
 
-            if let Some(update) = Update::new(&self, update, &chats) {
+            if let Some(mut update) = Update::new(&self, update, &chats) {
+                if own {
+                    update.mark_own();
+                }
                 return Ok(update);
             }
         }
@@ -77,6 +81,14 @@ impl Client {
     pub async fn next_raw_update(
         &self,
     ) -> Result<(tl::enums::Update, Arc<ChatMap>), InvocationError> {
+        let (update, chats, _) = self.next_queued_update().await?;
+        Ok((update, chats))
+    }
+
+    /// Returns the next update in the queue, along with whether it was caused by this client.
+    async fn next_queued_update(
+        &self,
+    ) -> Result<(tl::enums::Update, Arc<ChatMap>, bool), InvocationError> {
         loop {
             let (deadline, get_diff, get_channel_diff) = {
                 let state = &mut *self.0.state.write().unwrap();
@@ -98,7 +110,7 @@ impl Client {
                         .message_box
                         .apply_difference(response, &mut state.chat_hashes)
                 };
-                self.extend_update_queue(updates, ChatMap::new(users, chats));
+                self.extend_update_queue(updates, ChatMap::new(users, chats), false);
                 continue;
             }
 
@@ -177,7 +189,7 @@ impl Client {
                     )
                 };
 
-                self.extend_update_queue(updates, ChatMap::new(users, chats));
+                self.extend_update_queue(updates, ChatMap::new(users, chats), false);
                 continue;
             }
 
@@ -191,16 +203,26 @@ impl Client {
         }
     }
 
-    pub(crate) fn process_socket_updates(&self, all_updates: Vec<tl::enums::Updates>) {
+    pub(crate) fn process_socket_updates(&self, all_updates: Vec<UpdatesLike>) {
         if all_updates.is_empty() {
             return;
         }
 
-        let mut result = Option::<(Vec<_>, Vec<_>, Vec<_>)>::None;
+        // Consecutive updates are merged as long as they're all own updates or none of them are,
+        // so that the order in which they arrived is kept.
+        let mut results = Vec::<((Vec<_>, Vec<_>, Vec<_>), bool)>::new();
         {
             let state = &mut *self.0.state.write().unwrap();
 
             for updates in all_updates {
+                let (updates, own) = match updates {
+                    UpdatesLike::Updates(updates) => (updates, false),
+                    UpdatesLike::OwnUpdates(updates) => (updates, true),
+                    UpdatesLike::NewSession => {
+                        state.message_box.new_session_created();
+                        continue;
+                    }
+                };
                 if state
                     .message_box
                     .ensure_known_peer_hashes(&updates, &mut state.chat_hashes)
@@ -212,26 +234,30 @@ impl Client {
                     .message_box
                     .process_updates(updates, &state.chat_hashes)
                 {
-                    Ok(tup) => {
-                        if let Some(res) = result.as_mut() {
+                    Ok(tup) => match results.last_mut() {
+                        Some((res, res_own)) if *res_own == own => {
                             res.0.extend(tup.0);
                             res.1.extend(tup.1);
                             res.2.extend(tup.2);
-                        } else {
-                            result = Some(tup);
                         }
-                    }
+                        _ => results.push((tup, own)),
+                    },
                     Err(_) => return,
                 }
             }
         }
 
-        if let Some((updates, users, chats)) = result {
-            self.extend_update_queue(updates, ChatMap::new(users, chats));
+        for ((updates, users, chats), own) in results {
+            self.extend_update_queue(updates, ChatMap::new(users, chats), own);
         }
     }
 
-    fn extend_update_queue(&self, mut updates: Vec<tl::enums::Update>, chat_map: Arc<ChatMap>) {
+    fn extend_update_queue(
+        &self,
+        mut updates: Vec<tl::enums::Update>,
+        chat_map: Arc<ChatMap>,
+        own: bool,
+    ) {
         let mut state = self.0.state.write().unwrap();
 
         if let Some(limit) = self.0.config.params.update_queue_limit {
@@ -257,16 +283,21 @@ impl Client {
 
         state
             .updates
-            .extend(updates.into_iter().map(|u| (u, chat_map.clone())));
+            .extend(updates.into_iter().map(|u| (u, chat_map.clone(), own)));
     }
 
-    /// Synchronize the updates state to the session.
+    /// Synchronize the updates state and newly-known peers to the session.
     pub fn sync_update_state(&self) {
-        let state = self.0.state.read().unwrap();
+        let mut state = self.0.state.write().unwrap();
         self.0
             .config
             .session
             .set_state(state.message_box.session_state());
+
+        let peers = state.chat_hashes.take_unsaved();
+        if !peers.is_empty() {
+            self.0.config.session.insert_peers(&peers);
+        }
     }
 }
 
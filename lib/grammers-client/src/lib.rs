@@ -49,10 +49,14 @@ pub mod parsers;
 pub mod types;
 pub(crate) mod utils;
 
-pub use client::{Client, Config, InitParams, SignInError};
+pub use client::{Client, Config, InitParams, LoginOutcome, PasswordError, QrLogin, SignInError};
 pub use types::{button, reply_markup, ChatMap, InputMedia, InputMessage, Update};
 
+pub use grammers_mtproto::mtp::{Recorder, Recording};
 pub use grammers_mtproto::transport;
-pub use grammers_mtsender::{FixedReconnect, InvocationError, NoReconnect, ReconnectionPolicy};
+pub use grammers_mtsender::{
+    Connector, ExponentialReconnect, FixedReconnect, InvocationError, MtProxy, NoReconnect,
+    ReconnectionPolicy, Stream,
+};
 pub use grammers_session as session;
 pub use grammers_tl_types;
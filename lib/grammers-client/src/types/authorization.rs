@@ -0,0 +1,99 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use crate::utils;
+use chrono::{DateTime, Utc};
+use grammers_tl_types as tl;
+
+/// An active session where the account is logged in, such as the one used by this client.
+#[derive(Clone, Debug)]
+pub struct Authorization {
+    pub raw: tl::types::Authorization,
+}
+
+impl Authorization {
+    pub(crate) fn from_raw(
+        tl::enums::Authorization::Authorization(authorization): tl::enums::Authorization,
+    ) -> Self {
+        Self { raw: authorization }
+    }
+
+    /// The identifier used to terminate this session.
+    pub fn hash(&self) -> i64 {
+        self.raw.hash
+    }
+
+    /// Whether this is the session used by this client.
+    pub fn is_current(&self) -> bool {
+        self.raw.current
+    }
+
+    /// Whether the session belongs to an official application.
+    pub fn is_official_app(&self) -> bool {
+        self.raw.official_app
+    }
+
+    /// Whether the session is still waiting for the cloud password to be entered.
+    pub fn is_password_pending(&self) -> bool {
+        self.raw.password_pending
+    }
+
+    /// The model of the device where the session was created.
+    pub fn device_model(&self) -> &str {
+        &self.raw.device_model
+    }
+
+    /// The platform of the device where the session was created.
+    pub fn platform(&self) -> &str {
+        &self.raw.platform
+    }
+
+    /// The version of the operating system of the device where the session was created.
+    pub fn system_version(&self) -> &str {
+        &self.raw.system_version
+    }
+
+    /// The API ID of the application used to create the session.
+    pub fn api_id(&self) -> i32 {
+        self.raw.api_id
+    }
+
+    /// The name of the application used to create the session.
+    pub fn app_name(&self) -> &str {
+        &self.raw.app_name
+    }
+
+    /// The version of the application used to create the session.
+    pub fn app_version(&self) -> &str {
+        &self.raw.app_version
+    }
+
+    /// When the session was created.
+    pub fn date_created(&self) -> DateTime<Utc> {
+        utils::date(self.raw.date_created)
+    }
+
+    /// When the session was last active.
+    pub fn date_active(&self) -> DateTime<Utc> {
+        utils::date(self.raw.date_active)
+    }
+
+    /// The IP address the session was last active from.
+    pub fn ip(&self) -> &str {
+        &self.raw.ip
+    }
+
+    /// The country the session was last active from, as determined by its IP address.
+    pub fn country(&self) -> &str {
+        &self.raw.country
+    }
+
+    /// The region the session was last active from, as determined by its IP address.
+    pub fn region(&self) -> &str {
+        &self.raw.region
+    }
+}
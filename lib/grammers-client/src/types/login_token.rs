@@ -5,8 +5,150 @@
 // <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
+use grammers_tl_types as tl;
+use std::time::Duration;
 
+/// How the login code was sent to the user.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum SentCodeType {
+    /// The code was sent as a message to another application where the account is logged in.
+    App { length: i32 },
+    /// The code was sent through SMS.
+    Sms { length: i32 },
+    /// The code will be dictated in a phone call.
+    Call { length: i32 },
+    /// The code is the phone number of an incoming call, which will match the pattern.
+    FlashCall { pattern: String },
+    /// The code is the last digits of the phone number of a missed call, which starts with the
+    /// prefix.
+    MissedCall { prefix: String, length: i32 },
+    /// The code was sent to the login email of the account, which matches the pattern.
+    ///
+    /// [`Client::sign_in`] will verify the code against the email instead of the phone.
+    ///
+    /// [`Client::sign_in`]: crate::Client::sign_in
+    Email { email_pattern: String, length: i32 },
+    /// A login email must be set up before a code can be sent, with
+    /// [`Client::send_login_email_code`] and [`Client::verify_login_email`].
+    ///
+    /// [`Client::send_login_email_code`]: crate::Client::send_login_email_code
+    /// [`Client::verify_login_email`]: crate::Client::verify_login_email
+    SetUpEmailRequired,
+    /// The code was sent through Fragment, which can be opened with the URL.
+    FragmentSms { url: String, length: i32 },
+    /// The code was sent through an SMS requested via Firebase.
+    FirebaseSms { length: i32 },
+    /// The code is a word sent through SMS, starting with the given beginning, if any.
+    SmsWord { beginning: Option<String> },
+    /// The code is a phrase sent through SMS, starting with the given beginning, if any.
+    SmsPhrase { beginning: Option<String> },
+}
+
+/// How the login code will be sent the next time it is [resent].
+///
+/// [resent]: crate::Client::resend_code
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum CodeType {
+    Sms,
+    Call,
+    FlashCall,
+    MissedCall,
+    FragmentSms,
+}
+
+/// A token to sign in with the login code sent to the user, returned by
+/// [`Client::request_login_code`].
+///
+/// [`Client::request_login_code`]: crate::Client::request_login_code
+#[derive(Clone, Debug)]
 pub struct LoginToken {
     pub(crate) phone: String,
     pub(crate) phone_code_hash: String,
+    pub(crate) sent_code_type: SentCodeType,
+    pub(crate) next_type: Option<CodeType>,
+    pub(crate) timeout: Option<i32>,
+}
+
+impl SentCodeType {
+    pub(crate) fn from_raw(code_type: tl::enums::auth::SentCodeType) -> Self {
+        use tl::enums::auth::SentCodeType as SCT;
+        match code_type {
+            SCT::App(c) => Self::App { length: c.length },
+            SCT::Sms(c) => Self::Sms { length: c.length },
+            SCT::Call(c) => Self::Call { length: c.length },
+            SCT::FlashCall(c) => Self::FlashCall { pattern: c.pattern },
+            SCT::MissedCall(c) => Self::MissedCall {
+                prefix: c.prefix,
+                length: c.length,
+            },
+            SCT::EmailCode(c) => Self::Email {
+                email_pattern: c.email_pattern,
+                length: c.length,
+            },
+            SCT::SetUpEmailRequired(_) => Self::SetUpEmailRequired,
+            SCT::FragmentSms(c) => Self::FragmentSms {
+                url: c.url,
+                length: c.length,
+            },
+            SCT::FirebaseSms(c) => Self::FirebaseSms { length: c.length },
+            SCT::SmsWord(c) => Self::SmsWord {
+                beginning: c.beginning,
+            },
+            SCT::SmsPhrase(c) => Self::SmsPhrase {
+                beginning: c.beginning,
+            },
+        }
+    }
+}
+
+impl CodeType {
+    pub(crate) fn from_raw(code_type: tl::enums::auth::CodeType) -> Self {
+        use tl::enums::auth::CodeType as CT;
+        match code_type {
+            CT::Sms => Self::Sms,
+            CT::Call => Self::Call,
+            CT::FlashCall => Self::FlashCall,
+            CT::MissedCall => Self::MissedCall,
+            CT::FragmentSms => Self::FragmentSms,
+        }
+    }
+}
+
+impl LoginToken {
+    pub(crate) fn new(phone: String, sent_code: tl::types::auth::SentCode) -> Self {
+        Self {
+            phone,
+            phone_code_hash: sent_code.phone_code_hash,
+            sent_code_type: SentCodeType::from_raw(sent_code.r#type),
+            next_type: sent_code.next_type.map(CodeType::from_raw),
+            timeout: sent_code.timeout,
+        }
+    }
+
+    /// How the login code was sent.
+    pub fn sent_code_type(&self) -> &SentCodeType {
+        &self.sent_code_type
+    }
+
+    /// How the login code will be sent if it's resent, if it can be resent at all.
+    pub fn next_type(&self) -> Option<&CodeType> {
+        self.next_type.as_ref()
+    }
+
+    /// How long to wait before the code can be resent, if known.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+            .map(|secs| Duration::from_secs(u64::try_from(secs).unwrap_or(0)))
+    }
+
+    /// The purpose to verify the login email with, when it must be set up before signing in.
+    pub(crate) fn email_verify_purpose(&self) -> tl::enums::EmailVerifyPurpose {
+        tl::types::EmailVerifyPurposeLoginSetup {
+            phone_number: self.phone.clone(),
+            phone_code_hash: self.phone_code_hash.clone(),
+        }
+        .into()
+    }
 }
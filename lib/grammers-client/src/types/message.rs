@@ -82,6 +82,8 @@ pub struct Message {
     // a message action for instance. Keeping the entire set like this allows for cheaper clones
     // and moves, and saves us from worrying about picking out all the chats we care about.
     pub(crate) chats: Arc<ChatMap>,
+    // Whether this message was received as an update caused by a request made by this client.
+    pub(crate) own: bool,
 }
 
 impl Message {
@@ -98,6 +100,7 @@ impl Message {
                 raw_action: None,
                 client: client.clone(),
                 chats: Arc::clone(chats),
+                own: false,
             }),
             tl::enums::Message::Service(msg) => Some(Message {
                 raw: tl::types::Message {
@@ -143,6 +146,7 @@ impl Message {
                 raw_action: Some(msg.action),
                 client: client.clone(),
                 chats: Arc::clone(chats),
+                own: false,
             }),
         }
     }
@@ -212,6 +216,7 @@ impl Message {
             raw_action: None,
             client: client.clone(),
             chats: ChatMap::single(Chat::unpack(chat)),
+            own: false,
         }
     }
 
@@ -221,6 +226,14 @@ impl Message {
         self.raw.out
     }
 
+    /// Whether this message was received as an update caused by a request made by this same
+    /// client (for example, when sending or editing a message), rather than pushed by Telegram.
+    ///
+    /// Always `false` for messages that were not received as updates.
+    pub fn own(&self) -> bool {
+        self.own
+    }
+
     /// Whether you were mentioned in this message or not.
     ///
     /// This includes @username mentions, text mentions, and messages replying to one of your
@@ -701,6 +714,7 @@ impl fmt::Debug for Message {
         f.debug_struct("Message")
             .field("id", &self.id())
             .field("outgoing", &self.outgoing())
+            .field("own", &self.own())
             .field("date", &self.date())
             .field("text", &self.text())
             .field("chat", &self.chat())
@@ -16,6 +16,7 @@
 pub struct MessageDeletion {
     pub(crate) channel_id: Option<i64>,
     pub(crate) messages: Vec<i32>,
+    pub(crate) own: bool,
 }
 
 impl MessageDeletion {
@@ -25,6 +26,7 @@ impl MessageDeletion {
         Self {
             channel_id: Some(channel),
             messages,
+            own: false,
         }
     }
 
@@ -33,6 +35,7 @@ impl MessageDeletion {
         Self {
             channel_id: None,
             messages,
+            own: false,
         }
     }
 
@@ -46,6 +49,11 @@ impl MessageDeletion {
         &self.messages
     }
 
+    /// Whether the messages were deleted by a request made by this same client.
+    pub fn own(&self) -> bool {
+        self.own
+    }
+
     /// Gain ownership of underlying Vec of message IDs that was deleted.
     pub fn into_messages(self) -> Vec<i32> {
         self.messages
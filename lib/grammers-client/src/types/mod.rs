@@ -12,6 +12,7 @@
 //! they directly uses `grammers-tl-types`. This will probably change before the 1.0 release.
 pub mod action;
 pub mod attributes;
+pub mod authorization;
 pub mod button;
 pub mod callback_query;
 pub mod chat;
@@ -31,6 +32,7 @@ pub mod participant;
 pub mod password_token;
 pub mod permissions;
 pub mod photo_sizes;
+pub mod qr_login_token;
 pub mod reactions;
 pub mod reply_markup;
 pub mod terms_of_service;
@@ -38,6 +40,7 @@ pub mod update;
 
 pub use action::ActionSender;
 pub use attributes::Attribute;
+pub use authorization::Authorization;
 pub use callback_query::CallbackQuery;
 pub use chat::{Channel, Chat, Group, PackedChat, Platform, RestrictionReason, User};
 pub use chat_map::ChatMap;
@@ -50,7 +53,7 @@ pub use inline::send::InlineSend;
 pub use input_media::InputMedia;
 pub use input_message::InputMessage;
 pub use iter_buffer::IterBuffer;
-pub use login_token::LoginToken;
+pub use login_token::{CodeType, LoginToken, SentCodeType};
 pub(crate) use media::Uploaded;
 pub use media::{Media, Photo};
 pub use message::Message;
@@ -58,6 +61,7 @@ pub use message_deletion::MessageDeletion;
 pub use participant::{Participant, Role};
 pub use password_token::PasswordToken;
 pub use permissions::{Permissions, Restrictions};
+pub use qr_login_token::QrLoginToken;
 pub use reactions::InputReactions;
 pub(crate) use reply_markup::ReplyMarkup;
 pub use terms_of_service::TermsOfService;
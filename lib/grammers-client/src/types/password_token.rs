@@ -21,4 +21,19 @@ impl PasswordToken {
     pub fn hint(&self) -> Option<&str> {
         self.password.hint.as_deref()
     }
+
+    /// Whether the account has a cloud password set.
+    pub fn has_password(&self) -> bool {
+        self.password.has_password
+    }
+
+    /// Whether the account has a confirmed recovery email to recover the password with.
+    pub fn has_recovery(&self) -> bool {
+        self.password.has_recovery
+    }
+
+    /// The pattern of the recovery email which is still waiting for confirmation, if any.
+    pub fn email_unconfirmed_pattern(&self) -> Option<&str> {
+        self.password.email_unconfirmed_pattern.as_deref()
+    }
 }
@@ -0,0 +1,35 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use crate::utils;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+
+/// A token to log in by scanning a QR code from an application where the account is already
+/// logged in.
+///
+/// The token is only valid until it [expires](QrLoginToken::expires), after which a new one
+/// should be shown instead.
+#[derive(Clone, Debug)]
+pub struct QrLoginToken {
+    pub(crate) token: Vec<u8>,
+    pub(crate) expires: i32,
+    pub(crate) except_ids: Vec<i64>,
+}
+
+impl QrLoginToken {
+    /// The `tg://login` URL to encode into the QR code shown to the user.
+    pub fn url(&self) -> String {
+        format!("tg://login?token={}", URL_SAFE_NO_PAD.encode(&self.token))
+    }
+
+    /// When the token expires.
+    pub fn expires(&self) -> DateTime<Utc> {
+        utils::date(self.expires)
+    }
+}
@@ -97,4 +97,27 @@ impl Update {
             update => Some(Self::Raw(update)),
         }
     }
+
+    /// Whether this update was caused by a request made by this same client, such as sending,
+    /// editing or deleting a message, instead of being pushed by Telegram.
+    ///
+    /// Reacting to these updates by making more requests can easily lead to loops.
+    ///
+    /// Only message updates can be marked as such. Other updates always return `false`.
+    pub fn is_own(&self) -> bool {
+        match self {
+            Self::NewMessage(message) | Self::MessageEdited(message) => message.own,
+            Self::MessageDeleted(deletion) => deletion.own,
+            _ => false,
+        }
+    }
+
+    /// Mark the update as caused by a request made by this client.
+    pub(crate) fn mark_own(&mut self) {
+        match self {
+            Self::NewMessage(message) | Self::MessageEdited(message) => message.own = true,
+            Self::MessageDeleted(deletion) => deletion.own = true,
+            _ => {}
+        }
+    }
 }
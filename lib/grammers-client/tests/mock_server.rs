@@ -0,0 +1,1039 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Runs the client against the mock server, without network access.
+use grammers_client::grammers_tl_types as tl;
+use grammers_client::session::{MemorySession, Session};
+use grammers_client::types::{CodeType, SentCodeType};
+use grammers_client::{
+    Client, Config, InitParams, InvocationError, LoginOutcome, PasswordError, QrLogin, Recorder,
+    Recording, SignInError, Update,
+};
+use grammers_crypto::hex;
+use grammers_crypto::two_factor_auth::{calculate_2fa, calculate_password_hash};
+use grammers_mock_server::{MockServer, RpcError};
+use grammers_mtsender::{Connector, Stream};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const PHONE: &str = "+15550100";
+const PHONE_CODE_HASH: &str = "hash";
+const LOGIN_CODE: &str = "12345";
+const USER_ID: i64 = 1234;
+const PASSWORD: &str = "hunter2";
+const SRP_ID: i64 = 42;
+// The 2048-bit safe prime used by Telegram, with a generator of 3.
+const PASSWORD_P: &str = "c71caeb9c6b1c9048e6c522f70f13f73980d40238e3e21c14934d037563d930f\
+                          48198a0aa7c14058229493d22530f4dbfa336f6e0ac925139543aed44cce7c37\
+                          20fd51f69458705ac68cd4fe6b6b13abdc9746512969328454f18faf8c595f64\
+                          2477fe96bb2a941d5bcd1d4ac8cc49880708fa9b378e3c4f3a9060bee67cf9a4\
+                          a4a695811051907e162753b56b0f6b410dba74d8a84b2a14b3144e0ef1284754\
+                          fd17ed950d5965b4b9dd46582db1178d169c6bc465b0d6ff9ca3928fef5b9ae4\
+                          e418fc15e83ebea0f87fa9ff5eed70050ded2849f47bf959d956850ce929851f\
+                          0d8115f635b105ee2e4e15d04b2454bf6f4fadf034b10403119cd8e3b92fcc5b";
+
+fn block_on(future: impl Future<Output = ()>) {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+fn now() -> i32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i32
+}
+
+fn user() -> tl::types::User {
+    tl::types::User {
+        is_self: true,
+        contact: false,
+        mutual_contact: false,
+        deleted: false,
+        bot: false,
+        bot_chat_history: false,
+        bot_nochats: false,
+        verified: false,
+        restricted: false,
+        min: false,
+        bot_inline_geo: false,
+        support: false,
+        scam: false,
+        apply_min_photo: false,
+        fake: false,
+        bot_attach_menu: false,
+        premium: false,
+        attach_menu_enabled: false,
+        bot_can_edit: false,
+        close_friend: false,
+        stories_hidden: false,
+        stories_unavailable: false,
+        contact_require_premium: false,
+        bot_business: false,
+        bot_has_main_app: false,
+        id: USER_ID,
+        access_hash: Some(5678),
+        first_name: Some("Mock".into()),
+        last_name: None,
+        username: None,
+        phone: Some(PHONE[1..].into()),
+        photo: None,
+        status: None,
+        bot_info_version: None,
+        restriction_reason: None,
+        bot_inline_placeholder: None,
+        lang_code: None,
+        emoji_status: None,
+        usernames: None,
+        stories_max_id: None,
+        color: None,
+        profile_color: None,
+        bot_active_users: None,
+    }
+}
+
+fn password_algo() -> tl::types::PasswordKdfAlgoSha256Sha256Pbkdf2Hmacsha512iter100000Sha256ModPow {
+    tl::types::PasswordKdfAlgoSha256Sha256Pbkdf2Hmacsha512iter100000Sha256ModPow {
+        salt1: b"salt1".to_vec(),
+        salt2: b"salt2".to_vec(),
+        g: 3,
+        p: hex::from_hex(PASSWORD_P),
+    }
+}
+
+/// The password information of an account, with the password above if `has_password`.
+fn password_info(has_password: bool) -> tl::types::account::Password {
+    tl::types::account::Password {
+        has_recovery: false,
+        has_secure_values: false,
+        has_password,
+        current_algo: has_password.then(|| password_algo().into()),
+        srp_b: has_password.then(|| vec![5]),
+        srp_id: has_password.then_some(SRP_ID),
+        hint: None,
+        email_unconfirmed_pattern: None,
+        new_algo: password_algo().into(),
+        new_secure_algo: tl::enums::SecurePasswordKdfAlgo::Unknown,
+        secure_random: vec![6],
+        pending_reset_date: None,
+        login_email_pattern: None,
+    }
+}
+
+/// Check that the settings set `password` with the parameters of `password_algo`.
+fn assert_new_password(settings: &tl::enums::account::PasswordInputSettings, password: &str) {
+    let tl::enums::account::PasswordInputSettings::Settings(settings) = settings;
+    let algo = password_algo();
+    let salt1 = match settings.new_algo.as_ref().unwrap() {
+        tl::enums::PasswordKdfAlgo::Sha256Sha256Pbkdf2Hmacsha512iter100000Sha256ModPow(
+            new_algo,
+        ) => {
+            assert_eq!(new_algo.salt2, algo.salt2);
+            assert_eq!(new_algo.p, algo.p);
+            new_algo.salt1.clone()
+        }
+        tl::enums::PasswordKdfAlgo::Unknown => panic!("password was removed"),
+    };
+    // The client must add its own random bytes to the salt.
+    assert_eq!(salt1.len(), algo.salt1.len() + 32);
+    assert!(salt1.starts_with(&algo.salt1));
+    assert_eq!(
+        settings.new_password_hash.as_deref(),
+        Some(&calculate_password_hash(&salt1, &algo.salt2, &algo.p, &algo.g, password)[..])
+    );
+}
+
+/// Start a server that lets the user above log in with the login code above.
+fn login_server() -> MockServer {
+    let server = MockServer::new();
+    server
+        .on(|request: tl::functions::auth::SendCode| {
+            assert_eq!(request.phone_number, PHONE);
+            Ok(tl::types::auth::SentCode {
+                r#type: tl::types::auth::SentCodeTypeApp { length: 5 }.into(),
+                phone_code_hash: PHONE_CODE_HASH.into(),
+                next_type: None,
+                timeout: None,
+            }
+            .into())
+        })
+        .on(|request: tl::functions::auth::SignIn| {
+            assert_eq!(request.phone_code_hash, PHONE_CODE_HASH);
+            if request.phone_code.as_deref() == Some(LOGIN_CODE) {
+                Ok(tl::types::auth::Authorization {
+                    setup_password_required: false,
+                    otherwise_relogin_days: None,
+                    tmp_sessions: None,
+                    future_auth_token: None,
+                    user: user().into(),
+                }
+                .into())
+            } else {
+                Err(RpcError::new(400, "PHONE_CODE_INVALID"))
+            }
+        });
+    server
+}
+
+fn connector(server: &MockServer) -> Arc<dyn Connector> {
+    let server = server.clone();
+    Arc::new(move |_| {
+        let stream = server.connect();
+        async move { Ok(Box::new(stream) as Box<dyn Stream>) }
+    })
+}
+
+async fn connect(server: &MockServer, recorder: Option<Arc<dyn Recorder>>) -> Client {
+    Client::connect(Config {
+        session: Arc::new(MemorySession::new()),
+        api_id: 1,
+        api_hash: "hash".into(),
+        params: InitParams {
+            connector: Some(connector(server)),
+            recorder,
+            ..Default::default()
+        },
+    })
+    .await
+    .unwrap()
+}
+
+/// Updates with a new message sent to the user above by themselves.
+fn incoming_message() -> tl::types::Updates {
+    // The client doesn't know the sender's access hash, so the user must come along.
+    tl::types::Updates {
+        updates: vec![tl::types::UpdateNewMessage {
+            message: tl::types::Message {
+                out: false,
+                mentioned: false,
+                media_unread: false,
+                silent: false,
+                post: false,
+                from_scheduled: false,
+                legacy: false,
+                edit_hide: false,
+                pinned: false,
+                noforwards: false,
+                invert_media: false,
+                offline: false,
+                id: 2,
+                from_id: Some(tl::types::PeerUser { user_id: USER_ID }.into()),
+                from_boosts_applied: None,
+                peer_id: tl::types::PeerUser { user_id: USER_ID }.into(),
+                saved_peer_id: None,
+                fwd_from: None,
+                via_bot_id: None,
+                via_business_bot_id: None,
+                reply_to: None,
+                date: now(),
+                message: "Hi".into(),
+                media: None,
+                reply_markup: None,
+                entities: None,
+                views: None,
+                forwards: None,
+                replies: None,
+                edit_date: None,
+                post_author: None,
+                grouped_id: None,
+                reactions: None,
+                restriction_reason: None,
+                ttl_period: None,
+                quick_reply_shortcut_id: None,
+                effect: None,
+                factcheck: None,
+            }
+            .into(),
+            pts: 3,
+            pts_count: 1,
+        }
+        .into()],
+        users: vec![user().into()],
+        chats: Vec::new(),
+        date: now(),
+        seq: 0,
+    }
+}
+
+/// Start a server that also lets the user above send messages to themselves.
+fn chat_server() -> MockServer {
+    let server = login_server();
+    server.on(|request: tl::functions::messages::SendMessage| {
+        assert_eq!(request.message, "Hello");
+        Ok(tl::types::UpdateShortSentMessage {
+            out: true,
+            id: 1,
+            pts: 2,
+            pts_count: 1,
+            date: now(),
+            media: None,
+            entities: None,
+            ttl_period: None,
+        }
+        .into())
+    });
+    server
+}
+
+/// Log in, send a message to oneself, and wait for the message pushed by `push_reply`.
+async fn chat(client: &Client, push_reply: impl FnOnce()) {
+    let token = client.request_login_code(PHONE).await.unwrap();
+    let me = client.sign_in(&token, LOGIN_CODE).await.unwrap();
+
+    let message = client.send_message(me.pack(), "Hello").await.unwrap();
+    assert_eq!(message.id(), 1);
+    assert_eq!(message.text(), "Hello");
+
+    push_reply();
+    loop {
+        if let Update::NewMessage(message) = client.next_update().await.unwrap() {
+            if message.id() == 2 {
+                assert_eq!(message.text(), "Hi");
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn login() {
+    block_on(async {
+        let server = login_server();
+        let client = connect(&server, None).await;
+
+        let token = client.request_login_code(PHONE).await.unwrap();
+        assert_eq!(token.sent_code_type(), &SentCodeType::App { length: 5 });
+        assert!(matches!(
+            client.sign_in(&token, "00000").await,
+            Err(SignInError::InvalidCode)
+        ));
+
+        let user = client.sign_in(&token, LOGIN_CODE).await.unwrap();
+        assert_eq!(user.id(), USER_ID);
+        assert_eq!(user.first_name(), Some("Mock"));
+        assert_eq!(server.received::<tl::functions::auth::SignIn>().len(), 2);
+    });
+}
+
+#[test]
+fn connect_with_lost_auth_key() {
+    block_on(async {
+        let server = login_server();
+
+        // The server never knew this key, as if it had forgotten it while the client was offline.
+        let lost_auth_key = [7; 256];
+        let session = Arc::new(MemorySession::new());
+        session.insert_dc(2, "127.0.0.1:443".parse().unwrap(), lost_auth_key);
+        session.set_user(USER_ID, 2, false);
+
+        let client = Client::connect(Config {
+            session: session.clone(),
+            api_id: 1,
+            api_hash: "hash".into(),
+            params: InitParams {
+                connector: Some(connector(&server)),
+                ..Default::default()
+            },
+        })
+        .await
+        .unwrap();
+
+        let auth_key = session.dc_auth_key(2).unwrap();
+        assert_ne!(auth_key, lost_auth_key);
+        assert!(session.get_user().is_none());
+        client.request_login_code(PHONE).await.unwrap();
+    });
+}
+
+#[test]
+fn resend_code() {
+    block_on(async {
+        let server = login_server();
+        server
+            .on(|_: tl::functions::auth::SendCode| {
+                Ok(tl::types::auth::SentCode {
+                    r#type: tl::types::auth::SentCodeTypeApp { length: 5 }.into(),
+                    phone_code_hash: PHONE_CODE_HASH.into(),
+                    next_type: Some(tl::enums::auth::CodeType::Sms),
+                    timeout: Some(60),
+                }
+                .into())
+            })
+            .on(|request: tl::functions::auth::ResendCode| {
+                assert_eq!(request.phone_code_hash, PHONE_CODE_HASH);
+                Ok(tl::types::auth::SentCode {
+                    r#type: tl::types::auth::SentCodeTypeSms { length: 5 }.into(),
+                    phone_code_hash: PHONE_CODE_HASH.into(),
+                    next_type: None,
+                    timeout: None,
+                }
+                .into())
+            });
+
+        let client = connect(&server, None).await;
+        let token = client.request_login_code(PHONE).await.unwrap();
+        assert_eq!(token.next_type(), Some(&CodeType::Sms));
+        assert_eq!(token.timeout(), Some(Duration::from_secs(60)));
+
+        let token = match client.resend_code(&token).await.unwrap() {
+            LoginOutcome::Token(token) => token,
+            LoginOutcome::SignedIn(_) => panic!("signed in without a new code"),
+        };
+        assert_eq!(token.sent_code_type(), &SentCodeType::Sms { length: 5 });
+        assert_eq!(token.next_type(), None);
+
+        let user = client.sign_in(&token, LOGIN_CODE).await.unwrap();
+        assert_eq!(user.id(), USER_ID);
+    });
+}
+
+#[test]
+fn email_code_login() {
+    block_on(async {
+        let server = login_server();
+        server
+            .on(|_: tl::functions::auth::SendCode| {
+                Ok(tl::types::auth::SentCode {
+                    r#type: tl::types::auth::SentCodeTypeEmailCode {
+                        apple_signin_allowed: false,
+                        google_signin_allowed: false,
+                        email_pattern: "m*@example.com".into(),
+                        length: 6,
+                        reset_available_period: None,
+                        reset_pending_date: None,
+                    }
+                    .into(),
+                    phone_code_hash: PHONE_CODE_HASH.into(),
+                    next_type: None,
+                    timeout: None,
+                }
+                .into())
+            })
+            .on(|request: tl::functions::auth::SignIn| {
+                assert_eq!(request.phone_code, None);
+                if request.email_verification
+                    == Some(
+                        tl::types::EmailVerificationCode {
+                            code: "123456".into(),
+                        }
+                        .into(),
+                    )
+                {
+                    Ok(tl::types::auth::Authorization {
+                        setup_password_required: false,
+                        otherwise_relogin_days: None,
+                        tmp_sessions: None,
+                        future_auth_token: None,
+                        user: user().into(),
+                    }
+                    .into())
+                } else {
+                    Err(RpcError::new(400, "EMAIL_CODE_INVALID"))
+                }
+            });
+
+        let client = connect(&server, None).await;
+        let token = client.request_login_code(PHONE).await.unwrap();
+        assert_eq!(
+            token.sent_code_type(),
+            &SentCodeType::Email {
+                email_pattern: "m*@example.com".into(),
+                length: 6,
+            }
+        );
+        assert!(matches!(
+            client.sign_in(&token, "000000").await,
+            Err(SignInError::InvalidCode)
+        ));
+
+        let user = client.sign_in(&token, "123456").await.unwrap();
+        assert_eq!(user.id(), USER_ID);
+    });
+}
+
+#[test]
+fn set_up_login_email() {
+    block_on(async {
+        let server = login_server();
+        server
+            .on(|_: tl::functions::auth::SendCode| {
+                Ok(tl::types::auth::SentCode {
+                    r#type: tl::types::auth::SentCodeTypeSetUpEmailRequired {
+                        apple_signin_allowed: false,
+                        google_signin_allowed: false,
+                    }
+                    .into(),
+                    phone_code_hash: PHONE_CODE_HASH.into(),
+                    next_type: None,
+                    timeout: Some(-1),
+                }
+                .into())
+            })
+            .on(|request: tl::functions::account::SendVerifyEmailCode| {
+                assert_eq!(request.email, "me@example.com");
+                Ok(tl::types::account::SentEmailCode {
+                    email_pattern: "m*@example.com".into(),
+                    length: 6,
+                }
+                .into())
+            })
+            .on(|request: tl::functions::account::VerifyEmail| {
+                assert_eq!(
+                    request.purpose,
+                    tl::types::EmailVerifyPurposeLoginSetup {
+                        phone_number: PHONE.into(),
+                        phone_code_hash: PHONE_CODE_HASH.into(),
+                    }
+                    .into()
+                );
+                match request.verification {
+                    tl::enums::EmailVerification::Code(code) if code.code == "123456" => {
+                        Ok(tl::types::account::EmailVerifiedLogin {
+                            email: "me@example.com".into(),
+                            sent_code: tl::types::auth::SentCode {
+                                r#type: tl::types::auth::SentCodeTypeSms { length: 5 }.into(),
+                                phone_code_hash: PHONE_CODE_HASH.into(),
+                                next_type: None,
+                                timeout: None,
+                            }
+                            .into(),
+                        }
+                        .into())
+                    }
+                    _ => Err(RpcError::new(400, "EMAIL_CODE_INVALID")),
+                }
+            });
+
+        let client = connect(&server, None).await;
+        let token = client.request_login_code(PHONE).await.unwrap();
+        assert_eq!(token.sent_code_type(), &SentCodeType::SetUpEmailRequired);
+        assert_eq!(token.timeout(), Some(Duration::ZERO));
+
+        let length = client
+            .send_login_email_code(&token, "me@example.com")
+            .await
+            .unwrap();
+        assert_eq!(length, 6);
+        assert!(matches!(
+            client.verify_login_email(&token, "000000").await,
+            Err(SignInError::InvalidCode)
+        ));
+
+        let token = match client.verify_login_email(&token, "123456").await.unwrap() {
+            LoginOutcome::Token(token) => token,
+            LoginOutcome::SignedIn(_) => panic!("signed in without a new code"),
+        };
+        assert_eq!(token.sent_code_type(), &SentCodeType::Sms { length: 5 });
+
+        let user = client.sign_in(&token, LOGIN_CODE).await.unwrap();
+        assert_eq!(user.id(), USER_ID);
+    });
+}
+
+#[test]
+fn resend_code_signs_in() {
+    block_on(async {
+        let server = login_server();
+        server.on(|_: tl::functions::auth::ResendCode| {
+            Ok(tl::types::auth::SentCodeSuccess {
+                authorization: tl::types::auth::Authorization {
+                    setup_password_required: false,
+                    otherwise_relogin_days: None,
+                    tmp_sessions: None,
+                    future_auth_token: None,
+                    user: user().into(),
+                }
+                .into(),
+            }
+            .into())
+        });
+
+        let client = connect(&server, None).await;
+        let token = client.request_login_code(PHONE).await.unwrap();
+        match client.resend_code(&token).await {
+            Ok(LoginOutcome::SignedIn(user)) => assert_eq!(user.id(), USER_ID),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    });
+}
+
+#[test]
+fn sign_up() {
+    block_on(async {
+        let server = login_server();
+        server
+            .on(|_: tl::functions::auth::SignIn| {
+                Ok(tl::types::auth::AuthorizationSignUpRequired {
+                    terms_of_service: Some(
+                        tl::types::help::TermsOfService {
+                            popup: false,
+                            id: tl::types::DataJson {
+                                data: "\"tos\"".into(),
+                            }
+                            .into(),
+                            text: "Be nice.".into(),
+                            entities: Vec::new(),
+                            min_age_confirm: None,
+                        }
+                        .into(),
+                    ),
+                }
+                .into())
+            })
+            .on(|request: tl::functions::auth::SignUp| {
+                assert_eq!(request.phone_code_hash, PHONE_CODE_HASH);
+                Ok(tl::types::auth::Authorization {
+                    setup_password_required: false,
+                    otherwise_relogin_days: None,
+                    tmp_sessions: None,
+                    future_auth_token: None,
+                    user: user().into(),
+                }
+                .into())
+            })
+            .on(|_: tl::functions::help::AcceptTermsOfService| Ok(true));
+
+        let client = connect(&server, None).await;
+        let token = client.request_login_code(PHONE).await.unwrap();
+        let terms_of_service = match client.sign_in(&token, LOGIN_CODE).await {
+            Err(SignInError::SignUpRequired { terms_of_service }) => terms_of_service.unwrap(),
+            _ => panic!("sign up was not required"),
+        };
+        assert_eq!(terms_of_service.text(), "Be nice.");
+
+        let user = client.sign_up(&token, "Mock", "").await.unwrap();
+        assert_eq!(user.id(), USER_ID);
+        client
+            .accept_terms_of_service(&terms_of_service)
+            .await
+            .unwrap();
+
+        let sign_up = &server.received::<tl::functions::auth::SignUp>()[0];
+        assert_eq!(sign_up.phone_number, PHONE);
+        assert_eq!(sign_up.first_name, "Mock");
+        assert_eq!(
+            server.received::<tl::functions::help::AcceptTermsOfService>()[0].id,
+            tl::types::DataJson {
+                data: "\"tos\"".into()
+            }
+            .into()
+        );
+    });
+}
+
+#[test]
+fn sign_up_required_again() {
+    block_on(async {
+        let server = login_server();
+        server.on(|_: tl::functions::auth::SignUp| {
+            Ok(tl::types::auth::AuthorizationSignUpRequired {
+                terms_of_service: None,
+            }
+            .into())
+        });
+
+        let client = connect(&server, None).await;
+        let token = client.request_login_code(PHONE).await.unwrap();
+        assert!(matches!(
+            client.sign_up(&token, "Mock", "").await,
+            Err(SignInError::SignUpRequired {
+                terms_of_service: None
+            })
+        ));
+    });
+}
+
+#[test]
+fn qr_login() {
+    block_on(async {
+        let server = MockServer::new();
+        let mut exported = 0;
+        server
+            .on(move |_: tl::functions::auth::ExportLoginToken| {
+                exported += 1;
+                Ok(if exported == 1 {
+                    tl::types::auth::LoginToken {
+                        expires: now() + 30,
+                        token: b"token".to_vec(),
+                    }
+                    .into()
+                } else {
+                    // Once accepted, the account turns out to be in a different datacenter.
+                    tl::types::auth::LoginTokenMigrateTo {
+                        dc_id: 4,
+                        token: b"migrated".to_vec(),
+                    }
+                    .into()
+                })
+            })
+            .on(|request: tl::functions::auth::ImportLoginToken| {
+                assert_eq!(request.token, b"migrated");
+                Ok(tl::types::auth::LoginTokenSuccess {
+                    authorization: tl::types::auth::Authorization {
+                        setup_password_required: false,
+                        otherwise_relogin_days: None,
+                        tmp_sessions: None,
+                        future_auth_token: None,
+                        user: user().into(),
+                    }
+                    .into(),
+                }
+                .into())
+            });
+
+        let client = connect(&server, None).await;
+        let token = match client.request_qr_login(&[]).await.unwrap() {
+            QrLogin::Token(token) => token,
+            QrLogin::Success(_) => panic!("logged in before scanning the code"),
+        };
+        assert_eq!(token.url(), "tg://login?token=dG9rZW4");
+
+        server.send_update(tl::types::UpdateShort {
+            update: tl::enums::Update::LoginToken,
+            date: now(),
+        });
+        match client.wait_qr_login(token).await.unwrap() {
+            QrLogin::Success(user) => assert_eq!(user.id(), USER_ID),
+            QrLogin::Token(_) => panic!("token expired before being accepted"),
+        }
+        assert_eq!(
+            server
+                .received::<tl::functions::auth::ExportLoginToken>()
+                .len(),
+            2
+        );
+    });
+}
+
+#[test]
+fn qr_login_migration_loop() {
+    block_on(async {
+        let migrate_to = || -> Result<tl::enums::auth::LoginToken, RpcError> {
+            Ok(tl::types::auth::LoginTokenMigrateTo {
+                dc_id: 4,
+                token: b"migrated".to_vec(),
+            }
+            .into())
+        };
+        let server = MockServer::new();
+        server
+            .on(move |_: tl::functions::auth::ExportLoginToken| migrate_to())
+            .on(move |_: tl::functions::auth::ImportLoginToken| migrate_to());
+
+        let client = connect(&server, None).await;
+        match client.request_qr_login(&[]).await {
+            Err(SignInError::Other(InvocationError::Rpc(error))) => {
+                assert_eq!(error.name, "LOGIN_TOKEN_MIGRATE");
+                assert_eq!(error.value, Some(4));
+            }
+            _ => panic!("migrations were not limited"),
+        }
+        assert_eq!(
+            server
+                .received::<tl::functions::auth::ImportLoginToken>()
+                .len(),
+            3
+        );
+    });
+}
+
+#[test]
+fn set_and_change_password() {
+    block_on(async {
+        let server = MockServer::new();
+        let has_password = Arc::new(AtomicBool::new(false));
+        let set_password = has_password.clone();
+        server
+            .on(move |_: tl::functions::account::GetPassword| {
+                Ok(password_info(has_password.load(Ordering::SeqCst)).into())
+            })
+            .on(move |_: tl::functions::account::UpdatePasswordSettings| {
+                set_password.store(true, Ordering::SeqCst);
+                Ok(true)
+            });
+
+        let client = connect(&server, None).await;
+        client
+            .set_password(None, PASSWORD, Some("usual"))
+            .await
+            .unwrap();
+        client
+            .set_password(Some(PASSWORD.as_bytes()), "correct horse", None)
+            .await
+            .unwrap();
+
+        let requests = server.received::<tl::functions::account::UpdatePasswordSettings>();
+        assert_eq!(requests.len(), 2);
+
+        assert_eq!(
+            requests[0].password,
+            tl::enums::InputCheckPasswordSrp::InputCheckPasswordEmpty
+        );
+        assert_new_password(&requests[0].new_settings, PASSWORD);
+
+        let algo = password_algo();
+        let (m1, g_a) = calculate_2fa(
+            &algo.salt1,
+            &algo.salt2,
+            &algo.p,
+            &algo.g,
+            vec![5],
+            vec![6],
+            PASSWORD,
+        );
+        assert_eq!(
+            requests[1].password,
+            tl::types::InputCheckPasswordSrp {
+                srp_id: SRP_ID,
+                a: g_a.to_vec(),
+                m1: m1.to_vec(),
+            }
+            .into()
+        );
+        assert_new_password(&requests[1].new_settings, "correct horse");
+    });
+}
+
+#[test]
+fn set_password_with_invalid_parameters() {
+    block_on(async {
+        let server = MockServer::new();
+        server.on(|_: tl::functions::account::GetPassword| {
+            let mut password_info = password_info(false);
+            let mut algo = password_algo();
+            algo.p = vec![47];
+            password_info.new_algo = algo.into();
+            Ok(password_info.into())
+        });
+
+        let client = connect(&server, None).await;
+        assert!(matches!(
+            client.set_password(None, PASSWORD, None).await,
+            Err(PasswordError::InvalidParameters)
+        ));
+        assert!(server
+            .received::<tl::functions::account::UpdatePasswordSettings>()
+            .is_empty());
+    });
+}
+
+#[test]
+fn password_recovery_email() {
+    block_on(async {
+        let server = MockServer::new();
+        server
+            .on(|_: tl::functions::account::GetPassword| Ok(password_info(true).into()))
+            .on(|request: tl::functions::account::UpdatePasswordSettings| {
+                let tl::enums::account::PasswordInputSettings::Settings(settings) =
+                    request.new_settings;
+                if settings.email.is_some() {
+                    Err(RpcError::new(400, "EMAIL_UNCONFIRMED_6"))
+                } else {
+                    Err(RpcError::new(400, "PASSWORD_HASH_INVALID"))
+                }
+            })
+            .on(|request: tl::functions::account::ConfirmPasswordEmail| {
+                if request.code == "123456" {
+                    Ok(true)
+                } else {
+                    Err(RpcError::new(400, "CODE_INVALID"))
+                }
+            });
+
+        let client = connect(&server, None).await;
+        assert!(matches!(
+            client.remove_password("wrong").await,
+            Err(PasswordError::InvalidPassword)
+        ));
+        assert!(matches!(
+            client
+                .set_password_recovery_email(PASSWORD, "me@example.com")
+                .await,
+            Err(PasswordError::EmailUnconfirmed { code_length: 6 })
+        ));
+        assert!(matches!(
+            client.confirm_password_email("000000").await,
+            Err(PasswordError::InvalidCode)
+        ));
+        client.confirm_password_email("123456").await.unwrap();
+    });
+}
+
+#[test]
+fn recover_password() {
+    block_on(async {
+        let server = MockServer::new();
+        server
+            .on(|_: tl::functions::auth::RequestPasswordRecovery| {
+                Ok(tl::types::auth::PasswordRecovery {
+                    email_pattern: "m*@example.com".into(),
+                }
+                .into())
+            })
+            .on(|_: tl::functions::account::GetPassword| Ok(password_info(true).into()))
+            .on(|request: tl::functions::auth::RecoverPassword| {
+                if request.code == "123456" {
+                    Ok(tl::types::auth::Authorization {
+                        setup_password_required: false,
+                        otherwise_relogin_days: None,
+                        tmp_sessions: None,
+                        future_auth_token: None,
+                        user: user().into(),
+                    }
+                    .into())
+                } else {
+                    Err(RpcError::new(400, "CODE_INVALID"))
+                }
+            });
+
+        let client = connect(&server, None).await;
+        assert_eq!(
+            client.request_password_recovery().await.unwrap(),
+            "m*@example.com"
+        );
+        assert!(matches!(
+            client.recover_password("000000", None, None).await,
+            Err(SignInError::InvalidCode)
+        ));
+        let user = client
+            .recover_password("123456", Some(PASSWORD.as_bytes()), None)
+            .await
+            .unwrap();
+        assert_eq!(user.id(), USER_ID);
+
+        let requests = server.received::<tl::functions::auth::RecoverPassword>();
+        assert_eq!(requests[0].new_settings, None);
+        assert_new_password(requests[1].new_settings.as_ref().unwrap(), PASSWORD);
+    });
+}
+
+fn authorization(hash: i64, current: bool) -> tl::enums::Authorization {
+    tl::types::Authorization {
+        current,
+        official_app: !current,
+        password_pending: false,
+        encrypted_requests_disabled: false,
+        call_requests_disabled: false,
+        unconfirmed: false,
+        hash,
+        device_model: "Mock".into(),
+        platform: "Linux".into(),
+        system_version: "1.0".into(),
+        api_id: 1,
+        app_name: "grammers".into(),
+        app_version: "0.7.0".into(),
+        date_created: 0,
+        date_active: now(),
+        ip: "192.0.2.1".into(),
+        country: "Nowhere".into(),
+        region: "".into(),
+    }
+    .into()
+}
+
+#[test]
+fn authorizations() {
+    block_on(async {
+        let server = MockServer::new();
+        server
+            .on(|_: tl::functions::account::GetAuthorizations| {
+                Ok(tl::types::account::Authorizations {
+                    authorization_ttl_days: 180,
+                    authorizations: vec![authorization(0, true), authorization(1, false)],
+                }
+                .into())
+            })
+            .on(|request: tl::functions::account::ResetAuthorization| {
+                if request.hash == 0 {
+                    Err(RpcError::new(400, "HASH_INVALID"))
+                } else {
+                    Ok(true)
+                }
+            })
+            .on(|_: tl::functions::auth::ResetAuthorizations| Ok(true))
+            .on(|_: tl::functions::account::SetAuthorizationTtl| Ok(true));
+
+        let client = connect(&server, None).await;
+        let authorizations = client.get_authorizations().await.unwrap();
+        assert_eq!(authorizations.len(), 2);
+        assert!(authorizations[0].is_current());
+        assert_eq!(authorizations[1].ip(), "192.0.2.1");
+        assert_eq!(authorizations[1].date_created().timestamp(), 0);
+
+        assert!(client
+            .terminate_authorization(&authorizations[0])
+            .await
+            .unwrap_err()
+            .is("HASH_INVALID"));
+        client
+            .terminate_authorization(&authorizations[1])
+            .await
+            .unwrap();
+        client.terminate_other_authorizations().await.unwrap();
+        client.set_authorization_ttl(30).await.unwrap();
+
+        let terminated = server.received::<tl::functions::account::ResetAuthorization>();
+        assert_eq!(terminated.last().unwrap().hash, 1);
+        assert_eq!(
+            server
+                .received::<tl::functions::auth::ResetAuthorizations>()
+                .len(),
+            1
+        );
+        assert_eq!(
+            server.received::<tl::functions::account::SetAuthorizationTtl>()[0]
+                .authorization_ttl_days,
+            30
+        );
+    });
+}
+
+#[test]
+fn send_message_and_receive_updates() {
+    block_on(async {
+        let server = chat_server();
+        let client = connect(&server, None).await;
+        chat(&client, || server.send_update(incoming_message())).await;
+    });
+}
+
+#[test]
+fn record_and_replay() {
+    block_on(async {
+        let recording = Arc::new(Recording::new());
+        let server = chat_server();
+        let client = connect(&server, Some(recording.clone())).await;
+        chat(&client, || server.send_update(incoming_message())).await;
+        drop(client);
+
+        // The login code is not recorded.
+        let saved = recording.save();
+        assert!(!saved
+            .windows(LOGIN_CODE.len())
+            .any(|w| w == LOGIN_CODE.as_bytes()));
+
+        // There is no server this time, so every result and update must come from the recording.
+        let client = Client::connect(Config {
+            session: Arc::new(MemorySession::new()),
+            api_id: 1,
+            api_hash: "hash".into(),
+            params: InitParams {
+                replay: Some(Arc::new(Recording::load(&saved).unwrap())),
+                ..Default::default()
+            },
+        })
+        .await
+        .unwrap();
+        chat(&client, || {}).await;
+    });
+}
@@ -0,0 +1,100 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use bencher::{benchmark_group, benchmark_main, black_box, Bencher};
+use grammers_crypto::DequeBuffer;
+use grammers_mtproto::mtp::{Encrypted, Mtp};
+use grammers_tl_types as tl;
+
+/// Serializes the request in-place, then encrypts it.
+fn in_place(mtp: &mut Encrypted, buffer: &mut DequeBuffer<u8>, request: &impl tl::RemoteCall) {
+    mtp.push(buffer, request);
+    mtp.finalize(buffer);
+}
+
+/// Serializes the request on its own first, copies it into the buffer, then encrypts it.
+fn copied(mtp: &mut Encrypted, buffer: &mut DequeBuffer<u8>, request: &impl tl::RemoteCall) {
+    mtp.push(buffer, &tl::Blob(request.to_bytes()));
+    mtp.finalize(buffer);
+}
+
+macro_rules! define_benches {
+    ($(fn $func:ident($method:ident, $n:expr, $threshold:expr);)+) => {
+        $(
+            fn $func(bench: &mut Bencher) {
+                let mut mtp = Encrypted::build()
+                    .compression_threshold($threshold)
+                    .finish([1; 256]);
+                let mut buffer = DequeBuffer::with_capacity(1024 * 1024, 64);
+                let request = black_box(tl::functions::upload::SaveFilePart {
+                    file_id: 2,
+                    file_part: 3,
+                    bytes: vec![4; $n],
+                });
+
+                bench.iter(|| {
+                    buffer.clear();
+                    mtp.reset();
+                    $method(&mut mtp, &mut buffer, &request);
+                    black_box(&buffer);
+                });
+                bench.bytes = request.bytes.len() as u64;
+            }
+        )+
+    };
+}
+
+define_benches!(
+    fn in_place_b0016(in_place, 16, None);
+    fn in_place_b0256(in_place, 256, None);
+    fn in_place_b1024(in_place, 1024, None);
+    fn in_place_kb0016(in_place, 16 * 1024, None);
+    fn in_place_kb0128(in_place, 128 * 1024, None);
+    fn in_place_kb0512(in_place, 512 * 1024, None);
+
+    fn copied_b0016(copied, 16, None);
+    fn copied_b0256(copied, 256, None);
+    fn copied_b1024(copied, 1024, None);
+    fn copied_kb0016(copied, 16 * 1024, None);
+    fn copied_kb0128(copied, 128 * 1024, None);
+    fn copied_kb0512(copied, 512 * 1024, None);
+
+    fn in_place_gzip_kb0016(in_place, 16 * 1024, Some(512));
+    fn in_place_gzip_kb0128(in_place, 128 * 1024, Some(512));
+
+    fn copied_gzip_kb0016(copied, 16 * 1024, Some(512));
+    fn copied_gzip_kb0128(copied, 128 * 1024, Some(512));
+);
+
+benchmark_group!(
+    in_place_small,
+    in_place_b0016,
+    in_place_b0256,
+    in_place_b1024
+);
+benchmark_group!(
+    in_place_big,
+    in_place_kb0016,
+    in_place_kb0128,
+    in_place_kb0512
+);
+benchmark_group!(copied_small, copied_b0016, copied_b0256, copied_b1024);
+benchmark_group!(copied_big, copied_kb0016, copied_kb0128, copied_kb0512);
+benchmark_group!(
+    compressed,
+    in_place_gzip_kb0016,
+    in_place_gzip_kb0128,
+    copied_gzip_kb0016,
+    copied_gzip_kb0128
+);
+benchmark_main!(
+    in_place_small,
+    in_place_big,
+    copied_small,
+    copied_big,
+    compressed
+);
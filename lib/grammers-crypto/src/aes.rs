@@ -84,3 +84,82 @@ pub fn ige_decrypt(ciphertext: &[u8], key: &[u8; 32], iv: &[u8; 32]) -> Vec<u8>
 
     plaintext
 }
+
+/// The AES-256 cipher in CTR mode, as used by obfuscated transports.
+///
+/// The initialization vector is used as a big-endian 128-bit counter. Because CTR is a stream
+/// cipher, the state is kept between calls, so the data can be processed in chunks of any size,
+/// and encryption and decryption are the same operation.
+pub struct Aes256Ctr {
+    cipher: aes::Aes256,
+    counter: [u8; 16],
+    keystream: [u8; 16],
+    used: usize,
+}
+
+impl Aes256Ctr {
+    pub fn new(key: &[u8; 32], iv: &[u8; 16]) -> Self {
+        let key = GenericArray::from_slice(key);
+        Self {
+            cipher: aes::Aes256::new(key),
+            counter: *iv,
+            keystream: [0; 16],
+            used: 16,
+        }
+    }
+
+    /// Encrypt or decrypt the input buffer in-place, continuing where the last call left off.
+    pub fn apply(&mut self, buffer: &mut [u8]) {
+        for byte in buffer.iter_mut() {
+            if self.used == self.keystream.len() {
+                // keystream = encrypt(counter); counter += 1
+                self.keystream = self.counter;
+                self.cipher
+                    .encrypt_block(GenericArray::from_mut_slice(&mut self.keystream));
+                self.counter = u128::from_be_bytes(self.counter)
+                    .wrapping_add(1)
+                    .to_be_bytes();
+                self.used = 0;
+            }
+
+            *byte ^= self.keystream[self.used];
+            self.used += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex;
+
+    // F.5.5 CTR-AES256.Encrypt from NIST SP 800-38A.
+    const KEY: &str = "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4";
+    const IV: &str = "f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff";
+    const PLAINTEXT: &str = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710";
+    const CIPHERTEXT: &str = "601ec313775789a5b7a7f504bbf3d228f443e3ca4d62b59aca84e990cacaf5c52b0930daa23de94ce87017ba2d84988ddfc9c58db67aada613c2dd08457941a6";
+
+    fn get_test_cipher() -> Aes256Ctr {
+        Aes256Ctr::new(
+            &hex::from_hex(KEY).try_into().unwrap(),
+            &hex::from_hex(IV).try_into().unwrap(),
+        )
+    }
+
+    #[test]
+    fn verify_ctr_encryption() {
+        let mut buffer = hex::from_hex(PLAINTEXT);
+        get_test_cipher().apply(&mut buffer);
+        assert_eq!(buffer, hex::from_hex(CIPHERTEXT));
+    }
+
+    #[test]
+    fn verify_ctr_chunked_decryption() {
+        let mut buffer = hex::from_hex(CIPHERTEXT);
+        let mut cipher = get_test_cipher();
+        for chunk in buffer.chunks_mut(7) {
+            cipher.apply(chunk);
+        }
+        assert_eq!(buffer, hex::from_hex(PLAINTEXT));
+    }
+}
@@ -56,6 +56,11 @@ impl AuthKey {
         self.data
     }
 
+    /// Returns the identifier of this key, as used in `auth.bindTempAuthKey`.
+    pub fn id(&self) -> i64 {
+        i64::from_le_bytes(self.key_id)
+    }
+
     /// Calculates the new nonce hash based on the current attributes.
     pub fn calc_new_nonce_hash(&self, new_nonce: &[u8; 32], number: u8) -> [u8; 16] {
         let data = {
@@ -55,6 +55,13 @@ impl<T: Copy + Default> DequeBuffer<T> {
         self.buffer[self.head..self.head + slice.len()].copy_from_slice(slice);
     }
 
+    /// Shortens the buffer, keeping the first `len` elements and dropping the rest from the back.
+    ///
+    /// Has no effect if `len` is greater than or equal to the buffer's current length.
+    pub fn truncate(&mut self, len: usize) {
+        self.buffer.truncate(self.head + len);
+    }
+
     /// Appends an element to the back of the buffer.
     pub fn push(&mut self, value: T) {
         self.buffer.push(value)
@@ -211,4 +218,23 @@ mod tests {
         buffer.clear();
         assert_eq!(repr(&buffer), "[ 0 0 0 0|? ? ? ? ? ? ]");
     }
+
+    #[test]
+    fn truncate_drops_from_back() {
+        let mut buffer = DequeBuffer::<u8>::with_capacity(4, 2);
+        buffer.extend(1..=4);
+        buffer.extend_front(&[5]);
+
+        buffer.truncate(6);
+        sanity_checks(&buffer);
+        assert_eq!(repr(&buffer), "[ 0|5 1 2 3 4 ]");
+
+        buffer.truncate(2);
+        sanity_checks(&buffer);
+        assert_eq!(repr(&buffer), "[ 0|5 1 ? ? ? ]");
+
+        buffer.truncate(0);
+        sanity_checks(&buffer);
+        assert!(buffer.is_empty());
+    }
 }
@@ -105,14 +105,17 @@ fn determine_padding_v2_length(len: usize) -> usize {
 }
 
 // Inner body of `encrypt_data_v2`, separated for testing purposes.
-fn do_encrypt_data_v2(buffer: &mut DequeBuffer<u8>, auth_key: &AuthKey, random_padding: &[u8; 32]) {
+fn do_encrypt_data_v2(
+    buffer: &mut DequeBuffer<u8>,
+    auth_key: &AuthKey,
+    random_padding: &[u8; 32],
+    side: Side,
+) {
     // "Note that MTProto 2.0 requires from 12 to 1024 bytes of padding"
     // "[...] the resulting message length be divisible by 16 bytes"
     let padding_len = determine_padding_v2_length(buffer.len());
     buffer.extend(random_padding.iter().take(padding_len));
 
-    // Encryption is done by the client
-    let side = Side::Client;
     let x = side.x();
 
     // msg_key_large = SHA256 (substr (auth_key, 88+x, 32) + plaintext + random_padding);
@@ -139,19 +142,38 @@ fn do_encrypt_data_v2(buffer: &mut DequeBuffer<u8>, auth_key: &AuthKey, random_p
 ///
 /// [MTProto 2.0 algorithm]: https://core.telegram.org/mtproto/description#defining-aes-key-and-initialization-vector
 pub fn encrypt_data_v2(buffer: &mut DequeBuffer<u8>, auth_key: &AuthKey) {
+    // Encryption is done by the client
+    random_encrypt_data_v2(buffer, auth_key, Side::Client)
+}
+
+/// Like `encrypt_data_v2`, but encrypts the data as the server would, to send it to the client.
+pub fn encrypt_server_data_v2(buffer: &mut DequeBuffer<u8>, auth_key: &AuthKey) {
+    random_encrypt_data_v2(buffer, auth_key, Side::Server)
+}
+
+fn random_encrypt_data_v2(buffer: &mut DequeBuffer<u8>, auth_key: &AuthKey, side: Side) {
     let random_padding = {
         let mut rnd = [0; 32];
         getrandom(&mut rnd).expect("failed to generate a secure padding");
         rnd
     };
 
-    do_encrypt_data_v2(buffer, auth_key, &random_padding)
+    do_encrypt_data_v2(buffer, auth_key, &random_padding, side)
 }
 
 /// This method is the inverse of `encrypt_data_v2`.
 pub fn decrypt_data_v2(ciphertext: &[u8], auth_key: &AuthKey) -> Result<Vec<u8>, Error> {
     // Decryption is done from the server
-    let side = Side::Server;
+    do_decrypt_data_v2(ciphertext, auth_key, Side::Server)
+}
+
+/// This method is the inverse of `encrypt_data_v2`, used by the server to decrypt the data sent
+/// by the client.
+pub fn decrypt_client_data_v2(ciphertext: &[u8], auth_key: &AuthKey) -> Result<Vec<u8>, Error> {
+    do_decrypt_data_v2(ciphertext, auth_key, Side::Client)
+}
+
+fn do_decrypt_data_v2(ciphertext: &[u8], auth_key: &AuthKey, side: Side) -> Result<Vec<u8>, Error> {
     let x = side.x();
 
     if ciphertext.len() < 24 || (ciphertext.len() - 24) % 16 != 0 {
@@ -170,7 +192,7 @@ pub fn decrypt_data_v2(ciphertext: &[u8], auth_key: &AuthKey) -> Result<Vec<u8>,
         buffer
     };
 
-    let (key, iv) = calc_key(auth_key, &msg_key, Side::Server);
+    let (key, iv) = calc_key(auth_key, &msg_key, side);
     let plaintext = decrypt_ige(&ciphertext[24..], &key, &iv);
 
     // https://core.telegram.org/mtproto/security_guidelines#mtproto-encrypted-messages
@@ -183,6 +205,96 @@ pub fn decrypt_data_v2(ciphertext: &[u8], auth_key: &AuthKey) -> Result<Vec<u8>,
     Ok(plaintext)
 }
 
+/// Calculate the key based on Telegram [guidelines for MTProto 1],
+/// returning the pair `(key, iv)` for use in AES-IGE mode.
+///
+/// This older scheme is only used to encrypt the message that binds
+/// a temporary authorization key to the permanent one.
+///
+/// [guidelines for MTProto 1]: https://core.telegram.org/mtproto_v1#defining-aes-key-and-initialization-vector
+fn calc_key_v1(auth_key: &AuthKey, msg_key: &[u8; 16], side: Side) -> ([u8; 32], [u8; 32]) {
+    let x = side.x();
+
+    // sha1_a = SHA1 (msg_key + substr (auth_key, x, 32));
+    let sha1_a = sha1!(msg_key, &auth_key.data[x..x + 32]);
+
+    // sha1_b = SHA1 (substr (auth_key, 32+x, 16) + msg_key + substr (auth_key, 48+x, 16));
+    let sha1_b = sha1!(
+        &auth_key.data[32 + x..32 + x + 16],
+        msg_key,
+        &auth_key.data[48 + x..48 + x + 16]
+    );
+
+    // sha1_c = SHA1 (substr (auth_key, 64+x, 32) + msg_key);
+    let sha1_c = sha1!(&auth_key.data[64 + x..64 + x + 32], msg_key);
+
+    // sha1_d = SHA1 (msg_key + substr (auth_key, 96+x, 32));
+    let sha1_d = sha1!(msg_key, &auth_key.data[96 + x..96 + x + 32]);
+
+    // aes_key = substr (sha1_a, 0, 8) + substr (sha1_b, 8, 12) + substr (sha1_c, 4, 12);
+    let aes_key = {
+        let mut buffer = [0; 32];
+        buffer[0..8].copy_from_slice(&sha1_a[0..8]);
+        buffer[8..8 + 12].copy_from_slice(&sha1_b[8..8 + 12]);
+        buffer[20..20 + 12].copy_from_slice(&sha1_c[4..4 + 12]);
+        buffer
+    };
+
+    // aes_iv = substr (sha1_a, 8, 12) + substr (sha1_b, 0, 8) + substr (sha1_c, 16, 4) + substr (sha1_d, 0, 8);
+    let aes_iv = {
+        let mut buffer = [0; 32];
+        buffer[0..12].copy_from_slice(&sha1_a[8..8 + 12]);
+        buffer[12..12 + 8].copy_from_slice(&sha1_b[0..8]);
+        buffer[20..20 + 4].copy_from_slice(&sha1_c[16..16 + 4]);
+        buffer[24..24 + 8].copy_from_slice(&sha1_d[0..8]);
+        buffer
+    };
+
+    (aes_key, aes_iv)
+}
+
+// Inner body of `encrypt_data_v1`, separated for testing purposes.
+fn do_encrypt_data_v1(plaintext: &[u8], auth_key: &AuthKey, random_padding: &[u8; 16]) -> Vec<u8> {
+    // Encryption is done by the client
+    let side = Side::Client;
+
+    // msg_key = substr (SHA1 (plaintext), 4, 16);
+    let msg_key = {
+        let sha = sha1!(plaintext);
+        let mut buffer = [0; 16];
+        buffer.copy_from_slice(&sha[4..4 + 16]);
+        buffer
+    };
+
+    // "[...] random bytes are added to make the total length divisible by 16"
+    let padding_len = (16 - (plaintext.len() % 16)) % 16;
+    let mut buffer = Vec::with_capacity(8 + 16 + plaintext.len() + padding_len);
+    buffer.extend(&auth_key.key_id);
+    buffer.extend(&msg_key);
+    buffer.extend(plaintext);
+    buffer.extend(&random_padding[..padding_len]);
+
+    let (key, iv) = calc_key_v1(auth_key, &msg_key, side);
+    aes::ige_encrypt(&mut buffer[24..], &key, &iv);
+    buffer
+}
+
+/// This function implements the [MTProto 1.0 algorithm] for encrypting data.
+///
+/// Only the inner message of `auth.bindTempAuthKey` should be encrypted this way,
+/// everything else must use `encrypt_data_v2`.
+///
+/// [MTProto 1.0 algorithm]: https://core.telegram.org/mtproto_v1
+pub fn encrypt_data_v1(plaintext: &[u8], auth_key: &AuthKey) -> Vec<u8> {
+    let random_padding = {
+        let mut rnd = [0; 16];
+        getrandom(&mut rnd).expect("failed to generate a secure padding");
+        rnd
+    };
+
+    do_encrypt_data_v1(plaintext, auth_key, &random_padding)
+}
+
 /// Generate the AES key and initialization vector from the server nonce
 /// and the new client nonce. This is done after the DH exchange.
 pub fn generate_key_data_from_nonce(
@@ -320,10 +432,28 @@ mod tests {
             36, 61, 86, 62, 161, 128, 210, 24, 238, 117, 124, 154,
         ];
 
-        do_encrypt_data_v2(&mut buffer, &auth_key, &random_padding);
+        do_encrypt_data_v2(&mut buffer, &auth_key, &random_padding, Side::Client);
         assert_eq!(&buffer[..], expected);
     }
 
+    #[test]
+    fn encrypt_client_data_v1() {
+        let plaintext = b"Hello, world! This data should remain secure!";
+        let auth_key = get_test_auth_key();
+        let random_padding = [0; 16];
+        let expected = vec![
+            50, 209, 88, 110, 164, 87, 223, 200, 200, 130, 39, 170, 72, 144, 14, 82, 149, 202, 203,
+            166, 167, 177, 152, 142, 94, 205, 2, 62, 218, 159, 151, 93, 42, 140, 133, 195, 37, 141,
+            127, 222, 221, 235, 127, 237, 97, 83, 16, 66, 108, 78, 65, 76, 40, 0, 172, 28, 206,
+            194, 236, 25, 117, 229, 123, 165, 74, 184, 223, 132, 148, 113, 164, 144,
+        ];
+
+        assert_eq!(
+            do_encrypt_data_v1(plaintext, &auth_key, &random_padding),
+            expected
+        );
+    }
+
     #[test]
     fn decrypt_server_data_v2() {
         let ciphertext = vec![
@@ -366,6 +496,26 @@ mod tests {
         assert_eq!(decrypt_data_v2(&ciphertext, &auth_key).unwrap(), expected);
     }
 
+    #[test]
+    fn server_data_v2_roundtrip() {
+        let plaintext = b"Hello, world! This data should remain secure!";
+        let auth_key = get_test_auth_key();
+
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        buffer.extend(plaintext);
+        encrypt_data_v2(&mut buffer, &auth_key);
+        let decrypted = decrypt_client_data_v2(&buffer[..], &auth_key).unwrap();
+        assert_eq!(&decrypted[..plaintext.len()], plaintext);
+        assert!(decrypt_data_v2(&buffer[..], &auth_key).is_err());
+
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        buffer.extend(plaintext);
+        encrypt_server_data_v2(&mut buffer, &auth_key);
+        let decrypted = decrypt_data_v2(&buffer[..], &auth_key).unwrap();
+        assert_eq!(&decrypted[..plaintext.len()], plaintext);
+        assert!(decrypt_client_data_v2(&buffer[..], &auth_key).is_err());
+    }
+
     #[test]
     fn key_from_nonce() {
         let server_nonce = {
@@ -7,9 +7,13 @@
 // except according to those terms.
 use num_bigint::BigUint;
 
-use crate::{aes::ige_encrypt, sha256};
+use crate::{
+    aes::{ige_decrypt, ige_encrypt},
+    sha1, sha256,
+};
 
 /// RSA key.
+#[derive(Clone)]
 pub struct Key {
     n: BigUint,
     e: BigUint,
@@ -22,6 +26,51 @@ impl Key {
             e: BigUint::parse_bytes(e.as_bytes(), 10)?,
         })
     }
+
+    /// The fingerprint of the key, which is how Telegram refers to it.
+    ///
+    /// It's the lower 64 bits of the SHA1 of the serialized `rsa_public_key n:string e:string`.
+    pub fn fingerprint(&self) -> i64 {
+        let mut serialized = Vec::new();
+        serialize_bytes(&self.n.to_bytes_be(), &mut serialized);
+        serialize_bytes(&self.e.to_bytes_be(), &mut serialized);
+
+        let sha = sha1!(&serialized);
+        i64::from_le_bytes(sha[12..20].try_into().unwrap())
+    }
+}
+
+/// Private RSA key, such as the one held by a server.
+pub struct PrivateKey {
+    key: Key,
+    d: BigUint,
+}
+
+impl PrivateKey {
+    pub fn new(n: &str, e: &str, d: &str) -> Option<Self> {
+        Some(Self {
+            key: Key::new(n, e)?,
+            d: BigUint::parse_bytes(d.as_bytes(), 10)?,
+        })
+    }
+
+    /// The public part of the key.
+    pub fn public_key(&self) -> &Key {
+        &self.key
+    }
+}
+
+/// Serialize the data as a TL `bytes` value.
+fn serialize_bytes(data: &[u8], buffer: &mut Vec<u8>) {
+    let start = buffer.len();
+    if data.len() <= 253 {
+        buffer.push(data.len() as u8);
+    } else {
+        buffer.push(254);
+        buffer.extend(&(data.len() as u32).to_le_bytes()[..3]);
+    }
+    buffer.extend(data);
+    buffer.resize(start + (buffer.len() - start).div_ceil(4) * 4, 0);
 }
 
 /// Increment data by 1 when interpreted as a big-endian big int.
@@ -114,6 +163,48 @@ pub fn encrypt_hashed(data: &[u8], key: &Key, random_bytes: &[u8; 224]) -> Vec<u
     block
 }
 
+/// Decrypt the data encrypted by `encrypt_hashed`, as the owner of the private key would.
+///
+/// The data is returned along with its random padding, 192 bytes in total. `None` is returned
+/// if the ciphertext is malformed or its hash does not match.
+pub fn decrypt_hashed(ciphertext: &[u8], key: &PrivateKey) -> Option<Vec<u8>> {
+    if ciphertext.len() != 256 {
+        return None;
+    }
+
+    // key_aes_encrypted := RSA_DECRYPT(encrypted_data, server_privkey);
+    let encrypted = BigUint::from_bytes_be(ciphertext);
+    if encrypted >= key.key.n {
+        return None;
+    }
+    let key_aes_encrypted = {
+        let payload = encrypted.modpow(&key.d, &key.key.n).to_bytes_be();
+        let mut buffer = vec![0; 256 - payload.len()];
+        buffer.extend(payload);
+        buffer
+    };
+
+    // temp_key := temp_key_xor XOR SHA256(aes_encrypted);
+    let (temp_key_xor, aes_encrypted) = key_aes_encrypted.split_at(32);
+    let mut temp_key: [u8; 32] = temp_key_xor.try_into().unwrap();
+    temp_key
+        .iter_mut()
+        .zip(sha256!(aes_encrypted))
+        .for_each(|(a, b)| *a ^= b);
+
+    // data_with_hash := AES256_IGE_DECRYPT(aes_encrypted, temp_key, 0);
+    let data_with_hash = ige_decrypt(aes_encrypted, &temp_key, &[0u8; 32]);
+
+    // data_with_hash := BYTE_REVERSE(data_with_padding) + SHA256(temp_key + data_with_padding);
+    let (data_pad_reversed, hash) = data_with_hash.split_at(192);
+    let data_with_padding = data_pad_reversed.iter().copied().rev().collect::<Vec<u8>>();
+    if sha256!(&temp_key, &data_with_padding) != hash {
+        return None;
+    }
+
+    Some(data_with_padding)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,4 +223,26 @@ mod tests {
             hex::from_hex("b610642a828b4a61fe32931815cae318d311660580f1e0df768f3140f4d37dfcfcac0c2870318de4ff2d2e0e9669bcfdc0bad06cadb1b59d9726b427368a9c7b4fc0d5e7b2e99fc571968705c03acf5341fd7021bef653fa77b3776ae430e366fc46d232459ebe128b08d80e049ae579a48b56ca93b520709468587c81af96666046e9ea85091d729e921e8d8a36f57b27644052dae7387c7f4131701d59cda75251dac66c94276280ef950d3c44c21e5a2454f7da7a6818cf23ae9c490b72b2170d7cbc24f8a93db739d76f2d241c78b80123faaff3e664f074d6375d794dbf2800a0b5bb48d54eceafedfb355bfbebd287d9023264e3b53627888250787a9e")
         );
     }
+
+    #[test]
+    fn test_rsa_fingerprint() {
+        let key = Key::new("25342889448840415564971689590713473206898847759084779052582026594546022463853940585885215951168491965708222649399180603818074200620463776135424884632162512403163793083921641631564740959529419359595852941166848940585952337613333022396096584117954892216031229237302943701877588456738335398602461675225081791820393153757504952636234951323237820036543581047826906120927972487366805292115792231423684261262330394324750785450942589751755390156647751460719351439969059949569615302809050721500330239005077889855323917509948255722081644689442127297605422579707142646660768825302832201908302295573257427896031830742328565032949", "65537").unwrap();
+        assert_eq!(key.fingerprint(), -5595554452916591101);
+    }
+
+    #[test]
+    fn test_rsa_decryption() {
+        let key = PrivateKey::new("22997499762210375194026911006269358459503476558235451408434059334425240777706510206121042045076351105107455547785790870927158476238210398157597310205809855464695209546020090746257814980307746278801152428957369337480772263401616154792347948062172169257788634519145866829107183442544947579043138913342370244684933367079072134332975172472409879890038976559507302116972346823453904000237283714758400508534375583996925946826208923727192463056950132121147447787360388150930002469742843738099367676950970321320192610976768905486872839920093679320266130337621240310852123926387405872969536323198377846877501498820064483793669", "65537", "1543471667975072635166812152846113999483132301469378107328641988227557281546944247396484481060596767258421093839141265937914560595293779640450306985981272399735445308576367077336695289544587362975218105173703236811406942651815137355297655515990421997961202205356715981015730753193370949637933480481810511943563766437547239621644652991109777672169398210965189534659951112079971285454316824593856566034039350640781510355118208625177254056583624550311629509077385484098021430222552536955318666919820021106373440558179720633670512059913422239456734875036325852970165166119011614759090836528993462334589692891156806370961").unwrap();
+        let data = hex::from_hex("955ff5a9081a8e635f5743de9b00000004453dc27100000004622f1fcb000000f7a81627bbf511fa4afef71e94a0937474586c1add9198dda81a5df8393871c8293623c5fb968894af1be7dfe9c7be813f9307789242fd0cb0c16a5cb39a8d3e");
+        let random_bytes = [0x5a; 224];
+
+        let ciphertext = encrypt_hashed(&data, key.public_key(), &random_bytes);
+        let plaintext = decrypt_hashed(&ciphertext, &key).unwrap();
+        assert_eq!(&plaintext[..data.len()], &data[..]);
+        assert_eq!(&plaintext[data.len()..], &random_bytes[..192 - data.len()]);
+
+        let mut tampered = ciphertext.clone();
+        tampered[255] ^= 1;
+        assert_eq!(decrypt_hashed(&tampered, &key), None);
+    }
 }
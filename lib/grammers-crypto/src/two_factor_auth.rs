@@ -5,6 +5,7 @@
 // <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
+use getrandom::getrandom;
 use glass_pumpkin::safe_prime;
 use hmac::Hmac;
 use num_bigint::{BigInt, BigUint, Sign};
@@ -86,6 +87,41 @@ pub fn calculate_2fa(
     (m1, g_a)
 }
 
+/// Extend the `salt1` from the `new_algo` sent by Telegram with 32 random bytes, as the
+/// client is required to before using it to set a new password.
+pub fn extend_salt1(salt1: &[u8]) -> Vec<u8> {
+    let mut new_salt1 = vec![0; salt1.len() + 32];
+    new_salt1[..salt1.len()].copy_from_slice(salt1);
+    getrandom(&mut new_salt1[salt1.len()..]).expect("failed to generate a secure salt");
+    new_salt1
+}
+
+/// Prepare a new password for sending to telegram when setting it.
+/// The method returns the `new_password_hash` that should be sent to Telegram
+/// (without the raw password), to be set along with the `new_algo` it was calculated with.
+///
+/// The `salt1` must have been extended with [`extend_salt1`] first.
+///
+/// The algorithm is described in <https://core.telegram.org/api/srp#setting-a-new-2fa-password>.
+pub fn calculate_password_hash(
+    salt1: &[u8],
+    salt2: &[u8],
+    p: &[u8],
+    g: &i32,
+    password: impl AsRef<[u8]>,
+) -> [u8; 256] {
+    let big_p = BigInt::from_bytes_be(Sign::Plus, p);
+    let big_g = BigInt::from(*g as u32);
+
+    // x := PH2(password, salt1, salt2)
+    let x = ph2(&password, salt1, salt2);
+    let x = BigInt::from_bytes_be(Sign::Plus, &x);
+
+    // v := pow(g, x) mod p
+    let big_v = big_g.modpow(&x, &big_p);
+    pad_to_256(&big_v.to_bytes_be().1)
+}
+
 /// Validation for parameters required for two-factor authentication
 pub fn check_p_and_g(p: &[u8], g: &i32) -> bool {
     if !check_p_len(p) {
@@ -302,6 +338,96 @@ mod tests {
         assert_eq!(expected_g_a, g_a);
     }
 
+    #[test]
+    fn check_password_hash() {
+        let salt1 = vec![
+            0x5f, 0x48, 0x3c, 0x38, 0xbd, 0x9, 0x86, 0xe7, 0xcd, 0xc9, 0x5a, 0xe1, 0x38, 0xef,
+            0x4f, 0x49, 0xb9, 0x51, 0xc1, 0xf8, 0x1c, 0x71, 0x3f, 0xec, 0xde, 0xf3, 0xaf, 0x69,
+            0x2c, 0xec, 0x4b, 0x47, 0x16, 0xac, 0x9b, 0x77, 0xa, 0x19, 0x5e, 0xbe,
+        ];
+        let salt2 = vec![
+            0xb6, 0x16, 0xfc, 0x6b, 0xbe, 0xdf, 0x51, 0x11, 0x19, 0xc5, 0xed, 0x34, 0x62, 0x95,
+            0x27, 0xf1,
+        ];
+        let g = 3;
+        let p = vec![
+            0xc7, 0x1c, 0xae, 0xb9, 0xc6, 0xb1, 0xc9, 0x4, 0x8e, 0x6c, 0x52, 0x2f, 0x70, 0xf1,
+            0x3f, 0x73, 0x98, 0xd, 0x40, 0x23, 0x8e, 0x3e, 0x21, 0xc1, 0x49, 0x34, 0xd0, 0x37,
+            0x56, 0x3d, 0x93, 0xf, 0x48, 0x19, 0x8a, 0xa, 0xa7, 0xc1, 0x40, 0x58, 0x22, 0x94, 0x93,
+            0xd2, 0x25, 0x30, 0xf4, 0xdb, 0xfa, 0x33, 0x6f, 0x6e, 0xa, 0xc9, 0x25, 0x13, 0x95,
+            0x43, 0xae, 0xd4, 0x4c, 0xce, 0x7c, 0x37, 0x20, 0xfd, 0x51, 0xf6, 0x94, 0x58, 0x70,
+            0x5a, 0xc6, 0x8c, 0xd4, 0xfe, 0x6b, 0x6b, 0x13, 0xab, 0xdc, 0x97, 0x46, 0x51, 0x29,
+            0x69, 0x32, 0x84, 0x54, 0xf1, 0x8f, 0xaf, 0x8c, 0x59, 0x5f, 0x64, 0x24, 0x77, 0xfe,
+            0x96, 0xbb, 0x2a, 0x94, 0x1d, 0x5b, 0xcd, 0x1d, 0x4a, 0xc8, 0xcc, 0x49, 0x88, 0x7, 0x8,
+            0xfa, 0x9b, 0x37, 0x8e, 0x3c, 0x4f, 0x3a, 0x90, 0x60, 0xbe, 0xe6, 0x7c, 0xf9, 0xa4,
+            0xa4, 0xa6, 0x95, 0x81, 0x10, 0x51, 0x90, 0x7e, 0x16, 0x27, 0x53, 0xb5, 0x6b, 0xf,
+            0x6b, 0x41, 0xd, 0xba, 0x74, 0xd8, 0xa8, 0x4b, 0x2a, 0x14, 0xb3, 0x14, 0x4e, 0xe, 0xf1,
+            0x28, 0x47, 0x54, 0xfd, 0x17, 0xed, 0x95, 0xd, 0x59, 0x65, 0xb4, 0xb9, 0xdd, 0x46,
+            0x58, 0x2d, 0xb1, 0x17, 0x8d, 0x16, 0x9c, 0x6b, 0xc4, 0x65, 0xb0, 0xd6, 0xff, 0x9c,
+            0xa3, 0x92, 0x8f, 0xef, 0x5b, 0x9a, 0xe4, 0xe4, 0x18, 0xfc, 0x15, 0xe8, 0x3e, 0xbe,
+            0xa0, 0xf8, 0x7f, 0xa9, 0xff, 0x5e, 0xed, 0x70, 0x5, 0xd, 0xed, 0x28, 0x49, 0xf4, 0x7b,
+            0xf9, 0x59, 0xd9, 0x56, 0x85, 0xc, 0xe9, 0x29, 0x85, 0x1f, 0xd, 0x81, 0x15, 0xf6, 0x35,
+            0xb1, 0x5, 0xee, 0x2e, 0x4e, 0x15, 0xd0, 0x4b, 0x24, 0x54, 0xbf, 0x6f, 0x4f, 0xad,
+            0xf0, 0x34, 0xb1, 0x4, 0x3, 0x11, 0x9c, 0xd8, 0xe3, 0xb9, 0x2f, 0xcc, 0x5b,
+        ];
+        let password = vec![50, 51, 52, 53, 54, 55];
+
+        let v = calculate_password_hash(&salt1, &salt2, &p, &g, &password);
+
+        // Same as Telethon's `password.compute_digest` with the above parameters.
+        let expected_v = vec![
+            92, 106, 112, 48, 68, 164, 76, 34, 186, 190, 95, 87, 142, 0, 186, 220, 186, 46, 52,
+            237, 188, 117, 255, 22, 25, 118, 201, 189, 22, 74, 35, 27, 166, 248, 66, 99, 58, 122,
+            166, 190, 116, 46, 210, 166, 122, 233, 153, 207, 164, 255, 155, 191, 155, 176, 130, 94,
+            196, 95, 204, 76, 135, 84, 75, 1, 214, 111, 230, 127, 200, 224, 127, 22, 188, 59, 246,
+            60, 147, 247, 246, 209, 147, 20, 126, 5, 103, 218, 232, 226, 250, 127, 37, 227, 53,
+            215, 210, 25, 145, 134, 73, 109, 245, 84, 144, 195, 113, 55, 60, 49, 32, 85, 54, 135,
+            150, 163, 166, 73, 195, 155, 102, 7, 90, 226, 213, 127, 85, 150, 65, 161, 200, 160,
+            203, 242, 21, 236, 2, 115, 158, 193, 110, 158, 176, 156, 124, 145, 188, 137, 219, 2,
+            127, 90, 191, 197, 90, 245, 13, 187, 243, 238, 172, 236, 210, 194, 72, 150, 101, 65,
+            204, 117, 222, 11, 6, 62, 156, 67, 122, 189, 226, 244, 33, 46, 44, 114, 130, 199, 195,
+            44, 44, 121, 172, 142, 199, 42, 183, 48, 17, 224, 231, 127, 18, 20, 34, 139, 230, 247,
+            44, 157, 244, 7, 118, 237, 155, 107, 18, 21, 170, 63, 251, 231, 21, 22, 143, 247, 164,
+            185, 216, 65, 226, 160, 239, 70, 71, 231, 21, 56, 9, 119, 110, 224, 143, 216, 130, 214,
+            78, 62, 86, 242, 11, 199, 137, 35, 37, 46, 34, 128, 77, 191,
+        ];
+
+        assert_eq!(expected_v, v);
+
+        // Telegram stores `v` and verifies logins against it, which works only if `v` matches
+        // the proof that `calculate_2fa` builds from the same password.
+        let big_p = BigInt::from_bytes_be(Sign::Plus, &p);
+        let big_v = BigInt::from_bytes_be(Sign::Plus, &v);
+        let g_for_hash = pad_to_256(&[g as u8]);
+        let k = BigInt::from_bytes_be(Sign::Plus, &h!(&p, &g_for_hash));
+        let b = BigInt::from(0x1234_5678_9abc_def0u64);
+
+        // g_b := (k * v + pow(g, b)) mod p
+        let g_b = (k * &big_v + BigInt::from(g).modpow(&b, &big_p)) % &big_p;
+        let g_b = pad_to_256(&g_b.to_bytes_be().1);
+
+        let (m1, g_a) = calculate_2fa(&salt1, &salt2, &p, &g, g_b.to_vec(), vec![7; 256], password);
+
+        // s_b := pow(g_a * pow(v, u), b) mod p
+        let u = BigInt::from_bytes_be(Sign::Plus, &h!(&g_a, &g_b));
+        let big_g_a = BigInt::from_bytes_be(Sign::Plus, &g_a);
+        let s_b = (big_g_a * big_v.modpow(&u, &big_p)).modpow(&b, &big_p);
+        let k_b = h!(&pad_to_256(&s_b.to_bytes_be().1));
+
+        let p_xor_g = xor(&h!(&p), &h!(&g_for_hash));
+        let expected_m1 = h!(&p_xor_g, &h!(&salt1), &h!(&salt2), &g_a, &g_b, &k_b);
+
+        assert_eq!(expected_m1, m1);
+    }
+
+    #[test]
+    fn check_extend_salt1() {
+        let salt1 = extend_salt1(&[1, 2, 3]);
+        assert_eq!(salt1.len(), 35);
+        assert_eq!(&salt1[..3], &[1, 2, 3]);
+        assert_ne!(salt1, extend_salt1(&[1, 2, 3]));
+    }
+
     #[test]
     fn test_check_p_and_g() {
         // Not prime
@@ -0,0 +1,374 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The server side of the [authorization key generation].
+//!
+//! [authorization key generation]: https://core.telegram.org/mtproto/auth_key
+use getrandom::getrandom;
+use grammers_crypto::{rsa, AuthKey};
+use grammers_tl_types::{self as tl, Cursor, Deserializable, Identifiable, Serializable};
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The safe prime used in the Diffie-Hellman exchange, which is the same one Telegram uses.
+const DH_PRIME: &str = "c71caeb9c6b1c9048e6c522f70f13f73980d40238e3e21c14934d037563d930f48198a0aa7c14058229493d22530f4dbfa336f6e0ac925139543aed44cce7c3720fd51f69458705ac68cd4fe6b6b13abdc9746512969328454f18faf8c595f642477fe96bb2a941d5bcd1d4ac8cc49880708fa9b378e3c4f3a9060bee67cf9a4a4a695811051907e162753b56b0f6b410dba74d8a84b2a14b3144e0ef1284754fd17ed950d5965b4b9dd46582db1178d169c6bc465b0d6ff9ca3928fef5b9ae4e418fc15e83ebea0f87fa9ff5eed70050ded2849f47bf959d956850ce929851f0d8115f635b105ee2e4e15d04b2454bf6f4fadf034b10403119cd8e3b92fcc5b";
+
+/// The generator used along `DH_PRIME`. Clients expect `DH_PRIME mod 7` to be 3, 5 or 6 for it.
+const G: u32 = 7;
+
+/// The factors of the `pq` that clients must factorize as a proof of work.
+const P: u64 = 2147483647;
+const Q: u64 = 4294967291;
+
+/// Represents an error that prevented an authorization key from being generated.
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// The request could not be deserialized.
+    InvalidRequest(tl::deserialize::Error),
+    /// The request is not valid at this point of the generation.
+    UnexpectedRequest { constructor_id: u32 },
+    /// The request does not match what the server sent before, or its data is corrupted.
+    InvalidData { reason: &'static str },
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidRequest(error) => write!(f, "invalid request: {error}"),
+            Self::UnexpectedRequest { constructor_id } => {
+                write!(f, "unexpected request: {constructor_id:08x}")
+            }
+            Self::InvalidData { reason } => write!(f, "invalid data: {reason}"),
+        }
+    }
+}
+
+impl From<tl::deserialize::Error> for Error {
+    fn from(error: tl::deserialize::Error) -> Self {
+        Self::InvalidRequest(error)
+    }
+}
+
+/// The state of the authorization key generation on a connection.
+#[derive(Default)]
+pub(crate) enum Handshake {
+    /// No generation is taking place.
+    #[default]
+    Idle,
+    /// `resPQ` was sent.
+    SentPq {
+        nonce: [u8; 16],
+        server_nonce: [u8; 16],
+    },
+    /// `server_DH_params_ok` was sent.
+    SentDhParams {
+        nonce: [u8; 16],
+        server_nonce: [u8; 16],
+        new_nonce: [u8; 32],
+        a: BigUint,
+    },
+}
+
+/// The result of a successful generation.
+pub(crate) struct Finished {
+    pub auth_key: AuthKey,
+    pub first_salt: i64,
+}
+
+impl Handshake {
+    /// Process a plain request, and return the response to send back.
+    ///
+    /// Once the last step succeeds, the new authorization key is returned as well. Starting over
+    /// is always possible, as clients do when they retry after an error.
+    pub fn process(
+        &mut self,
+        key: &rsa::PrivateKey,
+        request: &[u8],
+    ) -> Result<(Vec<u8>, Option<Finished>), Error> {
+        let mut cursor = Cursor::from_slice(request);
+        let constructor_id = u32::deserialize(&mut cursor)?;
+        let state = std::mem::take(self);
+
+        match (constructor_id, state) {
+            (tl::functions::ReqPqMulti::CONSTRUCTOR_ID, _) => {
+                let request = tl::functions::ReqPqMulti::deserialize(&mut cursor)?;
+                Ok((self.res_pq(key, request.nonce), None))
+            }
+            (tl::functions::ReqPq::CONSTRUCTOR_ID, _) => {
+                let request = tl::functions::ReqPq::deserialize(&mut cursor)?;
+                Ok((self.res_pq(key, request.nonce), None))
+            }
+            (
+                tl::functions::ReqDhParams::CONSTRUCTOR_ID,
+                Handshake::SentPq {
+                    nonce,
+                    server_nonce,
+                },
+            ) => {
+                let request = tl::functions::ReqDhParams::deserialize(&mut cursor)?;
+                check(request.nonce == nonce, "nonce")?;
+                check(request.server_nonce == server_nonce, "server nonce")?;
+                check(request.p == trimmed_bytes(P), "p")?;
+                check(request.q == trimmed_bytes(Q), "q")?;
+                check(
+                    request.public_key_fingerprint == key.public_key().fingerprint(),
+                    "fingerprint",
+                )?;
+
+                let data = rsa::decrypt_hashed(&request.encrypted_data, key).ok_or(
+                    Error::InvalidData {
+                        reason: "encrypted data",
+                    },
+                )?;
+                let (inner_nonce, inner_server_nonce, new_nonce) =
+                    match tl::enums::PQInnerData::from_bytes(&data)? {
+                        tl::enums::PQInnerData::Data(x) => (x.nonce, x.server_nonce, x.new_nonce),
+                        tl::enums::PQInnerData::Dc(x) => (x.nonce, x.server_nonce, x.new_nonce),
+                        tl::enums::PQInnerData::Temp(x) => (x.nonce, x.server_nonce, x.new_nonce),
+                        tl::enums::PQInnerData::TempDc(x) => (x.nonce, x.server_nonce, x.new_nonce),
+                    };
+                check(inner_nonce == nonce, "inner nonce")?;
+                check(inner_server_nonce == server_nonce, "inner server nonce")?;
+
+                Ok((self.server_dh_params(nonce, server_nonce, new_nonce), None))
+            }
+            (
+                tl::functions::SetClientDhParams::CONSTRUCTOR_ID,
+                Handshake::SentDhParams {
+                    nonce,
+                    server_nonce,
+                    new_nonce,
+                    a,
+                },
+            ) => {
+                let request = tl::functions::SetClientDhParams::deserialize(&mut cursor)?;
+                check(request.nonce == nonce, "nonce")?;
+                check(request.server_nonce == server_nonce, "server nonce")?;
+                check(request.encrypted_data.len() % 16 == 0, "padding")?;
+
+                // sha1 hash + client DH inner data + padding
+                let (key, iv) =
+                    grammers_crypto::generate_key_data_from_nonce(&server_nonce, &new_nonce);
+                let plaintext = grammers_crypto::decrypt_ige(&request.encrypted_data, &key, &iv);
+                check(plaintext.len() > 20, "client DH inner data")?;
+
+                let mut inner_cursor = Cursor::from_slice(&plaintext[20..]);
+                let tl::enums::ClientDhInnerData::Data(inner) =
+                    tl::enums::ClientDhInnerData::deserialize(&mut inner_cursor)?;
+                let hash = Sha1::digest(&plaintext[20..20 + inner_cursor.pos()]);
+                check(plaintext[..20] == hash[..], "client DH inner data hash")?;
+                check(inner.nonce == nonce, "inner nonce")?;
+                check(inner.server_nonce == server_nonce, "inner server nonce")?;
+
+                let dh_prime = dh_prime();
+                let g_b = BigUint::from_bytes_be(&inner.g_b);
+                let one = BigUint::from(1u32);
+                check(one < g_b && g_b < &dh_prime - &one, "g_b")?;
+
+                let auth_key = {
+                    let gab = g_b.modpow(&a, &dh_prime).to_bytes_be();
+                    let mut buffer = [0; 256];
+                    buffer[256 - gab.len()..].copy_from_slice(&gab);
+                    AuthKey::from_bytes(buffer)
+                };
+                let first_salt = {
+                    let mut buffer = [0; 8];
+                    buffer
+                        .iter_mut()
+                        .zip(&new_nonce[..8])
+                        .zip(&server_nonce[..8])
+                        .for_each(|((x, a), b)| *x = a ^ b);
+                    i64::from_le_bytes(buffer)
+                };
+
+                let response = tl::enums::SetClientDhParamsAnswer::DhGenOk(tl::types::DhGenOk {
+                    nonce,
+                    server_nonce,
+                    new_nonce_hash1: auth_key.calc_new_nonce_hash(&new_nonce, 1),
+                })
+                .to_bytes();
+
+                Ok((
+                    response,
+                    Some(Finished {
+                        auth_key,
+                        first_salt,
+                    }),
+                ))
+            }
+            (constructor_id, _) => Err(Error::UnexpectedRequest { constructor_id }),
+        }
+    }
+
+    fn res_pq(&mut self, key: &rsa::PrivateKey, nonce: [u8; 16]) -> Vec<u8> {
+        let server_nonce = random_bytes();
+        *self = Handshake::SentPq {
+            nonce,
+            server_nonce,
+        };
+
+        tl::enums::ResPq::Pq(tl::types::ResPq {
+            nonce,
+            server_nonce,
+            pq: (P * Q).to_be_bytes().to_vec(),
+            server_public_key_fingerprints: vec![key.public_key().fingerprint()],
+        })
+        .to_bytes()
+    }
+
+    fn server_dh_params(
+        &mut self,
+        nonce: [u8; 16],
+        server_nonce: [u8; 16],
+        new_nonce: [u8; 32],
+    ) -> Vec<u8> {
+        let dh_prime = dh_prime();
+        let g = BigUint::from(G);
+
+        // Clients refuse a `g_a` too close to either end of the range.
+        let safety_range = BigUint::from(1u32) << (2048 - 64);
+        let (a, g_a) = loop {
+            let a = BigUint::from_bytes_be(&random_bytes::<256>());
+            let g_a = g.modpow(&a, &dh_prime);
+            if safety_range < g_a && g_a < &dh_prime - &safety_range {
+                break (a, g_a);
+            }
+        };
+
+        let server_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is before epoch")
+            .as_secs() as i32;
+
+        let inner = tl::enums::ServerDhInnerData::Data(tl::types::ServerDhInnerData {
+            nonce,
+            server_nonce,
+            g: G as i32,
+            dh_prime: dh_prime.to_bytes_be(),
+            g_a: g_a.to_bytes_be(),
+            server_time,
+        })
+        .to_bytes();
+
+        // sha1 hash + server DH inner data + padding (added by `encrypt_ige`)
+        let mut answer = Sha1::digest(&inner).to_vec();
+        answer.extend(inner);
+        let (key, iv) = grammers_crypto::generate_key_data_from_nonce(&server_nonce, &new_nonce);
+
+        *self = Handshake::SentDhParams {
+            nonce,
+            server_nonce,
+            new_nonce,
+            a,
+        };
+
+        tl::enums::ServerDhParams::Ok(tl::types::ServerDhParamsOk {
+            nonce,
+            server_nonce,
+            encrypted_answer: grammers_crypto::encrypt_ige(&answer, &key, &iv),
+        })
+        .to_bytes()
+    }
+}
+
+fn check(condition: bool, reason: &'static str) -> Result<(), Error> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Error::InvalidData { reason })
+    }
+}
+
+fn dh_prime() -> BigUint {
+    BigUint::parse_bytes(DH_PRIME.as_bytes(), 16).unwrap()
+}
+
+/// Big-endian bytes of the number, without leading zeros, as clients send `p` and `q`.
+fn trimmed_bytes(n: u64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut buffer = [0; N];
+    getrandom(&mut buffer).expect("failed to generate secure data for auth key");
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RSA_D, RSA_E, RSA_N};
+    use grammers_mtproto::authentication;
+
+    fn rsa_key() -> rsa::PrivateKey {
+        let key = rsa::PrivateKey::new(RSA_N, RSA_E, RSA_D).unwrap();
+        authentication::trust_rsa_key(key.public_key().clone());
+        key
+    }
+
+    #[test]
+    fn client_generates_same_key() {
+        let key = rsa_key();
+        let mut handshake = Handshake::default();
+
+        let (request, data) = authentication::step1().unwrap();
+        let (response, finished) = handshake.process(&key, &request).unwrap();
+        assert!(finished.is_none());
+
+        let (request, data) = authentication::step2(data, &response).unwrap();
+        let (response, finished) = handshake.process(&key, &request).unwrap();
+        assert!(finished.is_none());
+
+        let (request, data) = authentication::step3(data, &response).unwrap();
+        let (response, finished) = handshake.process(&key, &request).unwrap();
+        let finished = finished.unwrap();
+
+        let client = authentication::create_key(data, &response).unwrap();
+        assert_eq!(client.auth_key, finished.auth_key.to_bytes());
+        assert_eq!(client.first_salt, finished.first_salt);
+        assert!(matches!(handshake, Handshake::Idle));
+    }
+
+    #[test]
+    fn temp_key_generation() {
+        let key = rsa_key();
+        let mut handshake = Handshake::default();
+
+        let (request, data) = authentication::step1().unwrap();
+        let (response, _) = handshake.process(&key, &request).unwrap();
+        let (request, data) = authentication::step2_temp(data, &response, 2, 3600).unwrap();
+        let (response, _) = handshake.process(&key, &request).unwrap();
+        let (request, data) = authentication::step3(data, &response).unwrap();
+        let (response, finished) = handshake.process(&key, &request).unwrap();
+
+        let client = authentication::create_key(data, &response).unwrap();
+        assert_eq!(client.auth_key, finished.unwrap().auth_key.to_bytes());
+    }
+
+    #[test]
+    fn out_of_order_request() {
+        let key = rsa_key();
+        let mut handshake = Handshake::default();
+
+        let (request, data) = authentication::step1().unwrap();
+        let (response, _) = handshake.process(&key, &request).unwrap();
+        let (request, _) = authentication::step2(data, &response).unwrap();
+        handshake.process(&key, &request).unwrap();
+
+        assert!(matches!(
+            handshake.process(&key, &request),
+            Err(Error::UnexpectedRequest { .. })
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The IO loop serving a single client connection.
+use crate::authentication::Handshake;
+use crate::server::Shared;
+use crate::session::Session;
+use grammers_crypto::DequeBuffer;
+use grammers_mtproto::transport::{self, Transport};
+use log::{info, warn};
+use std::io;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast;
+
+/// The transport-level error sent when the authorization key used by the client is not known,
+/// which is also what Telegram sends when the generation of a key fails.
+const UNKNOWN_AUTH_KEY: i32 = -404;
+
+/// What to send back to the client after processing one of its packets.
+enum Reply {
+    Packet(DequeBuffer<u8>),
+    TransportError(i32),
+}
+
+/// The state of a connection, which can hold at most one encrypted session at a time.
+struct Connection {
+    shared: Arc<Shared>,
+    handshake: Handshake,
+    session: Option<Session>,
+    updates: Option<broadcast::Receiver<Vec<u8>>>,
+}
+
+/// Serve a client over the stream until it disconnects.
+pub(crate) async fn serve<S: AsyncRead + AsyncWrite>(
+    shared: Arc<Shared>,
+    stream: S,
+) -> io::Result<()> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut read_buffer = Vec::new();
+
+    let (mut transport, raw_errors) = loop {
+        if let Some((transport, tag_len)) = detect_transport(&read_buffer) {
+            read_buffer.drain(..tag_len);
+            // The full transport has no tag, and sends transport errors as a bare negative length.
+            break (transport, tag_len == 0);
+        }
+        if reader.read_buf(&mut read_buffer).await? == 0 {
+            return Ok(());
+        }
+    };
+
+    let mut connection = Connection {
+        shared,
+        handshake: Handshake::default(),
+        session: None,
+        updates: None,
+    };
+
+    loop {
+        loop {
+            let offset = match transport.unpack(&mut read_buffer) {
+                Ok(offset) => offset,
+                Err(transport::Error::MissingBytes) => break,
+                Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+            };
+            let replies = connection.process(&read_buffer[offset.data_start..offset.data_end])?;
+            read_buffer.drain(..offset.next_offset);
+
+            for reply in replies {
+                let mut packet = match reply {
+                    Reply::Packet(packet) => packet,
+                    Reply::TransportError(code) if raw_errors => {
+                        writer.write_all(&code.to_le_bytes()).await?;
+                        continue;
+                    }
+                    Reply::TransportError(code) => {
+                        let mut packet = DequeBuffer::with_capacity(4, 8);
+                        packet.extend(code.to_le_bytes());
+                        packet
+                    }
+                };
+                transport.pack(&mut packet);
+                writer.write_all(&packet[..]).await?;
+            }
+        }
+
+        tokio::select! {
+            n = reader.read_buf(&mut read_buffer) => {
+                if n? == 0 {
+                    info!("client disconnected");
+                    return Ok(());
+                }
+            }
+            updates = next_updates(&mut connection.updates) => {
+                let session = connection.session.as_mut();
+                if let Some(mut packet) = session.and_then(|s| s.encrypt_updates(&updates)) {
+                    transport.pack(&mut packet);
+                    writer.write_all(&packet[..]).await?;
+                }
+            }
+        }
+    }
+}
+
+impl Connection {
+    /// Process a packet sent by the client, either plain or encrypted.
+    fn process(&mut self, packet: &[u8]) -> io::Result<Vec<Reply>> {
+        let auth_key_id = match packet.get(..8) {
+            Some(id) => i64::from_le_bytes(id.try_into().unwrap()),
+            None => return Err(invalid_data("packet too short")),
+        };
+
+        if auth_key_id == 0 {
+            return self.process_plain(packet);
+        }
+
+        if self.session.as_ref().map(Session::auth_key_id) != Some(auth_key_id) {
+            match self.shared.auth_key(auth_key_id) {
+                Some((auth_key, salt)) => {
+                    self.session = Some(Session::new(auth_key, salt));
+                    self.updates.get_or_insert_with(|| self.shared.subscribe());
+                }
+                None => {
+                    warn!("client used unknown auth key {auth_key_id}");
+                    return Ok(vec![Reply::TransportError(UNKNOWN_AUTH_KEY)]);
+                }
+            }
+        }
+
+        let session = self.session.as_mut().unwrap();
+        match session.process(&self.shared, packet) {
+            Ok(packets) => Ok(packets.into_iter().map(Reply::Packet).collect()),
+            Err(error) => Err(invalid_data(error)),
+        }
+    }
+
+    /// Process a plain message, which may only be used to generate an authorization key.
+    fn process_plain(&mut self, packet: &[u8]) -> io::Result<Vec<Reply>> {
+        // auth_key_id, msg_id and length preceding the body of the message.
+        let len = match packet.get(16..20) {
+            Some(len) => i32::from_le_bytes(len.try_into().unwrap()),
+            None => return Err(invalid_data("plain message too short")),
+        };
+        let body = usize::try_from(len)
+            .ok()
+            .and_then(|len| packet.get(20..20 + len))
+            .ok_or_else(|| invalid_data("bad plain message length"))?;
+
+        let (response, finished) = match self.handshake.process(&self.shared.rsa_key, body) {
+            Ok(result) => result,
+            Err(error) => {
+                warn!("failed to generate auth key: {error}");
+                return Ok(vec![Reply::TransportError(UNKNOWN_AUTH_KEY)]);
+            }
+        };
+        if let Some(finished) = finished {
+            info!("generated auth key {}", finished.auth_key.id());
+            self.shared
+                .add_auth_key(finished.auth_key, finished.first_salt);
+        }
+
+        // Plain responses still need a valid `msg_id`, which is 1 modulo 4.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is before epoch");
+        let msg_id = ((now.as_secs() as i64) << 32) | ((now.subsec_nanos() as i64) << 2) | 1;
+
+        let mut packet = DequeBuffer::with_capacity(20 + response.len(), 8);
+        packet.extend(0i64.to_le_bytes());
+        packet.extend(msg_id.to_le_bytes());
+        packet.extend((response.len() as i32).to_le_bytes());
+        packet.extend(response);
+        Ok(vec![Reply::Packet(packet)])
+    }
+}
+
+/// Determine the transport used by the client from the first bytes it sent, and return it along
+/// with the length of its tag, or `None` if more bytes are needed to tell.
+fn detect_transport(data: &[u8]) -> Option<(Box<dyn Transport + Send>, usize)> {
+    let (mut transport, tag_len): (Box<dyn Transport + Send>, _) = match data {
+        [0xef, ..] => (Box::new(transport::Abridged::new()), 1),
+        [0xee, 0xee, 0xee, 0xee, ..] => (Box::new(transport::Intermediate::new()), 4),
+        [0xdd, 0xdd, 0xdd, 0xdd, ..] => (Box::new(transport::PaddedIntermediate::new()), 4),
+        _ if data.len() < 4 => return None,
+        _ => (Box::new(transport::Full::new()), 0),
+    };
+
+    // The client already sent the tag, and the server must not send one back.
+    transport.obfuscated_tag();
+    Some((transport, tag_len))
+}
+
+/// Wait for the next updates to push to the client, which never happens until it has a session.
+async fn next_updates(updates: &mut Option<broadcast::Receiver<Vec<u8>>>) -> Vec<u8> {
+    if let Some(receiver) = updates {
+        loop {
+            match receiver.recv().await {
+                Ok(updates) => return updates,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("client fell behind and missed {n} updates")
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+    std::future::pending().await
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
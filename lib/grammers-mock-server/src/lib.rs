@@ -0,0 +1,54 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![deny(unsafe_code)]
+
+//! An in-process stand-in for Telegram's servers, so that clients using the
+//! [Mobile Transport Protocol] can be tested without network access.
+//!
+//! The [`MockServer`] generates [authorization keys] with its clients using a test RSA key,
+//! which it makes clients trust when created. It then keeps encrypted sessions with them,
+//! answers service messages such as pings on its own, and replies to remote calls with whatever
+//! the test scripted for them:
+//!
+//! ```
+//! use grammers_mock_server::{MockServer, RpcError};
+//! use grammers_tl_types as tl;
+//!
+//! let server = MockServer::new();
+//! server
+//!     .on(|_: tl::functions::help::GetNearestDc| {
+//!         Ok(tl::types::NearestDc {
+//!             country: "ES".into(),
+//!             this_dc: 2,
+//!             nearest_dc: 2,
+//!         }
+//!         .into())
+//!     })
+//!     .on(|_: tl::functions::auth::SendCode| Err(RpcError::new(400, "PHONE_NUMBER_INVALID")));
+//! ```
+//!
+//! Clients reach the server through in-memory pipes returned by [`MockServer::connect`], or
+//! through any other stream handed to [`MockServer::serve`]. Only the non-obfuscated transports
+//! are supported.
+//!
+//! [Mobile Transport Protocol]: https://core.telegram.org/mtproto
+//! [authorization keys]: https://core.telegram.org/mtproto/auth_key
+mod authentication;
+mod connection;
+mod server;
+mod session;
+
+pub use server::{MockServer, RpcError};
+
+// The RSA key with which clients encrypt their part of the authorization key generation.
+//
+// Its private part is public, so it must never be trusted outside of tests.
+const RSA_N: &str = "22997499762210375194026911006269358459503476558235451408434059334425240777706510206121042045076351105107455547785790870927158476238210398157597310205809855464695209546020090746257814980307746278801152428957369337480772263401616154792347948062172169257788634519145866829107183442544947579043138913342370244684933367079072134332975172472409879890038976559507302116972346823453904000237283714758400508534375583996925946826208923727192463056950132121147447787360388150930002469742843738099367676950970321320192610976768905486872839920093679320266130337621240310852123926387405872969536323198377846877501498820064483793669";
+const RSA_E: &str = "65537";
+const RSA_D: &str = "1543471667975072635166812152846113999483132301469378107328641988227557281546944247396484481060596767258421093839141265937914560595293779640450306985981272399735445308576367077336695289544587362975218105173703236811406942651815137355297655515990421997961202205356715981015730753193370949637933480481810511943563766437547239621644652991109777672169398210965189534659951112079971285454316824593856566034039350640781510355118208625177254056583624550311629509077385484098021430222552536955318666919820021106373440558179720633670512059913422239456734875036325852970165166119011614759090836528993462334589692891156806370961";
@@ -0,0 +1,555 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use crate::connection;
+use crate::{RSA_D, RSA_E, RSA_N};
+use getrandom::getrandom;
+use grammers_crypto::{rsa, AuthKey};
+use grammers_mtproto::authentication;
+use grammers_tl_types::{self as tl, Deserializable, Identifiable, RemoteCall, Serializable};
+use log::warn;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// The size of the in-memory pipes returned by [`MockServer::connect`].
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// How many updates may be pending to be pushed to a client before it starts missing them.
+const UPDATES_CAPACITY: usize = 1024;
+
+/// The identifier of the datacenter the server claims to be.
+const THIS_DC: i32 = 2;
+
+type Handler = Box<dyn FnMut(&[u8]) -> Result<Vec<u8>, RpcError> + Send>;
+
+/// An error to reply with instead of a result, the same as Telegram does when a request fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RpcError {
+    /// The error code, such as `400` or `420`.
+    pub code: i32,
+    /// The error message, such as `"PHONE_CODE_INVALID"` or `"FLOOD_WAIT_31"`.
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// The state shared by all connections to the same server.
+pub(crate) struct Shared {
+    pub(crate) rsa_key: rsa::PrivateKey,
+    handlers: Mutex<HashMap<u32, Handler>>,
+    auth_keys: Mutex<HashMap<i64, (AuthKey, i64)>>,
+    requests: Mutex<Vec<Vec<u8>>>,
+    updates: broadcast::Sender<Vec<u8>>,
+}
+
+/// An in-process stand-in for Telegram's servers.
+///
+/// Creating a server makes the current process trust its RSA key when generating authorization
+/// keys, so it must only be used in tests. Cloning the server is cheap, and all clones share the
+/// same state.
+///
+/// Out of the box, the server answers the requests made by clients when connecting, such as
+/// `help.getConfig` and `updates.getState`. Any other request fails with a `400` error until a
+/// handler is registered for it with [`MockServer::on`].
+#[derive(Clone)]
+pub struct MockServer {
+    shared: Arc<Shared>,
+}
+
+impl MockServer {
+    /// Create a new server, with no authorization keys and the default handlers.
+    pub fn new() -> Self {
+        let rsa_key = rsa::PrivateKey::new(RSA_N, RSA_E, RSA_D).unwrap();
+        authentication::trust_rsa_key(rsa_key.public_key().clone());
+
+        let server = Self {
+            shared: Arc::new(Shared {
+                rsa_key,
+                handlers: Mutex::new(HashMap::new()),
+                auth_keys: Mutex::new(HashMap::new()),
+                requests: Mutex::new(Vec::new()),
+                updates: broadcast::channel(UPDATES_CAPACITY).0,
+            }),
+        };
+
+        server
+            .on(|_: tl::functions::help::GetConfig| Ok(default_config().into()))
+            .on(|_: tl::functions::updates::GetState| {
+                Ok(tl::types::updates::State {
+                    pts: 1,
+                    qts: 1,
+                    date: now(),
+                    seq: 1,
+                    unread_count: 0,
+                }
+                .into())
+            })
+            .on(|_: tl::functions::updates::GetDifference| {
+                Ok(tl::types::updates::DifferenceEmpty {
+                    date: now(),
+                    seq: 1,
+                }
+                .into())
+            })
+            .on(|_: tl::functions::auth::BindTempAuthKey| Ok(true));
+
+        server
+    }
+
+    /// Reply to every request of type `R` with the result of the handler, replacing the previous
+    /// handler for it, if any.
+    ///
+    /// Requests are unwrapped from `invokeWithLayer`, `initConnection` and similar wrappers
+    /// before being handed to the handler. The handler may register other handlers, or push
+    /// updates, while it runs.
+    pub fn on<R, F>(&self, mut handler: F) -> &Self
+    where
+        R: RemoteCall + Deserializable + Identifiable,
+        R::Return: Serializable,
+        F: FnMut(R) -> Result<R::Return, RpcError> + Send + 'static,
+    {
+        let handler: Handler = Box::new(move |request| {
+            let request = R::from_bytes(&request[4..])
+                .map_err(|_| RpcError::new(400, "INPUT_REQUEST_INVALID"))?;
+            handler(request).map(|result| result.to_bytes())
+        });
+
+        self.shared
+            .handlers
+            .lock()
+            .unwrap()
+            .insert(R::CONSTRUCTOR_ID, handler);
+        self
+    }
+
+    /// Make the server know about an existing authorization key, as if it had been generated
+    /// with a client earlier. Clients can then connect with it directly.
+    pub fn add_auth_key(&self, auth_key: [u8; 256]) {
+        let mut salt = [0; 8];
+        getrandom(&mut salt).expect("failed to generate a secure salt");
+        self.shared
+            .add_auth_key(AuthKey::from_bytes(auth_key), i64::from_le_bytes(salt));
+    }
+
+    /// The requests of type `R` received so far, in the order they arrived.
+    ///
+    /// Every request is recorded, regardless of whether it had a handler or not.
+    pub fn received<R: Deserializable + Identifiable>(&self) -> Vec<R> {
+        self.shared
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|request| request[..4] == R::CONSTRUCTOR_ID.to_le_bytes())
+            .map(|request| R::from_bytes(&request[4..]).expect("recorded request was valid"))
+            .collect()
+    }
+
+    /// Push the updates to every client connected with an encrypted session.
+    ///
+    /// Clients connecting later will not receive them.
+    pub fn send_update<U: Into<tl::enums::Updates>>(&self, updates: U) {
+        // It's fine if there are no clients to receive the updates.
+        let _ = self.shared.updates.send(updates.into().to_bytes());
+    }
+
+    /// Open a new in-memory connection to the server, and return the client side of it.
+    ///
+    /// The connection is served in its own task, so this must be called from within the context
+    /// of a Tokio runtime.
+    pub fn connect(&self) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(PIPE_SIZE);
+        self.serve(server);
+        client
+    }
+
+    /// Serve a client connected through the given stream in a new task, until it disconnects.
+    ///
+    /// This must be called from within the context of a Tokio runtime.
+    pub fn serve<S>(&self, stream: S) -> JoinHandle<io::Result<()>>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        tokio::spawn(connection::serve(Arc::clone(&self.shared), stream))
+    }
+}
+
+impl Default for MockServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shared {
+    pub(crate) fn auth_key(&self, id: i64) -> Option<(AuthKey, i64)> {
+        self.auth_keys.lock().unwrap().get(&id).cloned()
+    }
+
+    pub(crate) fn add_auth_key(&self, auth_key: AuthKey, salt: i64) {
+        self.auth_keys
+            .lock()
+            .unwrap()
+            .insert(auth_key.id(), (auth_key, salt));
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Vec<u8>> {
+        self.updates.subscribe()
+    }
+
+    /// Run the handler for the query, returning the serialized result.
+    pub(crate) fn invoke(&self, query: Vec<u8>) -> Result<Vec<u8>, RpcError> {
+        let constructor_id = match query.get(..4) {
+            Some(id) => u32::from_le_bytes(id.try_into().unwrap()),
+            None => return Err(RpcError::new(400, "INPUT_METHOD_INVALID")),
+        };
+        self.requests.lock().unwrap().push(query.clone());
+
+        // The handler is taken out while it runs, so that it can use the server too.
+        let handler = self.handlers.lock().unwrap().remove(&constructor_id);
+        let Some(mut handler) = handler else {
+            warn!("no handler for {}", tl::name_for_id(constructor_id));
+            return Err(RpcError::new(
+                400,
+                format!("INPUT_METHOD_INVALID_{constructor_id}"),
+            ));
+        };
+
+        let result = handler(&query);
+        self.handlers
+            .lock()
+            .unwrap()
+            .entry(constructor_id)
+            .or_insert(handler);
+        result
+    }
+}
+
+/// The configuration sent in response to `help.getConfig`, which points every datacenter back
+/// to the local host.
+fn default_config() -> tl::types::Config {
+    let now = now();
+    tl::types::Config {
+        default_p2p_contacts: false,
+        preload_featured_stickers: false,
+        revoke_pm_inbox: true,
+        blocked_mode: false,
+        force_try_ipv6: false,
+        date: now,
+        expires: now + 3600,
+        test_mode: false,
+        this_dc: THIS_DC,
+        dc_options: (1..=5)
+            .map(|id| {
+                tl::types::DcOption {
+                    ipv6: false,
+                    media_only: false,
+                    tcpo_only: false,
+                    cdn: false,
+                    r#static: false,
+                    this_port_only: false,
+                    id,
+                    ip_address: "127.0.0.1".into(),
+                    port: 443,
+                    secret: None,
+                }
+                .into()
+            })
+            .collect(),
+        dc_txt_domain_name: "localhost".into(),
+        chat_size_max: 200,
+        megagroup_size_max: 200000,
+        forwarded_count_max: 100,
+        online_update_period_ms: 210000,
+        offline_blur_timeout_ms: 5000,
+        offline_idle_timeout_ms: 30000,
+        online_cloud_timeout_ms: 300000,
+        notify_cloud_delay_ms: 30000,
+        notify_default_delay_ms: 1500,
+        push_chat_period_ms: 60000,
+        push_chat_limit: 2,
+        edit_time_limit: 172800,
+        revoke_time_limit: i32::MAX,
+        revoke_pm_time_limit: i32::MAX,
+        rating_e_decay: 2419200,
+        stickers_recent_limit: 200,
+        channels_read_media_period: 604800,
+        tmp_sessions: None,
+        call_receive_timeout_ms: 20000,
+        call_ring_timeout_ms: 90000,
+        call_connect_timeout_ms: 30000,
+        call_packet_timeout_ms: 10000,
+        me_url_prefix: "https://t.me/".into(),
+        autoupdate_url_prefix: None,
+        gif_search_username: None,
+        venue_search_username: None,
+        img_search_username: None,
+        static_maps_provider: None,
+        caption_length_max: 1024,
+        message_length_max: 4096,
+        webfile_dc_id: 4,
+        suggested_lang_code: None,
+        lang_pack_version: None,
+        base_lang_pack_version: None,
+        reactions_default: None,
+        autologin_token: None,
+    }
+}
+
+fn now() -> i32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before epoch")
+        .as_secs() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grammers_mtproto::mtp;
+    use grammers_mtproto::transport::{self, Transport};
+    use grammers_mtsender::{
+        connect_via_connector, connect_via_connector_with_auth, Connector, Enqueuer,
+        InvocationError, NoReconnect, Sender, Stream, UpdatesLike,
+    };
+    use std::future::Future;
+
+    fn block_on(future: impl Future<Output = ()>) {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn connector(server: &MockServer) -> Arc<dyn Connector> {
+        let server = server.clone();
+        Arc::new(move |_| {
+            let stream = server.connect();
+            async move { Ok(Box::new(stream) as Box<dyn Stream>) }
+        })
+    }
+
+    /// Connect to the server, generating a new authorization key.
+    async fn connect<T: Transport>(
+        server: &MockServer,
+        transport: T,
+    ) -> (Sender<T, mtp::Encrypted>, Enqueuer) {
+        connect_via_connector(
+            transport,
+            "127.0.0.1:443".parse().unwrap(),
+            connector(server),
+            &NoReconnect,
+        )
+        .await
+        .unwrap()
+    }
+
+    fn nearest_dc() -> tl::enums::NearestDc {
+        tl::types::NearestDc {
+            country: "ES".into(),
+            this_dc: 2,
+            nearest_dc: 4,
+        }
+        .into()
+    }
+
+    #[test]
+    fn scripted_reply() {
+        block_on(async {
+            let server = MockServer::new();
+            server.on(|_: tl::functions::help::GetNearestDc| Ok(nearest_dc()));
+
+            let (mut sender, _enqueuer) = connect(&server, transport::Full::new()).await;
+            let response = sender
+                .invoke(&tl::functions::help::GetNearestDc {})
+                .await
+                .unwrap();
+
+            assert_eq!(
+                tl::enums::NearestDc::from_bytes(&response).unwrap(),
+                nearest_dc()
+            );
+            assert_eq!(
+                server.received::<tl::functions::help::GetNearestDc>().len(),
+                1
+            );
+        });
+    }
+
+    #[test]
+    fn scripted_error() {
+        block_on(async {
+            let server = MockServer::new();
+            server.on(|request: tl::functions::auth::ExportAuthorization| {
+                assert_eq!(request.dc_id, 9);
+                Err(RpcError::new(400, "DC_ID_INVALID"))
+            });
+
+            let (mut sender, _enqueuer) = connect(&server, transport::Abridged::new()).await;
+            let result = sender
+                .invoke(&tl::functions::auth::ExportAuthorization { dc_id: 9 })
+                .await;
+
+            match result {
+                Err(InvocationError::Rpc(error)) => {
+                    assert_eq!(error.code, 400);
+                    assert_eq!(error.name, "DC_ID_INVALID");
+                }
+                _ => panic!("unexpected result: {result:?}"),
+            }
+        });
+    }
+
+    #[test]
+    fn unscripted_request() {
+        block_on(async {
+            let server = MockServer::new();
+            let (mut sender, _enqueuer) = connect(&server, transport::Intermediate::new()).await;
+            let result = sender.invoke(&tl::functions::help::GetNearestDc {}).await;
+
+            assert!(matches!(
+                result,
+                Err(InvocationError::Rpc(error)) if error.name == "INPUT_METHOD_INVALID"
+            ));
+        });
+    }
+
+    #[test]
+    fn wrapped_request() {
+        block_on(async {
+            let server = MockServer::new();
+            let (mut sender, _enqueuer) =
+                connect(&server, transport::PaddedIntermediate::new()).await;
+            let response = sender
+                .invoke(&tl::functions::InvokeWithLayer {
+                    layer: tl::LAYER,
+                    query: tl::functions::InitConnection {
+                        api_id: 1,
+                        device_model: "".into(),
+                        system_version: "".into(),
+                        app_version: "".into(),
+                        system_lang_code: "".into(),
+                        lang_pack: "".into(),
+                        lang_code: "".into(),
+                        proxy: None,
+                        params: None,
+                        query: tl::functions::help::GetConfig {},
+                    },
+                })
+                .await
+                .unwrap();
+
+            let tl::enums::Config::Config(config) =
+                tl::enums::Config::from_bytes(&response).unwrap();
+            assert_eq!(config.this_dc, THIS_DC);
+            assert_eq!(server.received::<tl::functions::help::GetConfig>().len(), 1);
+        });
+    }
+
+    #[test]
+    fn service_messages() {
+        block_on(async {
+            let server = MockServer::new();
+            let (mut sender, _enqueuer) = connect(&server, transport::Full::new()).await;
+
+            let response = sender
+                .invoke(&tl::functions::Ping { ping_id: 42 })
+                .await
+                .unwrap();
+            let tl::enums::Pong::Pong(pong) = tl::enums::Pong::from_bytes(&response).unwrap();
+            assert_eq!(pong.ping_id, 42);
+
+            let response = sender
+                .invoke(&tl::functions::GetFutureSalts { num: 3 })
+                .await
+                .unwrap();
+            let tl::enums::FutureSalts::Salts(salts) =
+                tl::enums::FutureSalts::from_bytes(&response).unwrap();
+            assert_eq!(salts.salts.0.len(), 3);
+        });
+    }
+
+    #[test]
+    fn pushed_updates() {
+        block_on(async {
+            let server = MockServer::new();
+            let (mut sender, _enqueuer) = connect(&server, transport::Intermediate::new()).await;
+            sender
+                .invoke(&tl::functions::Ping { ping_id: 0 })
+                .await
+                .unwrap();
+
+            server.send_update(tl::enums::Updates::TooLong);
+            loop {
+                let updates = sender.step().await.unwrap();
+                if updates
+                    .iter()
+                    .any(|u| matches!(u, UpdatesLike::Updates(tl::enums::Updates::TooLong)))
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn existing_auth_key() {
+        block_on(async {
+            let server = MockServer::new();
+            server.on(|_: tl::functions::help::GetNearestDc| Ok(nearest_dc()));
+            server.add_auth_key([7; 256]);
+
+            let (mut sender, _enqueuer) = connect_via_connector_with_auth(
+                transport::Full::new(),
+                "127.0.0.1:443".parse().unwrap(),
+                [7; 256],
+                connector(&server),
+                &NoReconnect,
+            )
+            .await
+            .unwrap();
+
+            sender
+                .invoke(&tl::functions::help::GetNearestDc {})
+                .await
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn unknown_auth_key() {
+        block_on(async {
+            let server = MockServer::new();
+            let (mut sender, _enqueuer) = connect_via_connector_with_auth(
+                transport::Intermediate::new(),
+                "127.0.0.1:443".parse().unwrap(),
+                [7; 256],
+                connector(&server),
+                &NoReconnect,
+            )
+            .await
+            .unwrap();
+
+            assert!(sender
+                .invoke(&tl::functions::help::GetNearestDc {})
+                .await
+                .is_err());
+        });
+    }
+}
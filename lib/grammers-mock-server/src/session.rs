@@ -0,0 +1,359 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The server side of the [encrypted messages] exchanged once an authorization key exists.
+//!
+//! [encrypted messages]: https://core.telegram.org/mtproto/description#encrypted-message
+use crate::server::{RpcError, Shared};
+use flate2::read::GzDecoder;
+use getrandom::getrandom;
+use grammers_crypto::{decrypt_client_data_v2, encrypt_server_data_v2, AuthKey, DequeBuffer};
+use grammers_tl_types::{self as tl, Cursor, Deserializable, Identifiable, Serializable};
+use log::debug;
+use std::fmt;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// rpc_result#f35c6d01 req_msg_id:long result:Object = RpcResult;
+const RPC_RESULT_ID: u32 = 0xf35c6d01;
+
+// msg_container#73f1f8dc messages:vector<message> = MessageContainer;
+const MSG_CONTAINER_ID: u32 = 0x73f1f8dc;
+
+// gzip_packed#3072cfa1 packed_data:string = Object;
+const GZIP_PACKED_ID: u32 = 0x3072cfa1;
+
+// The auth_key_id and msg_key preceding the ciphertext.
+const ENCRYPTED_PACKET_HEADER_LEN: usize = 8 + 16;
+
+// salt, session_id, msg_id, seq_no and length preceding the body of the message.
+const PLAINTEXT_HEADER_LEN: usize = 8 + 8 + 8 + 4 + 4;
+
+/// Represents an error that occurred while processing an encrypted packet.
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// The packet could not be decrypted with the session's authorization key.
+    Decryption(grammers_crypto::Error),
+    /// A message in the packet could not be deserialized.
+    InvalidMessage(tl::deserialize::Error),
+    /// The length of a message does not fit in the data containing it.
+    BadLength { got: i32 },
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Decryption(error) => write!(f, "failed to decrypt packet: {error}"),
+            Self::InvalidMessage(error) => write!(f, "invalid message: {error}"),
+            Self::BadLength { got } => write!(f, "bad message length: {got}"),
+        }
+    }
+}
+
+impl From<grammers_crypto::Error> for Error {
+    fn from(error: grammers_crypto::Error) -> Self {
+        Self::Decryption(error)
+    }
+}
+
+impl From<tl::deserialize::Error> for Error {
+    fn from(error: tl::deserialize::Error) -> Self {
+        Self::InvalidMessage(error)
+    }
+}
+
+/// A message to be sent to the client, along with whether it answers one of the client's.
+struct Outgoing {
+    body: Vec<u8>,
+    response: bool,
+}
+
+/// The state of an encrypted session with a client.
+pub(crate) struct Session {
+    auth_key: AuthKey,
+    salt: i64,
+    session_id: Option<i64>,
+    sequence: i32,
+    last_msg_id: i64,
+}
+
+impl Session {
+    pub fn new(auth_key: AuthKey, salt: i64) -> Self {
+        Self {
+            auth_key,
+            salt,
+            session_id: None,
+            sequence: 0,
+            last_msg_id: 0,
+        }
+    }
+
+    pub fn auth_key_id(&self) -> i64 {
+        self.auth_key.id()
+    }
+
+    /// Process an encrypted packet sent by the client, and return the packets to send back.
+    pub fn process(
+        &mut self,
+        shared: &Shared,
+        packet: &[u8],
+    ) -> Result<Vec<DequeBuffer<u8>>, Error> {
+        // Padded transports may leave up to 15 bytes after the ciphertext, which must be ignored.
+        let excess = packet.len().saturating_sub(ENCRYPTED_PACKET_HEADER_LEN) % 16;
+        let plaintext = decrypt_client_data_v2(&packet[..packet.len() - excess], &self.auth_key)?;
+
+        let mut cursor = Cursor::from_slice(&plaintext);
+        let _salt = i64::deserialize(&mut cursor)?;
+        let session_id = i64::deserialize(&mut cursor)?;
+        let msg_id = i64::deserialize(&mut cursor)?;
+        let _seq_no = i32::deserialize(&mut cursor)?;
+        let len = i32::deserialize(&mut cursor)?;
+        let body = message_body(&plaintext, PLAINTEXT_HEADER_LEN, len)?;
+
+        let mut outgoing = Vec::new();
+        if self.session_id != Some(session_id) {
+            debug!("client started new session {session_id}");
+            self.session_id = Some(session_id);
+            self.sequence = 0;
+            outgoing.push(Outgoing {
+                body: tl::enums::NewSession::Created(tl::types::NewSessionCreated {
+                    first_msg_id: msg_id,
+                    unique_id: random_i64(),
+                    server_salt: self.salt,
+                })
+                .to_bytes(),
+                response: false,
+            });
+        }
+
+        self.process_message(shared, msg_id, body, &mut outgoing)?;
+
+        Ok(outgoing
+            .into_iter()
+            .map(|message| self.encrypt(&message.body, message.response))
+            .collect())
+    }
+
+    /// Encrypt updates to push them to the client, unless it has not started a session yet.
+    pub fn encrypt_updates(&mut self, updates: &[u8]) -> Option<DequeBuffer<u8>> {
+        self.session_id?;
+        Some(self.encrypt(updates, false))
+    }
+
+    fn process_message(
+        &mut self,
+        shared: &Shared,
+        msg_id: i64,
+        body: &[u8],
+        outgoing: &mut Vec<Outgoing>,
+    ) -> Result<(), Error> {
+        let mut cursor = Cursor::from_slice(body);
+        let constructor_id = u32::deserialize(&mut cursor)?;
+
+        match constructor_id {
+            MSG_CONTAINER_ID => {
+                let count = i32::deserialize(&mut cursor)?;
+                let mut pos = cursor.pos();
+                for _ in 0..count {
+                    // msg_id, seq_no and length preceding the body of each inner message.
+                    let mut header = Cursor::from_slice(message_body(body, pos, 16)?);
+                    let inner_msg_id = i64::deserialize(&mut header)?;
+                    let _seq_no = i32::deserialize(&mut header)?;
+                    let len = i32::deserialize(&mut header)?;
+
+                    let inner = message_body(body, pos + 16, len)?;
+                    self.process_message(shared, inner_msg_id, inner, outgoing)?;
+                    pos += 16 + inner.len();
+                }
+                Ok(())
+            }
+            GZIP_PACKED_ID => {
+                let packed = Vec::<u8>::deserialize(&mut cursor)?;
+                let mut unpacked = Vec::new();
+                GzDecoder::new(&packed[..])
+                    .read_to_end(&mut unpacked)
+                    .map_err(|_| tl::deserialize::Error::UnexpectedEof)?;
+                self.process_message(shared, msg_id, &unpacked, outgoing)
+            }
+            tl::functions::Ping::CONSTRUCTOR_ID => {
+                let ping = tl::functions::Ping::deserialize(&mut cursor)?;
+                outgoing.push(pong(msg_id, ping.ping_id));
+                Ok(())
+            }
+            tl::functions::PingDelayDisconnect::CONSTRUCTOR_ID => {
+                let ping = tl::functions::PingDelayDisconnect::deserialize(&mut cursor)?;
+                outgoing.push(pong(msg_id, ping.ping_id));
+                Ok(())
+            }
+            tl::functions::GetFutureSalts::CONSTRUCTOR_ID => {
+                let request = tl::functions::GetFutureSalts::deserialize(&mut cursor)?;
+                let now = now();
+                let salts = (0..request.num.clamp(1, 64))
+                    .map(|i| tl::types::FutureSalt {
+                        valid_since: now + i * 3600,
+                        valid_until: now + (i + 1) * 3600,
+                        salt: self.salt,
+                    })
+                    .collect();
+                outgoing.push(Outgoing {
+                    body: tl::enums::FutureSalts::Salts(tl::types::FutureSalts {
+                        req_msg_id: msg_id,
+                        now,
+                        salts: tl::RawVec(salts),
+                    })
+                    .to_bytes(),
+                    response: true,
+                });
+                Ok(())
+            }
+            tl::functions::DestroySession::CONSTRUCTOR_ID => {
+                let request = tl::functions::DestroySession::deserialize(&mut cursor)?;
+                outgoing.push(Outgoing {
+                    body: tl::enums::DestroySessionRes::DestroySessionOk(
+                        tl::types::DestroySessionOk {
+                            session_id: request.session_id,
+                        },
+                    )
+                    .to_bytes(),
+                    response: true,
+                });
+                Ok(())
+            }
+            tl::types::MsgsAck::CONSTRUCTOR_ID
+            | tl::types::MsgsStateReq::CONSTRUCTOR_ID
+            | tl::types::MsgsStateInfo::CONSTRUCTOR_ID
+            | tl::types::MsgsAllInfo::CONSTRUCTOR_ID
+            | tl::types::MsgResendReq::CONSTRUCTOR_ID
+            | tl::types::HttpWait::CONSTRUCTOR_ID => {
+                debug!("ignoring service message {constructor_id:08x}");
+                Ok(())
+            }
+            _ => {
+                let mut response = Vec::new();
+                RPC_RESULT_ID.serialize(&mut response);
+                msg_id.serialize(&mut response);
+                match shared.invoke(unwrap_query(body)?) {
+                    Ok(result) => response.extend(result),
+                    Err(RpcError { code, message }) => {
+                        tl::enums::RpcError::Error(tl::types::RpcError {
+                            error_code: code,
+                            error_message: message,
+                        })
+                        .serialize(&mut response);
+                    }
+                }
+                outgoing.push(Outgoing {
+                    body: response,
+                    response: true,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn encrypt(&mut self, body: &[u8], response: bool) -> DequeBuffer<u8> {
+        let msg_id = self.next_msg_id(response);
+        // Everything the server sends is content-related, and so has an odd sequence number.
+        let seq_no = self.sequence * 2 + 1;
+        self.sequence += 1;
+
+        let mut buffer = DequeBuffer::with_capacity(PLAINTEXT_HEADER_LEN + body.len() + 1024, 32);
+        self.salt.serialize(&mut buffer);
+        self.session_id.unwrap_or_default().serialize(&mut buffer);
+        msg_id.serialize(&mut buffer);
+        seq_no.serialize(&mut buffer);
+        (body.len() as i32).serialize(&mut buffer);
+        buffer.extend(body.iter().copied());
+
+        encrypt_server_data_v2(&mut buffer, &self.auth_key);
+        buffer
+    }
+
+    /// Generate a new message identifier, which is 1 modulo 4 for responses and 3 otherwise.
+    fn next_msg_id(&mut self, response: bool) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is before epoch");
+
+        let mut msg_id = ((now.as_secs() as i64) << 32) | ((now.subsec_nanos() as i64) << 2);
+        msg_id &= !3;
+        if msg_id <= self.last_msg_id & !3 {
+            msg_id = (self.last_msg_id & !3) + 4;
+        }
+        msg_id |= if response { 1 } else { 3 };
+
+        self.last_msg_id = msg_id;
+        msg_id
+    }
+}
+
+/// Strip the wrappers that only change how a query is invoked, such as `invokeWithLayer`.
+fn unwrap_query(body: &[u8]) -> Result<Vec<u8>, tl::deserialize::Error> {
+    let mut query = body.to_vec();
+    loop {
+        let mut cursor = Cursor::from_slice(&query);
+        query = match u32::deserialize(&mut cursor)? {
+            tl::functions::InvokeWithLayer::<tl::Blob>::CONSTRUCTOR_ID => {
+                tl::functions::InvokeWithLayer::<tl::Blob>::deserialize(&mut cursor)?
+                    .query
+                    .0
+            }
+            tl::functions::InitConnection::<tl::Blob>::CONSTRUCTOR_ID => {
+                tl::functions::InitConnection::<tl::Blob>::deserialize(&mut cursor)?
+                    .query
+                    .0
+            }
+            tl::functions::InvokeWithoutUpdates::<tl::Blob>::CONSTRUCTOR_ID => {
+                tl::functions::InvokeWithoutUpdates::<tl::Blob>::deserialize(&mut cursor)?
+                    .query
+                    .0
+            }
+            tl::functions::InvokeAfterMsg::<tl::Blob>::CONSTRUCTOR_ID => {
+                tl::functions::InvokeAfterMsg::<tl::Blob>::deserialize(&mut cursor)?
+                    .query
+                    .0
+            }
+            tl::functions::InvokeAfterMsgs::<tl::Blob>::CONSTRUCTOR_ID => {
+                tl::functions::InvokeAfterMsgs::<tl::Blob>::deserialize(&mut cursor)?
+                    .query
+                    .0
+            }
+            _ => return Ok(query),
+        };
+    }
+}
+
+/// Return the `len` bytes of the message body found at `start`.
+fn message_body(data: &[u8], start: usize, len: i32) -> Result<&[u8], Error> {
+    usize::try_from(len)
+        .ok()
+        .and_then(|len| data.get(start..start.checked_add(len)?))
+        .ok_or(Error::BadLength { got: len })
+}
+
+fn pong(msg_id: i64, ping_id: i64) -> Outgoing {
+    Outgoing {
+        body: tl::enums::Pong::Pong(tl::types::Pong { msg_id, ping_id }).to_bytes(),
+        response: true,
+    }
+}
+
+fn now() -> i32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before epoch")
+        .as_secs() as i32
+}
+
+fn random_i64() -> i64 {
+    let mut buffer = [0; 8];
+    getrandom(&mut buffer).expect("failed to generate a secure random number");
+    i64::from_le_bytes(buffer)
+}
@@ -39,12 +39,18 @@ use grammers_tl_types::{self as tl, Cursor, Deserializable, RemoteCall, Serializ
 use num_bigint::{BigUint, ToBigUint};
 use sha1::{Digest, Sha1};
 use std::fmt;
+#[cfg(feature = "testing")]
+use std::sync::RwLock;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 // NOTE! Turning this on will leak the key generation process to stdout!
 // Should only be used for debugging purposes and generating test cases.
 const TRACE_AUTH_GEN: bool = false;
 
+/// RSA keys trusted in addition to Telegram's own, added with [`trust_rsa_key`].
+#[cfg(feature = "testing")]
+static TRUSTED_KEYS: RwLock<Vec<(i64, rsa::Key)>> = RwLock::new(Vec::new());
+
 /// Represents an error that occured during the generation of an
 /// authorization key.
 #[derive(Clone, Debug, PartialEq)]
@@ -243,6 +249,28 @@ fn do_step1(random_bytes: &[u8; 16]) -> Result<(Vec<u8>, Step1), Error> {
 
 /// The second step of the process to generate an authorization key.
 pub fn step2(data: Step1, response: &[u8]) -> Result<(Vec<u8>, Step2), Error> {
+    do_random_step2(data, response, None)
+}
+
+/// The second step of the process to generate a temporary authorization key.
+///
+/// The key will be valid for the datacenter `dc_id` during `expires_in` seconds,
+/// and has to be bound to a permanent key before it can be used to make requests.
+/// The rest of the steps are the same as those for a permanent key.
+pub fn step2_temp(
+    data: Step1,
+    response: &[u8],
+    dc_id: i32,
+    expires_in: i32,
+) -> Result<(Vec<u8>, Step2), Error> {
+    do_random_step2(data, response, Some((dc_id, expires_in)))
+}
+
+fn do_random_step2(
+    data: Step1,
+    response: &[u8],
+    temp: Option<(i32, i32)>,
+) -> Result<(Vec<u8>, Step2), Error> {
     if TRACE_AUTH_GEN {
         println!("< {}", hex::to_hex(response));
     }
@@ -257,7 +285,7 @@ pub fn step2(data: Step1, response: &[u8]) -> Result<(Vec<u8>, Step2), Error> {
         println!("r {}", hex::to_hex(&random_bytes));
     }
 
-    let res = do_step2(data, response, &random_bytes);
+    let res = do_step2(data, response, &random_bytes, temp);
     if TRACE_AUTH_GEN {
         if let Ok((x, _)) = &res {
             println!("> {}", hex::to_hex(x));
@@ -270,6 +298,7 @@ fn do_step2(
     data: Step1,
     response: &[u8],
     random_bytes: &[u8; 32 + 224],
+    temp: Option<(i32, i32)>,
 ) -> Result<(Vec<u8>, Step2), Error> {
     // Step 2. Validate the PQ response. Return `(p, q)` if it's valid.
     let Step1 { nonce } = data;
@@ -323,14 +352,26 @@ fn do_step2(
 
     // "pq is a representation of a natural number (in binary big endian format)"
     // https://core.telegram.org/mtproto/auth_key#dh-exchange-initiation
-    let pq_inner_data = tl::enums::PQInnerData::Data(tl::types::PQInnerData {
-        pq: pq.to_be_bytes().to_vec(),
-        p: p_bytes.clone(),
-        q: q_bytes.clone(),
-        nonce,
-        server_nonce: res_pq.server_nonce,
-        new_nonce,
-    })
+    let pq_inner_data = match temp {
+        None => tl::enums::PQInnerData::Data(tl::types::PQInnerData {
+            pq: pq.to_be_bytes().to_vec(),
+            p: p_bytes.clone(),
+            q: q_bytes.clone(),
+            nonce,
+            server_nonce: res_pq.server_nonce,
+            new_nonce,
+        }),
+        Some((dc, expires_in)) => tl::enums::PQInnerData::TempDc(tl::types::PQInnerDataTempDc {
+            pq: pq.to_be_bytes().to_vec(),
+            p: p_bytes.clone(),
+            q: q_bytes.clone(),
+            nonce,
+            server_nonce: res_pq.server_nonce,
+            new_nonce,
+            dc,
+            expires_in,
+        }),
+    }
     .to_bytes();
 
     // sha_digest + data + random_bytes
@@ -714,9 +755,35 @@ fn check_g_in_range(value: &BigUint, low: &BigUint, high: &BigUint) -> Result<()
     }
 }
 
+/// Trust an additional RSA key when generating authorization keys, besides Telegram's own.
+///
+/// This is only needed to talk to servers other than Telegram's, such as those used in tests.
+/// Whoever holds the private part of the key will be trusted to be the server, so the key must
+/// only come from trusted sources. The key remains trusted for the rest of the process.
+///
+/// Only available with the `testing` feature, which should never be enabled outside of tests.
+#[cfg(feature = "testing")]
+pub fn trust_rsa_key(key: rsa::Key) {
+    let fingerprint = key.fingerprint();
+    let mut keys = TRUSTED_KEYS.write().unwrap();
+    if !keys.iter().any(|&(f, _)| f == fingerprint) {
+        keys.push((fingerprint, key));
+    }
+}
+
 /// Find the RSA key's `(n, e)` pair for a certain fingerprint.
 #[allow(clippy::unreadable_literal)]
 fn key_for_fingerprint(fingerprint: i64) -> Option<rsa::Key> {
+    #[cfg(feature = "testing")]
+    if let Some((_, key)) = TRUSTED_KEYS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|&&(f, _)| f == fingerprint)
+    {
+        return Some(key.clone());
+    }
+
     Some(match fingerprint {
         // Production
         -3414540481677951611 => rsa::Key::new("29379598170669337022986177149456128565388431120058863768162556424047512191330847455146576344487764408661701890505066208632169112269581063774293102577308490531282748465986139880977280302242772832972539403531316010870401287642763009136156734339538042419388722777357134487746169093539093850251243897188928735903389451772730245253062963384108812842079887538976360465290946139638691491496062099570836476454855996319192747663615955633778034897140982517446405334423701359108810182097749467210509584293428076654573384828809574217079944388301239431309115013843331317877374435868468779972014486325557807783825502498215169806323", "65537").unwrap(),
@@ -753,7 +820,7 @@ mod tests {
         assert_eq!(request, step1_request.to_vec());
         let response = step1_response;
 
-        let (request, data) = do_step2(data, &response, &step2_random)?;
+        let (request, data) = do_step2(data, &response, &step2_random, None)?;
         assert_eq!(request, step2_request.to_vec());
         let response = step2_response;
 
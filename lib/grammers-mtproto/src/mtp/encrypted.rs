@@ -6,15 +6,20 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 use super::{
-    Deserialization, DeserializationFailure, DeserializeError, Mtp, RpcResult, RpcResultError,
+    Deserialization, DeserializationFailure, DeserializeError, Mtp, Record, Recorder, RpcResult,
+    RpcResultError,
 };
 use crate::utils::StackBuffer;
 use crate::{manual_tl, MsgId};
 use getrandom::getrandom;
-use grammers_crypto::{decrypt_data_v2, encrypt_data_v2, AuthKey, DequeBuffer};
-use grammers_tl_types::{self as tl, Cursor, Deserializable, Identifiable, Serializable};
+use grammers_crypto::{decrypt_data_v2, encrypt_data_v1, encrypt_data_v2, AuthKey, DequeBuffer};
+use grammers_tl_types::{
+    self as tl, Cursor, Deserializable, Identifiable, RemoteCall, Serializable,
+};
 use log::info;
+use std::collections::{BTreeMap, HashMap};
 use std::mem;
+use std::sync::Arc;
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 /// How many future salts to fetch or have stored at a given time.
@@ -28,7 +33,14 @@ const NUM_FUTURE_SALTS: i32 = 64;
 /// Used to prevent small fluctuations in the system clock.
 const SALT_USE_DELAY: i32 = 60;
 
-static UPDATE_IDS: [u32; 8] = [
+/// How many identifiers of the messages received from the server to remember, in order to tell
+/// the server about their state when it asks.
+const MAX_RECEIVED_IDS: usize = 1024;
+
+/// How many of our requests to re-send answers to remember at a given time.
+const MAX_RESEND_REQUESTS: usize = 64;
+
+pub(super) static UPDATE_IDS: [u32; 8] = [
     tl::types::UpdateShortMessage::CONSTRUCTOR_ID,
     tl::types::UpdateShortChatMessage::CONSTRUCTOR_ID,
     tl::types::UpdateShort::CONSTRUCTOR_ID,
@@ -77,6 +89,11 @@ pub struct Encrypted {
     /// The secure, random identifier for this instance.
     client_id: i64,
 
+    /// The identifier used before starting a fresh session, if any.
+    ///
+    /// The server may still send messages in the old session, which are of no use anymore.
+    previous_client_id: Option<i64>,
+
     /// The current message sequence number.
     sequence: i32,
 
@@ -92,6 +109,25 @@ pub struct Encrypted {
     /// [Content-related Message]: https://core.telegram.org/mtproto/description#content-related-message
     pending_ack: Vec<i64>,
 
+    /// Identifiers of the messages recently received from the server, along with whether they
+    /// required an acknowledgment. Used to answer the server when it asks about their state.
+    received: BTreeMap<i64, bool>,
+
+    /// Answers to the server's requests for the state of its messages, to be sent along with the
+    /// next messages.
+    pending_state_info: Vec<tl::types::MsgsStateInfo>,
+
+    /// Identifiers of the server's answers that were never received and must be re-sent, along
+    /// with the identifier of our message they answer, if known.
+    pending_resend: Vec<(i64, Option<i64>)>,
+
+    /// Sent requests to re-send answers, in case the server reports it can't re-send them.
+    resend_requests: BTreeMap<i64, Vec<(i64, Option<i64>)>>,
+
+    /// Sent requests to destroy a session, by session identifier. The results don't contain the
+    /// identifier of the message they answer, so it must be remembered.
+    destroy_session_requests: HashMap<i64, MsgId>,
+
     /// If present, the threshold in bytes at which a message will be
     /// considered large enough to attempt compressing it. Otherwise,
     /// outgoing messages will never be compressed.
@@ -100,6 +136,9 @@ pub struct Encrypted {
     /// Temporary deserialization results.
     deserialization: Vec<Deserialization>,
 
+    /// The hook notified of every request pushed and every response deserialized, if any.
+    recorder: Option<Arc<dyn Recorder>>,
+
     /// How many messages are there in the buffer.
     msg_count: usize,
 }
@@ -135,16 +174,19 @@ impl Builder {
             }],
             start_salt_time: None,
             salt_request_msg_id: None,
-            client_id: {
-                let mut buffer = [0u8; 8];
-                getrandom(&mut buffer).expect("failed to generate a secure client_id");
-                i64::from_le_bytes(buffer)
-            },
+            client_id: generate_client_id(),
+            previous_client_id: None,
             sequence: 0,
             last_msg_id: 0,
             pending_ack: vec![],
+            received: BTreeMap::new(),
+            pending_state_info: Vec::new(),
+            pending_resend: Vec::new(),
+            resend_requests: BTreeMap::new(),
+            destroy_session_requests: HashMap::new(),
             compression_threshold: self.compression_threshold,
             deserialization: Vec::new(),
+            recorder: None,
             msg_count: 0,
         }
     }
@@ -165,6 +207,82 @@ impl Encrypted {
         self.auth_key.to_bytes()
     }
 
+    /// Serializes the [`auth.bindTempAuthKey`] request that binds the key of this instance, which
+    /// must be a temporary key, to the permanent `perm_auth_key`, until the server time reaches
+    /// `expires_at`.
+    ///
+    /// The binding message requires the request to be sent with a specific message ID, so it must
+    /// be the only message in the buffer (meaning this should be the first thing an instance sends).
+    /// The buffer still needs to be finalized afterwards, and the returned message ID will be the
+    /// one of the response.
+    ///
+    /// [`auth.bindTempAuthKey`]: https://core.telegram.org/method/auth.bindTempAuthKey
+    pub fn push_temp_auth_key_binding(
+        &mut self,
+        buffer: &mut DequeBuffer<u8>,
+        perm_auth_key: &[u8; 256],
+        expires_at: i32,
+    ) -> MsgId {
+        assert!(self.msg_count == 0 && self.pending_ack.is_empty());
+
+        let perm_auth_key = AuthKey::from_bytes(*perm_auth_key);
+        let nonce = {
+            let mut buffer = [0u8; 8];
+            getrandom(&mut buffer).expect("failed to generate a secure nonce");
+            i64::from_le_bytes(buffer)
+        };
+        let msg_id = self.get_new_msg_id();
+
+        let inner = tl::enums::BindAuthKeyInner::Inner(tl::types::BindAuthKeyInner {
+            nonce,
+            temp_auth_key_id: self.auth_key.id(),
+            perm_auth_key_id: perm_auth_key.id(),
+            temp_session_id: self.client_id,
+            expires_at,
+        })
+        .to_bytes();
+
+        // The binding message follows the format of an encrypted message, where
+        // the salt and session identifier are replaced with random bytes.
+        let mut plaintext = Vec::with_capacity(16 + 8 + 4 + 4 + inner.len());
+        plaintext.extend({
+            let mut buffer = [0u8; 16];
+            getrandom(&mut buffer).expect("failed to generate secure random data");
+            buffer
+        });
+        plaintext.extend(msg_id.to_le_bytes());
+        plaintext.extend(0i32.to_le_bytes());
+        plaintext.extend((inner.len() as i32).to_le_bytes());
+        plaintext.extend(inner);
+
+        let body = tl::functions::auth::BindTempAuthKey {
+            perm_auth_key_id: perm_auth_key.id(),
+            nonce,
+            expires_at,
+            encrypted_message: encrypt_data_v1(&plaintext, &perm_auth_key),
+        };
+
+        self.serialize_msg_with_id(buffer, msg_id, &body, true)
+    }
+
+    /// Start a new session, with a new identifier and sequence numbers.
+    ///
+    /// Nothing about the messages of the previous session is relevant in the new one, so all
+    /// state about them is dropped too.
+    fn start_fresh_session(&mut self) {
+        self.previous_client_id = Some(self.client_id);
+        self.client_id = generate_client_id();
+        self.sequence = 0;
+        self.last_msg_id = 0;
+        self.pending_ack.clear();
+        self.received.clear();
+        self.pending_state_info.clear();
+        self.pending_resend.clear();
+        self.resend_requests.clear();
+        self.destroy_session_requests.clear();
+        self.salt_request_msg_id = None;
+    }
+
     /// Correct our time offset based on a known valid message ID.
     fn correct_time_offset(&mut self, msg_id: i64) {
         let now = SystemTime::now()
@@ -209,15 +327,42 @@ impl Encrypted {
     fn serialize_msg(
         &mut self,
         buffer: &mut DequeBuffer<u8>,
-        body: &[u8],
+        body: &impl Serializable,
         content_related: bool,
     ) -> MsgId {
         let msg_id = self.get_new_msg_id();
+        self.serialize_msg_with_id(buffer, msg_id, body, content_related)
+    }
+
+    fn serialize_msg_with_id(
+        &mut self,
+        buffer: &mut DequeBuffer<u8>,
+        msg_id: i64,
+        body: &impl Serializable,
+        content_related: bool,
+    ) -> MsgId {
+        let header_pos = buffer.len();
+        buffer.extend([0; manual_tl::Message::SIZE_OVERHEAD]);
+        body.serialize(buffer);
+        self.write_msg_header(buffer, header_pos, msg_id, content_related)
+    }
+
+    /// Fills in the header of the message starting at `header_pos`, whose body must be the rest
+    /// of the buffer. This lets the body be serialized in-place before its length is known.
+    fn write_msg_header(
+        &mut self,
+        buffer: &mut DequeBuffer<u8>,
+        header_pos: usize,
+        msg_id: i64,
+        content_related: bool,
+    ) -> MsgId {
+        let len = (buffer.len() - header_pos - manual_tl::Message::SIZE_OVERHEAD) as i32;
+        let seq_no = self.get_seq_no(content_related);
 
-        msg_id.serialize(buffer);
-        self.get_seq_no(content_related).serialize(buffer);
-        (body.len() as i32).serialize(buffer);
-        buffer.extend(body);
+        let header = &mut buffer[header_pos..header_pos + manual_tl::Message::SIZE_OVERHEAD];
+        header[0..8].copy_from_slice(&msg_id.to_le_bytes());
+        header[8..12].copy_from_slice(&seq_no.to_le_bytes());
+        header[12..16].copy_from_slice(&len.to_le_bytes());
 
         self.msg_count += 1;
         MsgId(msg_id)
@@ -242,12 +387,74 @@ impl Encrypted {
             info!("only one future salt remaining; asking for more salts");
             let body = tl::functions::GetFutureSalts {
                 num: NUM_FUTURE_SALTS,
-            }
-            .to_bytes();
+            };
             self.salt_request_msg_id = Some(self.serialize_msg(buffer, &body, true));
         }
     }
 
+    /// Serialize the service messages waiting to be sent: acknowledgments, answers to the
+    /// server's requests for the state of its messages, and requests to re-send lost answers.
+    fn push_service_messages(&mut self, buffer: &mut DequeBuffer<u8>) {
+        if !self.pending_ack.is_empty() {
+            let body = tl::enums::MsgsAck::Ack(tl::types::MsgsAck {
+                msg_ids: mem::take(&mut self.pending_ack),
+            });
+            self.serialize_msg(buffer, &body, false);
+        }
+
+        for info in mem::take(&mut self.pending_state_info) {
+            let body = tl::enums::MsgsStateInfo::Info(info);
+            self.serialize_msg(buffer, &body, false);
+        }
+
+        if !self.pending_resend.is_empty() {
+            let answers = mem::take(&mut self.pending_resend);
+            let body = tl::enums::MsgResendReq::Req(tl::types::MsgResendReq {
+                msg_ids: answers
+                    .iter()
+                    .map(|&(answer_msg_id, _)| answer_msg_id)
+                    .collect(),
+            });
+            let msg_id = self.serialize_msg(buffer, &body, true);
+            self.resend_requests.insert(msg_id.0, answers);
+            if self.resend_requests.len() > MAX_RESEND_REQUESTS {
+                self.resend_requests.pop_first();
+            }
+        }
+    }
+
+    /// The state of a message sent by the server, as reported in `msgs_state_info`.
+    fn msg_state(&self, msg_id: i64) -> u8 {
+        if let Some(&requires_ack) = self.received.get(&msg_id) {
+            return if !requires_ack {
+                4 | 16 // received, not requiring acknowledgment
+            } else if self.pending_ack.contains(&msg_id) {
+                4 // received, acknowledgment not sent yet
+            } else {
+                4 | 8 // received and acknowledged
+            };
+        }
+
+        let first = self.received.keys().next();
+        let last = self.received.keys().next_back();
+        match (first, last) {
+            (Some(&first), _) if msg_id < first => 1, // too old, it may have been forgotten
+            (_, Some(&last)) if msg_id < last => 2,   // certainly not received
+            _ => 3,                                   // not received yet
+        }
+    }
+
+    /// Ask the server to re-send an answer, unless it was already received, in which case the
+    /// server only needs to know.
+    fn request_answer(&mut self, answer_msg_id: i64, msg_id: Option<i64>) {
+        if self.received.contains_key(&answer_msg_id) {
+            self.pending_ack.push(answer_msg_id);
+        } else {
+            info!("answer {answer_msg_id} was not received; asking to re-send it");
+            self.pending_resend.push((answer_msg_id, msg_id));
+        }
+    }
+
     /// `finalize`, but without encryption.
     ///
     /// The buffer is *not* cleared, but is instead returned.
@@ -289,6 +496,10 @@ impl Encrypted {
         if message.requires_ack() {
             self.pending_ack.push(message.msg_id);
         }
+        self.received.insert(message.msg_id, message.requires_ack());
+        if self.received.len() > MAX_RECEIVED_IDS {
+            self.received.pop_first();
+        }
 
         // Handle all the possible Service Messages:
         // * https://core.telegram.org/mtproto/service_messages
@@ -522,9 +733,8 @@ impl Encrypted {
         match u32::from_bytes(body) {
             Ok(body_id) => {
                 if UPDATE_IDS.iter().any(|&id| body_id == id) {
-                    // TODO somehow signal that this updates is our own, to avoid getting into nasty loops
                     self.deserialization
-                        .push(Deserialization::Update(body.to_vec()));
+                        .push(Deserialization::OwnUpdate(body.to_vec()));
                 }
             }
             Err(_err) => {
@@ -562,9 +772,13 @@ impl Encrypted {
     /// transmits a stand-alone acknowledgment.
     ///
     /// [Acknowledgment of Receipt]: https://core.telegram.org/mtproto/service_messages_about_messages#acknowledgment-of-receipt
-    fn handle_ack(&self, message: manual_tl::Message) -> Result<(), DeserializeError> {
-        // TODO notify about this somehow
-        let _ack = tl::enums::MsgsAck::from_bytes(&message.body)?;
+    fn handle_ack(&mut self, message: manual_tl::Message) -> Result<(), DeserializeError> {
+        let tl::enums::MsgsAck::Ack(ack) = tl::enums::MsgsAck::from_bytes(&message.body)?;
+        self.deserialization.extend(
+            ack.msg_ids
+                .into_iter()
+                .map(|id| Deserialization::Ack(MsgId(id))),
+        );
         Ok(())
     }
 
@@ -671,23 +885,20 @@ impl Encrypted {
         };
 
         match bad_msg.error_code {
-            16 => {
-                // Sent `msg_id` was too low (our `time_offset` is wrong).
-                self.correct_time_offset(message.msg_id);
-            }
-            17 => {
-                // Sent `msg_id` was too high (our `time_offset` is wrong).
+            // Sent `msg_id` was too low or too high (our `time_offset` is wrong),
+            // or sent `seq_no` was too low or too high.
+            //
+            // Guessing by how much to fix either is prone to getting it wrong over and over again,
+            // so instead the time is synchronized with the server's `msg_id` (which is always
+            // correct), and a fresh session is started so that `msg_id` and `seq_no` start over.
+            // Upper layers are notified through the `BadMessage` and re-send what's affected.
+            16 | 17 | 32 | 33 => {
+                info!(
+                    "got bad msg with code {}; starting a fresh session",
+                    bad_msg.error_code
+                );
                 self.correct_time_offset(message.msg_id);
-            }
-            32 => {
-                // Sent `seq_no` was too low. Bump it by some large-ish value.
-                // TODO start with a fresh session rather than guessing
-                self.sequence += 64;
-            }
-            33 => {
-                // Sent `seq_no` was too high (this error doesn't seem to occur).
-                // TODO start with a fresh session rather than guessing
-                self.sequence -= 16;
+                self.start_fresh_session();
             }
             _ => {
                 // Just notify about it.
@@ -708,8 +919,13 @@ impl Encrypted {
     /// ```
     ///
     /// [Request for Message Status Information]: https://core.telegram.org/mtproto/service_messages_about_messages#request-for-message-status-information
-    fn handle_state_req(&self, _message: manual_tl::Message) -> Result<(), DeserializeError> {
-        // TODO implement
+    fn handle_state_req(&mut self, message: manual_tl::Message) -> Result<(), DeserializeError> {
+        let tl::enums::MsgsStateReq::Req(req) = tl::enums::MsgsStateReq::from_bytes(&message.body)?;
+        let info = req.msg_ids.iter().map(|&id| self.msg_state(id)).collect();
+        self.pending_state_info.push(tl::types::MsgsStateInfo {
+            req_msg_id: message.msg_id,
+            info,
+        });
         Ok(())
     }
 
@@ -749,8 +965,33 @@ impl Encrypted {
     /// valid, the message is to be wrapped in `msg_copy`).
     ///
     /// [Informational Message regarding Status of Messages]: https://core.telegram.org/mtproto/service_messages_about_messages#informational-message-regarding-status-of-messages
-    fn handle_state_info(&mut self, _message: manual_tl::Message) -> Result<(), DeserializeError> {
-        // TODO implement
+    fn handle_state_info(&mut self, message: manual_tl::Message) -> Result<(), DeserializeError> {
+        let tl::enums::MsgsStateInfo::Info(info) =
+            tl::enums::MsgsStateInfo::from_bytes(&message.body)?;
+
+        // We never ask for the state of messages, so this can only be the server telling us
+        // that some of the answers we asked it to re-send are gone.
+        let answers = match self.resend_requests.remove(&info.req_msg_id) {
+            Some(answers) => answers,
+            None => {
+                info!(
+                    "got state info for unknown request {}; ignoring",
+                    info.req_msg_id
+                );
+                return Ok(());
+            }
+        };
+
+        for ((answer_msg_id, msg_id), state) in answers.into_iter().zip(info.info) {
+            if state & 7 == 4 {
+                continue;
+            }
+            info!("answer {answer_msg_id} is lost (state {state})");
+            if let Some(msg_id) = msg_id {
+                self.deserialization
+                    .push(Deserialization::AnswerLost(MsgId(msg_id)));
+            }
+        }
         Ok(())
     }
 
@@ -771,8 +1012,17 @@ impl Encrypted {
     /// This message does not require an acknowledgment.
     ///
     /// [Voluntary Communication of Status of Messages]: https://core.telegram.org/mtproto/service_messages_about_messages#voluntary-communication-of-status-of-messages
-    fn handle_msg_all(&mut self, _message: manual_tl::Message) -> Result<(), DeserializeError> {
-        // TODO implement
+    fn handle_msg_all(&mut self, message: manual_tl::Message) -> Result<(), DeserializeError> {
+        let tl::enums::MsgsAllInfo::Info(info) = tl::enums::MsgsAllInfo::from_bytes(&message.body)?;
+
+        // Messages the server has certainly not received can simply be sent again. Those with a
+        // state of 3 may still be on their way, so sending them again could execute them twice.
+        for (msg_id, state) in info.msg_ids.into_iter().zip(info.info) {
+            if state & 7 == 2 {
+                self.deserialization
+                    .push(Deserialization::Resend(MsgId(msg_id)));
+            }
+        }
         Ok(())
     }
 
@@ -803,15 +1053,16 @@ impl Encrypted {
         &mut self,
         message: manual_tl::Message,
     ) -> Result<(), DeserializeError> {
-        // TODO https://github.com/telegramdesktop/tdesktop/blob/8f82880b938e06b7a2a27685ef9301edb12b4648/Telegram/SourceFiles/mtproto/connection.cpp#L1790-L1820
-        // TODO https://github.com/telegramdesktop/tdesktop/blob/8f82880b938e06b7a2a27685ef9301edb12b4648/Telegram/SourceFiles/mtproto/connection.cpp#L1822-L1845
+        // Acknowledging an answer that never arrived would make the server forget about it,
+        // so it's only done for those that did. The rest are requested again.
+        // See https://github.com/telegramdesktop/tdesktop/blob/8f82880b938e06b7a2a27685ef9301edb12b4648/Telegram/SourceFiles/mtproto/connection.cpp#L1790-L1845
         let msg_detailed = tl::enums::MsgDetailedInfo::from_bytes(&message.body)?;
         match msg_detailed {
             tl::enums::MsgDetailedInfo::Info(x) => {
-                self.pending_ack.push(x.answer_msg_id);
+                self.request_answer(x.answer_msg_id, Some(x.msg_id));
             }
             tl::enums::MsgDetailedInfo::MsgNewDetailedInfo(x) => {
-                self.pending_ack.push(x.answer_msg_id);
+                self.request_answer(x.answer_msg_id, None);
             }
         }
         Ok(())
@@ -845,9 +1096,25 @@ impl Encrypted {
     ///
     /// [Explicit Request to Re-Send Answers]: https://core.telegram.org/mtproto/service_messages_about_messages#explicit-request-to-re-send-answers
     /// [Explicit Request to Re-Send Messages]: https://core.telegram.org/mtproto/service_messages_about_messages#explicit-request-to-re-send-messages
-    fn handle_msg_resend(&self, _message: manual_tl::Message) -> Result<(), DeserializeError> {
-        // TODO implement
-        // `msg_resend_ans_req` seems to never occur (it was even missing from `mtproto.tl`)
+    fn handle_msg_resend(&mut self, message: manual_tl::Message) -> Result<(), DeserializeError> {
+        match tl::enums::MsgResendReq::from_bytes(&message.body)? {
+            tl::enums::MsgResendReq::Req(req) => {
+                for msg_id in req.msg_ids {
+                    self.deserialization
+                        .push(Deserialization::Resend(MsgId(msg_id)));
+                }
+            }
+            tl::enums::MsgResendReq::MsgResendAnsReq(req) => {
+                // `msg_resend_ans_req` seems to never occur (it was even missing from `mtproto.tl`).
+                // We don't answer the server's messages, so there is nothing to re-send other than
+                // the state of the messages.
+                let info = req.msg_ids.iter().map(|&id| self.msg_state(id)).collect();
+                self.pending_state_info.push(tl::types::MsgsStateInfo {
+                    req_msg_id: message.msg_id,
+                    info,
+                });
+            }
+        }
         Ok(())
     }
 
@@ -959,8 +1226,24 @@ impl Encrypted {
     /// ```
     ///
     /// [Request to Destroy Session]: https://core.telegram.org/mtproto/service_messages#request-to-destroy-session
-    fn handle_destroy_session(&self, _message: manual_tl::Message) -> Result<(), DeserializeError> {
-        // TODO implement
+    fn handle_destroy_session(
+        &mut self,
+        message: manual_tl::Message,
+    ) -> Result<(), DeserializeError> {
+        let session_id = match tl::enums::DestroySessionRes::from_bytes(&message.body)? {
+            tl::enums::DestroySessionRes::DestroySessionOk(x) => x.session_id,
+            tl::enums::DestroySessionRes::DestroySessionNone(x) => x.session_id,
+        };
+
+        match self.destroy_session_requests.remove(&session_id) {
+            Some(msg_id) => self
+                .deserialization
+                .push(Deserialization::RpcResult(RpcResult {
+                    msg_id,
+                    body: message.body,
+                })),
+            None => info!("got destroy session result for {session_id} but it was not requested"),
+        }
         Ok(())
     }
 
@@ -1000,7 +1283,6 @@ impl Encrypted {
         &mut self,
         message: manual_tl::Message,
     ) -> Result<(), DeserializeError> {
-        // TODO notify upper layers about the need to use getDifference
         let new_session = tl::enums::NewSession::from_bytes(&message.body)?;
         match new_session {
             tl::enums::NewSession::Created(x) => {
@@ -1010,6 +1292,8 @@ impl Encrypted {
                     valid_until: i32::MAX,
                     salt: x.server_salt,
                 });
+                self.deserialization
+                    .push(Deserialization::NewSession(MsgId(x.first_msg_id)));
             }
         }
         Ok(())
@@ -1157,7 +1441,8 @@ impl Encrypted {
     ///
     /// [HTTP Wait/Long Poll]: https://core.telegram.org/mtproto/service_messages#http-wait-long-poll
     fn handle_http_wait(&mut self, _message: manual_tl::Message) -> Result<(), DeserializeError> {
-        // TODO implement
+        // Only clients send this, and only through HTTP, which is not supported.
+        info!("got http_wait from the server; ignoring");
         Ok(())
     }
 
@@ -1191,9 +1476,7 @@ impl Mtp for Encrypted {
     /// efficiency. If the buffer is full, returns `None`.
     ///
     /// [MTProto 2.0 guidelines]: https://core.telegram.org/mtproto/description.
-    fn push(&mut self, buffer: &mut DequeBuffer<u8>, request: &[u8]) -> Option<MsgId> {
-        // TODO rather than taking in bytes, take requests, serialize them in place, and if too large drop the last part of the buffer
-
+    fn push<R: RemoteCall>(&mut self, buffer: &mut DequeBuffer<u8>, request: &R) -> Option<MsgId> {
         // Check to see if the next salt can be used already. If it can, drop the current one and,
         // if the next salt is the last one, fetch more.
         if let Some((start_secs, start_instant)) = self.start_salt_time {
@@ -1212,57 +1495,93 @@ impl Mtp for Encrypted {
             return None;
         }
 
-        // If we need to acknowledge messages, this notification goes in with the rest of requests
-        // so that we can also include it. It has priority over user requests because these should
-        // be sent out as soon as possible.
-        if !self.pending_ack.is_empty() {
-            // TODO avoid to_bytes here, serialize it in-place
-            let body = tl::enums::MsgsAck::Ack(tl::types::MsgsAck {
-                msg_ids: mem::take(&mut self.pending_ack),
-            })
-            .to_bytes();
-            self.serialize_msg(buffer, &body, false);
-        }
+        // If we need to acknowledge messages (or answer the server), this notification goes in
+        // with the rest of requests so that we can also include it. It has priority over user
+        // requests because these should be sent out as soon as possible.
+        self.push_service_messages(buffer);
 
         // Serialize `MAXIMUM_LENGTH` requests at most.
         if self.msg_count == manual_tl::MessageContainer::MAXIMUM_LENGTH {
             return None;
         }
 
+        // Not even the message header fits, so don't bother serializing the request.
+        if buffer.len() + manual_tl::Message::SIZE_OVERHEAD
+            >= manual_tl::MessageContainer::MAXIMUM_SIZE
+        {
+            return None;
+        }
+
+        // Serialize the request in-place, after the space reserved for its message header. If it
+        // turns out it doesn't fit in the container, it's dropped from the buffer instead.
+        let header_pos = buffer.len();
+        buffer.extend([0; manual_tl::Message::SIZE_OVERHEAD]);
+        let body_pos = buffer.len();
+        request.serialize(buffer);
+        let request_len = buffer.len() - body_pos;
+
         // Requests that are too large would cause Telegram to close the
         // connection but are so uncommon it's not worth returning `Err`.
         assert!(
-            request.len() + manual_tl::Message::SIZE_OVERHEAD
+            request_len + manual_tl::Message::SIZE_OVERHEAD
                 <= manual_tl::MessageContainer::MAXIMUM_SIZE
         );
 
         // Serialized requests will always be correctly padded.
-        assert!(request.len() % 4 == 0);
+        assert_eq!(request_len % 4, 0);
+
+        let destroy_session_id = if u32::from_bytes(&buffer[body_pos..])
+            == Ok(tl::functions::DestroySession::CONSTRUCTOR_ID)
+        {
+            i64::from_bytes(&buffer[body_pos + 4..]).ok()
+        } else {
+            None
+        };
+        // The request is recorded as it was serialized, before it's compressed.
+        let recorded = self.recorder.is_some().then(|| buffer[body_pos..].to_vec());
 
         // Payload provided by the user is always considered to be
         // content-related, which means we can apply compression.
-        let mut body = request;
-        let compressed;
         if let Some(threshold) = self.compression_threshold {
-            if request.len() >= threshold {
-                compressed = manual_tl::GzipPacked::new(request).to_bytes();
-                if compressed.len() < request.len() {
-                    body = &compressed;
+            if request_len >= threshold {
+                // The packed request goes after the original, and replaces it if it's smaller.
+                manual_tl::GzipPacked::new(&buffer[body_pos..]).serialize(buffer);
+                let compressed_len = buffer.len() - body_pos - request_len;
+                if compressed_len < request_len {
+                    buffer[body_pos..].copy_within(request_len.., 0);
+                    buffer.truncate(body_pos + compressed_len);
+                } else {
+                    buffer.truncate(body_pos + request_len);
                 }
             }
         }
 
-        let new_size = buffer.len() + body.len() + manual_tl::Message::SIZE_OVERHEAD;
-        if new_size >= manual_tl::MessageContainer::MAXIMUM_SIZE {
+        if buffer.len() >= manual_tl::MessageContainer::MAXIMUM_SIZE {
             // No more messages fit in this container.
+            buffer.truncate(header_pos);
             return None;
         }
 
         // This request still fits in the container, so give it a message ID.
-        Some(self.serialize_msg(buffer, body, true))
+        let msg_id = self.get_new_msg_id();
+        let msg_id = self.write_msg_header(buffer, header_pos, msg_id, true);
+        if let Some(session_id) = destroy_session_id {
+            self.destroy_session_requests.insert(session_id, msg_id);
+        }
+        if let (Some(recorder), Some(request)) = (&self.recorder, recorded) {
+            recorder.record(Record::request(msg_id, &request));
+        }
+        Some(msg_id)
     }
 
     fn finalize(&mut self, buffer: &mut DequeBuffer<u8>) -> Option<MsgId> {
+        // Answers to the server should not wait until there is a request to send them with.
+        if self.msg_count == 0
+            && (!self.pending_state_info.is_empty() || !self.pending_resend.is_empty())
+        {
+            self.push_service_messages(buffer);
+        }
+
         self.finalize_plain(buffer);
         if buffer.is_empty() {
             None
@@ -1276,17 +1595,32 @@ impl Mtp for Encrypted {
     fn deserialize(&mut self, payload: &[u8]) -> Result<Vec<Deserialization>, DeserializeError> {
         crate::utils::check_message_buffer(payload)?;
 
+        // Padded transports may leave up to 15 bytes after the ciphertext, which must be ignored.
+        let excess = payload.len().saturating_sub(ENCRYPTED_PACKET_HEADER_LEN) % 16;
+        let payload = &payload[..payload.len() - excess];
+
         let plaintext = decrypt_data_v2(payload, &self.auth_key)?;
         let mut buffer = Cursor::from_slice(&plaintext[..]);
 
         let _salt = i64::deserialize(&mut buffer)?;
         let client_id = i64::deserialize(&mut buffer)?;
         if client_id != self.client_id {
+            if self.previous_client_id == Some(client_id) {
+                info!("ignoring message sent to the previous session");
+                return Ok(Vec::new());
+            }
             panic!("wrong session id");
         }
 
         self.process_message(manual_tl::Message::deserialize(&mut buffer)?)?;
 
+        if let Some(recorder) = &self.recorder {
+            self.deserialization
+                .iter()
+                .filter_map(Record::from_deserialization)
+                .for_each(|record| recorder.record(record));
+        }
+
         // For simplicity, and to avoid passing too much stuff around (RPC results, updates),
         // the processing result is stored in self. After processing is done, that temporary
         // state is cleaned and returned with `mem::take`.
@@ -1295,22 +1629,27 @@ impl Mtp for Encrypted {
 
     fn reset(&mut self) {
         log::info!("resetting mtp client id and related state");
-        self.client_id = {
-            let mut buffer = [0u8; 8];
-            getrandom(&mut buffer).expect("failed to generate a secure client_id");
-            i64::from_le_bytes(buffer)
-        };
-        self.sequence = 0;
-        self.last_msg_id = 0;
-        self.pending_ack.clear();
+        self.start_fresh_session();
+        // The connection is new, so nothing from the previous session will arrive.
+        self.previous_client_id = None;
         self.msg_count = 0;
-        self.salt_request_msg_id = None;
+    }
+
+    fn set_recorder(&mut self, recorder: Option<Arc<dyn Recorder>>) {
+        self.recorder = recorder;
     }
 }
 
+fn generate_client_id() -> i64 {
+    let mut buffer = [0u8; 8];
+    getrandom(&mut buffer).expect("failed to generate a secure client_id");
+    i64::from_le_bytes(buffer)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mtp::Recording;
 
     // salt + client_id
     const MESSAGE_PREFIX_LEN: usize = 8 + 8;
@@ -1344,7 +1683,7 @@ mod tests {
         let mut buffer = DequeBuffer::with_capacity(0, 0);
         let mut mtproto = Encrypted::build().finish(auth_key());
 
-        mtproto.push(&mut buffer, REQUEST);
+        mtproto.push(&mut buffer, &tl::Blob(REQUEST.into()));
         mtproto.finalize_plain(&mut buffer);
 
         // salt comes first, it's zero by default.
@@ -1362,13 +1701,46 @@ mod tests {
         let mut buffer = DequeBuffer::with_capacity(0, 0);
         let mut mtproto = Encrypted::build().finish(auth_key());
 
-        assert!(mtproto.push(&mut buffer, REQUEST).is_some());
+        assert!(mtproto
+            .push(&mut buffer, &tl::Blob(REQUEST.into()))
+            .is_some());
         mtproto.finalize_plain(&mut buffer);
 
         let buffer = &buffer[MESSAGE_PREFIX_LEN..];
         ensure_buffer_is_message(buffer, REQUEST, 1);
     }
 
+    #[test]
+    fn ensure_temp_auth_key_binding_is_serialized() {
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        let perm_auth_key = [1; 256];
+
+        let msg_id = mtproto.push_temp_auth_key_binding(&mut buffer, &perm_auth_key, 1234);
+        mtproto.finalize_plain(&mut buffer);
+
+        let buffer = &buffer[MESSAGE_PREFIX_LEN..];
+        assert_eq!(&buffer[0..8], msg_id.0.to_le_bytes());
+        assert_eq!(&buffer[8..12], [1, 0, 0, 0]);
+
+        let mut body = Cursor::from_slice(&buffer[16..]);
+        assert_eq!(
+            u32::deserialize(&mut body).unwrap(),
+            tl::functions::auth::BindTempAuthKey::CONSTRUCTOR_ID
+        );
+        let perm_auth_key_id = i64::deserialize(&mut body).unwrap();
+        let _nonce = i64::deserialize(&mut body).unwrap();
+        let expires_at = i32::deserialize(&mut body).unwrap();
+        let encrypted_message = Vec::<u8>::deserialize(&mut body).unwrap();
+
+        let perm_auth_key = AuthKey::from_bytes(perm_auth_key);
+        assert_eq!(perm_auth_key_id, perm_auth_key.id());
+        assert_eq!(expires_at, 1234);
+        // key_id + msg_key + (random + msg_id + seq_no + len + bind_auth_key_inner) padded to 16
+        assert_eq!(&encrypted_message[..8], perm_auth_key_id.to_le_bytes());
+        assert_eq!(encrypted_message.len(), 8 + 16 + 80);
+    }
+
     #[test]
     fn ensure_correct_multi_serialization() {
         let mut buffer = DequeBuffer::with_capacity(0, 0);
@@ -1376,8 +1748,12 @@ mod tests {
             .compression_threshold(None)
             .finish(auth_key());
 
-        assert!(mtproto.push(&mut buffer, REQUEST).is_some());
-        assert!(mtproto.push(&mut buffer, REQUEST_B).is_some());
+        assert!(mtproto
+            .push(&mut buffer, &tl::Blob(REQUEST.into()))
+            .is_some());
+        assert!(mtproto
+            .push(&mut buffer, &tl::Blob(REQUEST_B.into()))
+            .is_some());
         mtproto.finalize_plain(&mut buffer);
         let buffer = &buffer[MESSAGE_PREFIX_LEN..];
 
@@ -1401,19 +1777,32 @@ mod tests {
         ensure_buffer_is_message(&buffer[44..], REQUEST_B, 3);
     }
 
+    #[test]
+    fn ensure_typed_request_serialization() {
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        let request = tl::functions::Ping { ping_id: 1234 };
+
+        assert!(mtproto.push(&mut buffer, &request).is_some());
+        mtproto.finalize_plain(&mut buffer);
+
+        let buffer = &buffer[MESSAGE_PREFIX_LEN..];
+        ensure_buffer_is_message(buffer, &request.to_bytes(), 1);
+    }
+
     #[test]
     fn ensure_correct_single_large_serialization() {
         let mut buffer = DequeBuffer::with_capacity(0, 0);
         let mut mtproto = Encrypted::build()
             .compression_threshold(None)
             .finish(auth_key());
-        let data = vec![0x7f; 768 * 1024];
+        let data = tl::Blob(vec![0x7f; 768 * 1024]);
 
         assert!(mtproto.push(&mut buffer, &data).is_some());
         mtproto.finalize_plain(&mut buffer);
 
         let buffer = &buffer[MESSAGE_PREFIX_LEN..];
-        assert_eq!(buffer.len(), 16 + data.len());
+        assert_eq!(buffer.len(), 16 + data.0.len());
     }
 
     #[test]
@@ -1422,7 +1811,7 @@ mod tests {
         let mut mtproto = Encrypted::build()
             .compression_threshold(None)
             .finish(auth_key());
-        let data = vec![0x7f; 768 * 1024];
+        let data = tl::Blob(vec![0x7f; 768 * 1024]);
 
         assert!(mtproto.push(&mut buffer, &data).is_some());
         assert!(mtproto.push(&mut buffer, &data).is_none());
@@ -1430,7 +1819,7 @@ mod tests {
         // No container should be used, only the `salt` + `client_id` (16 bytes) should count.
         mtproto.finalize_plain(&mut buffer);
         let buffer = &buffer[MESSAGE_PREFIX_LEN..];
-        assert_eq!(buffer.len(), 16 + data.len());
+        assert_eq!(buffer.len(), 16 + data.0.len());
     }
 
     #[test]
@@ -1439,7 +1828,7 @@ mod tests {
         let mut buffer = DequeBuffer::with_capacity(0, 0);
         let mut mtproto = Encrypted::build().finish(auth_key());
 
-        mtproto.push(&mut buffer, &vec![0; 2 * 1024 * 1024]);
+        mtproto.push(&mut buffer, &tl::Blob(vec![0; 2 * 1024 * 1024]));
     }
 
     #[test]
@@ -1448,7 +1837,7 @@ mod tests {
         let mut buffer = DequeBuffer::with_capacity(0, 0);
         let mut mtproto = Encrypted::build().finish(auth_key());
 
-        mtproto.push(&mut buffer, &[1, 2, 3]);
+        mtproto.push(&mut buffer, &tl::Blob(vec![1, 2, 3]));
     }
 
     #[test]
@@ -1459,7 +1848,7 @@ mod tests {
             .compression_threshold(None)
             .finish(auth_key());
 
-        mtproto.push(&mut buffer, &vec![0; 512 * 1024]);
+        mtproto.push(&mut buffer, &tl::Blob(vec![0; 512 * 1024]));
         mtproto.finalize_plain(&mut buffer);
         assert!(!buffer.as_ref().windows(4).any(|w| w == GZIP_PACKED_HEADER));
     }
@@ -1473,7 +1862,7 @@ mod tests {
             let mut mtproto = Encrypted::build()
                 .compression_threshold(Some(768 * 1024))
                 .finish(auth_key());
-            mtproto.push(&mut buffer, &vec![0; 512 * 1024]);
+            mtproto.push(&mut buffer, &tl::Blob(vec![0; 512 * 1024]));
             mtproto.finalize_plain(&mut buffer);
             assert!(!buffer.as_ref().windows(4).any(|w| w == GZIP_PACKED_HEADER));
         }
@@ -1483,7 +1872,7 @@ mod tests {
             let mut mtproto = Encrypted::build()
                 .compression_threshold(Some(256 * 1024))
                 .finish(auth_key());
-            mtproto.push(&mut buffer, &vec![0; 512 * 1024]);
+            mtproto.push(&mut buffer, &tl::Blob(vec![0; 512 * 1024]));
             mtproto.finalize_plain(&mut buffer);
             assert!(buffer.as_ref().windows(4).any(|w| w == GZIP_PACKED_HEADER));
         }
@@ -1491,9 +1880,336 @@ mod tests {
             // The default should compress
             let mut buffer = DequeBuffer::with_capacity(0, 0);
             let mut mtproto = Encrypted::build().finish(auth_key());
-            mtproto.push(&mut buffer, &vec![0; 512 * 1024]);
+            mtproto.push(&mut buffer, &tl::Blob(vec![0; 512 * 1024]));
             mtproto.finalize_plain(&mut buffer);
             assert!(buffer.as_ref().windows(4).any(|w| w == GZIP_PACKED_HEADER));
         }
     }
+
+    /// Feed a message from the server directly to the handlers, as if it had been decrypted.
+    fn receive(mtproto: &mut Encrypted, msg_id: i64, seq_no: i32, body: Vec<u8>) {
+        mtproto
+            .process_message(manual_tl::Message {
+                msg_id,
+                seq_no,
+                body,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn ensure_state_req_is_answered() {
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        receive(&mut mtproto, 100, 1, vec![0; 4]); // requires ack
+        receive(&mut mtproto, 200, 2, vec![0; 4]); // does not require ack
+
+        let body = tl::enums::MsgsStateReq::Req(tl::types::MsgsStateReq {
+            msg_ids: vec![50, 100, 150, 200, 300],
+        })
+        .to_bytes();
+        receive(&mut mtproto, 400, 4, body);
+
+        assert_eq!(mtproto.pending_state_info.len(), 1);
+        let info = &mtproto.pending_state_info[0];
+        assert_eq!(info.req_msg_id, 400);
+        assert_eq!(info.info, vec![1, 4, 2, 4 | 16, 2]);
+
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        assert!(mtproto.finalize(&mut buffer).is_some());
+        assert!(mtproto.pending_state_info.is_empty());
+        assert!(mtproto.pending_ack.is_empty());
+    }
+
+    #[test]
+    fn ensure_detailed_info_acks_received_answer() {
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        receive(&mut mtproto, 100, 2, vec![0; 4]);
+
+        let body = tl::enums::MsgDetailedInfo::Info(tl::types::MsgDetailedInfo {
+            msg_id: 10,
+            answer_msg_id: 100,
+            bytes: 4,
+            status: 0,
+        })
+        .to_bytes();
+        receive(&mut mtproto, 200, 2, body);
+
+        assert_eq!(mtproto.pending_ack, vec![100]);
+        assert!(mtproto.pending_resend.is_empty());
+    }
+
+    #[test]
+    fn ensure_lost_answer_is_requested_again() {
+        let mut mtproto = Encrypted::build().finish(auth_key());
+
+        let body = tl::enums::MsgDetailedInfo::Info(tl::types::MsgDetailedInfo {
+            msg_id: 10,
+            answer_msg_id: 100,
+            bytes: 4,
+            status: 0,
+        })
+        .to_bytes();
+        receive(&mut mtproto, 200, 2, body);
+        assert!(mtproto.pending_ack.is_empty());
+        assert_eq!(mtproto.pending_resend, vec![(100, Some(10))]);
+
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        assert!(mtproto.finalize(&mut buffer).is_some());
+        assert!(mtproto.pending_resend.is_empty());
+        let (&req_msg_id, answers) = mtproto.resend_requests.iter().next().unwrap();
+        assert_eq!(answers, &vec![(100, Some(10))]);
+
+        // The server no longer has the answer, so the request should be reported as lost.
+        let body = tl::enums::MsgsStateInfo::Info(tl::types::MsgsStateInfo {
+            req_msg_id,
+            info: vec![1],
+        })
+        .to_bytes();
+        receive(&mut mtproto, 300, 2, body);
+        assert!(mtproto.resend_requests.is_empty());
+        assert!(matches!(
+            mtproto.deserialization[..],
+            [Deserialization::AnswerLost(MsgId(10))]
+        ));
+    }
+
+    #[test]
+    fn ensure_resend_req_resends() {
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        let body = tl::enums::MsgResendReq::Req(tl::types::MsgResendReq {
+            msg_ids: vec![10, 20],
+        })
+        .to_bytes();
+        receive(&mut mtproto, 100, 2, body);
+
+        assert!(matches!(
+            mtproto.deserialization[..],
+            [
+                Deserialization::Resend(MsgId(10)),
+                Deserialization::Resend(MsgId(20))
+            ]
+        ));
+    }
+
+    #[test]
+    fn ensure_acks_are_reported() {
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        let body = tl::enums::MsgsAck::Ack(tl::types::MsgsAck {
+            msg_ids: vec![10, 20],
+        })
+        .to_bytes();
+        receive(&mut mtproto, 100, 2, body);
+
+        assert!(matches!(
+            mtproto.deserialization[..],
+            [
+                Deserialization::Ack(MsgId(10)),
+                Deserialization::Ack(MsgId(20))
+            ]
+        ));
+    }
+
+    #[test]
+    fn ensure_msgs_all_info_resends_unreceived() {
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        let body = tl::enums::MsgsAllInfo::Info(tl::types::MsgsAllInfo {
+            msg_ids: vec![10, 20, 30],
+            info: vec![4, 2, 3],
+        })
+        .to_bytes();
+        receive(&mut mtproto, 100, 2, body);
+
+        assert!(matches!(
+            mtproto.deserialization[..],
+            [Deserialization::Resend(MsgId(20))]
+        ));
+    }
+
+    #[test]
+    fn ensure_destroy_session_result_is_matched() {
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        let request = tl::functions::DestroySession { session_id: 1234 };
+        let msg_id = mtproto.push(&mut buffer, &request).unwrap();
+
+        let body = tl::enums::DestroySessionRes::DestroySessionOk(tl::types::DestroySessionOk {
+            session_id: 1234,
+        })
+        .to_bytes();
+        receive(&mut mtproto, 100, 2, body.clone());
+
+        match &mtproto.deserialization[..] {
+            [Deserialization::RpcResult(result)] => {
+                assert_eq!(result.msg_id, msg_id);
+                assert_eq!(result.body, body);
+            }
+            _ => panic!("destroy session result was not matched"),
+        }
+    }
+
+    #[test]
+    fn ensure_requests_and_responses_are_recorded() {
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        let recording = Arc::new(Recording::new());
+        mtproto.set_recorder(Some(recording.clone()));
+
+        // Large enough to be compressed, but recorded as it was serialized.
+        let request = tl::Blob(vec![0x7f; 1024]);
+        let msg_id = mtproto.push(&mut buffer, &request).unwrap();
+
+        let mut body = Vec::new();
+        manual_tl::RpcResult::CONSTRUCTOR_ID.serialize(&mut body);
+        msg_id.0.serialize(&mut body);
+        body.extend(REQUEST);
+
+        let mut payload = DequeBuffer::with_capacity(0, 0);
+        payload.extend(0i64.to_le_bytes());
+        payload.extend(mtproto.client_id.to_le_bytes());
+        payload.extend(101i64.to_le_bytes());
+        payload.extend(1i32.to_le_bytes());
+        payload.extend((body.len() as i32).to_le_bytes());
+        payload.extend(body);
+        grammers_crypto::encrypt_server_data_v2(&mut payload, &AuthKey::from_bytes(auth_key()));
+        mtproto.deserialize(&payload[..]).unwrap();
+
+        assert_eq!(
+            recording.records(),
+            [
+                Record::Request {
+                    msg_id,
+                    body: request.0
+                },
+                Record::Result {
+                    msg_id,
+                    body: REQUEST.to_vec()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ensure_new_session_is_signaled() {
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        let body = tl::enums::NewSession::Created(tl::types::NewSessionCreated {
+            first_msg_id: 10,
+            unique_id: 20,
+            server_salt: 30,
+        })
+        .to_bytes();
+        receive(&mut mtproto, 100, 1, body);
+
+        assert_eq!(mtproto.get_current_salt(), 30);
+        assert!(matches!(
+            mtproto.deserialization[..],
+            [Deserialization::NewSession(MsgId(10))]
+        ));
+    }
+
+    #[test]
+    fn ensure_updates_in_result_are_own() {
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        let updates = tl::enums::Updates::Updates(tl::types::Updates {
+            updates: Vec::new(),
+            users: Vec::new(),
+            chats: Vec::new(),
+            date: 0,
+            seq: 0,
+        })
+        .to_bytes();
+        let mut body = manual_tl::RpcResult::CONSTRUCTOR_ID.to_bytes();
+        body.extend(20i64.to_bytes());
+        body.extend(&updates);
+        receive(&mut mtproto, 200, 1, body);
+
+        match &mtproto.deserialization[..] {
+            [Deserialization::OwnUpdate(update), Deserialization::RpcResult(result)] => {
+                assert_eq!(update, &updates);
+                assert_eq!(result.msg_id, MsgId(20));
+                assert_eq!(result.body, updates);
+            }
+            _ => panic!("updates in the result were not marked as own"),
+        }
+    }
+
+    #[test]
+    fn ensure_bad_msg_starts_fresh_session() {
+        for code in [16, 17, 32, 33] {
+            let mut buffer = DequeBuffer::with_capacity(0, 0);
+            let mut mtproto = Encrypted::build().finish(auth_key());
+            let msg_id = mtproto
+                .push(&mut buffer, &tl::Blob(REQUEST.into()))
+                .unwrap();
+            mtproto.finalize_plain(&mut buffer);
+            receive(&mut mtproto, 50, 1, vec![0; 4]);
+            mtproto.deserialization.clear();
+
+            let client_id = mtproto.client_id;
+            assert_ne!(mtproto.sequence, 0);
+            assert!(!mtproto.pending_ack.is_empty());
+
+            // The server is 1000 seconds ahead, which its message ID reflects.
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+            let server_msg_id = (now + 1000) << 32 | 1;
+
+            let body = tl::enums::BadMsgNotification::Notification(tl::types::BadMsgNotification {
+                bad_msg_id: msg_id.0,
+                bad_msg_seqno: 1,
+                error_code: code,
+            })
+            .to_bytes();
+            receive(&mut mtproto, server_msg_id, 2, body);
+
+            assert_ne!(mtproto.client_id, client_id, "code {code}");
+            assert_eq!(mtproto.previous_client_id, Some(client_id));
+            assert_eq!(mtproto.sequence, 0);
+            assert_eq!(mtproto.last_msg_id, 0);
+            assert!(mtproto.pending_ack.is_empty());
+            assert!((999..=1001).contains(&mtproto.time_offset));
+            match &mtproto.deserialization[..] {
+                [Deserialization::BadMessage(bad_msg)] => {
+                    assert_eq!(bad_msg.msg_id, msg_id);
+                    assert_eq!(bad_msg.code, code);
+                    assert!(bad_msg.retryable());
+                    assert!(bad_msg.session_reset());
+                }
+                _ => panic!("bad message {code} was not notified"),
+            }
+
+            // New messages use the new session and the corrected time.
+            let mut buffer = DequeBuffer::with_capacity(0, 0);
+            let new_msg_id = mtproto
+                .push(&mut buffer, &tl::Blob(REQUEST.into()))
+                .unwrap();
+            mtproto.finalize_plain(&mut buffer);
+            assert_eq!(&buffer[8..16], mtproto.client_id.to_le_bytes());
+            assert!(new_msg_id.0 >> 32 >= now + 999);
+        }
+    }
+
+    #[test]
+    fn ensure_other_bad_msg_keeps_session() {
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        let mut mtproto = Encrypted::build().finish(auth_key());
+        let msg_id = mtproto
+            .push(&mut buffer, &tl::Blob(REQUEST.into()))
+            .unwrap();
+        mtproto.finalize_plain(&mut buffer);
+
+        let client_id = mtproto.client_id;
+        let body = tl::enums::BadMsgNotification::Notification(tl::types::BadMsgNotification {
+            bad_msg_id: msg_id.0,
+            bad_msg_seqno: 1,
+            error_code: 20,
+        })
+        .to_bytes();
+        receive(&mut mtproto, 100, 2, body);
+
+        assert_eq!(mtproto.client_id, client_id);
+        assert_eq!(mtproto.previous_client_id, None);
+        assert_ne!(mtproto.sequence, 0);
+    }
 }
@@ -18,6 +18,7 @@
 //! [Mobile Transport Protocol]: https://core.telegram.org/mtproto/description
 mod encrypted;
 mod plain;
+mod recording;
 
 use crate::MsgId;
 use crypto::DequeBuffer;
@@ -28,7 +29,9 @@ pub use encrypted::{
 use grammers_crypto as crypto;
 use grammers_tl_types as tl;
 pub use plain::Plain;
+pub use recording::{Record, Recorder, Recording, Replay};
 use std::fmt;
+use std::sync::Arc;
 
 pub struct RpcResult {
     pub msg_id: MsgId,
@@ -51,12 +54,30 @@ pub struct DeserializationFailure {
 }
 
 /// Results from the deserialization of a response.
+///
+/// More variants may be added in the future, so matching on it requires a wildcard arm.
+#[non_exhaustive]
 pub enum Deserialization {
     Update(Vec<u8>),
+    /// Updates contained in the result of a request made by this client, as opposed to those
+    /// pushed by the server.
+    OwnUpdate(Vec<u8>),
     RpcResult(RpcResult),
     RpcError(RpcResultError),
     BadMessage(BadMessage),
     Failure(DeserializationFailure),
+    /// The server did not receive the message with this identifier, so it should be sent again.
+    Resend(MsgId),
+    /// The server received the message with this identifier, but its answer was lost. It can only
+    /// be sent again if executing it twice is not a problem.
+    AnswerLost(MsgId),
+    /// The server acknowledged receiving the message with this identifier, so it may have been
+    /// executed even if its answer never arrives.
+    Ack(MsgId),
+    /// The server created a new session, starting with the message with this identifier.
+    /// Updates that occurred while the old session was in use may never be pushed, so they
+    /// should be fetched manually.
+    NewSession(MsgId),
 }
 
 impl BadMessage {
@@ -79,11 +100,17 @@ impl BadMessage {
     }
 
     pub fn retryable(&self) -> bool {
-        [16, 17, 48].contains(&self.code)
+        [16, 17, 32, 33, 48].contains(&self.code)
     }
 
     pub fn fatal(&self) -> bool {
-        !self.retryable() && ![32, 33].contains(&self.code)
+        !self.retryable()
+    }
+
+    /// Whether a fresh session was started to recover from this error, in which case the
+    /// answers to the messages sent in the previous session will never arrive.
+    pub fn session_reset(&self) -> bool {
+        [16, 17, 32, 33].contains(&self.code)
     }
 }
 
@@ -171,6 +198,9 @@ pub trait Mtp {
     /// Serializes one request to the input buffer.
     /// The same buffer should be used until `finalize` is called.
     ///
+    /// The request is serialized in-place, so it's never copied around. If it has already been
+    /// serialized, it can still be pushed by wrapping its bytes in a [`tl::Blob`].
+    ///
     /// Returns the message ID assigned the request if it was serialized, or `None` if the buffer
     /// is full and cannot hold more requests.
     ///
@@ -188,7 +218,11 @@ pub trait Mtp {
     ///
     /// The definition of "too large" is roughly 1MB, so as long as the
     /// payload is below that mark, it's safe to call.
-    fn push(&mut self, buffer: &mut DequeBuffer<u8>, request: &[u8]) -> Option<MsgId>;
+    fn push<R: tl::RemoteCall>(
+        &mut self,
+        buffer: &mut DequeBuffer<u8>,
+        request: &R,
+    ) -> Option<MsgId>;
 
     /// Finalizes the buffer of requests.
     ///
@@ -206,4 +240,12 @@ pub trait Mtp {
 
     /// Reset the state, as if a new instance was just created.
     fn reset(&mut self);
+
+    /// Report the requests pushed and the responses deserialized from now on to the recorder,
+    /// or stop reporting them if `None`.
+    ///
+    /// Implementations with nothing worth recording may ignore it, which is the default.
+    fn set_recorder(&mut self, recorder: Option<Arc<dyn Recorder>>) {
+        let _ = recorder;
+    }
 }
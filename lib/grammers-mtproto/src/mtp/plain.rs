@@ -8,7 +8,7 @@
 use super::{Deserialization, DeserializeError, Mtp, RpcResult};
 use crate::MsgId;
 use grammers_crypto::DequeBuffer;
-use grammers_tl_types::{Cursor, Deserializable, Serializable};
+use grammers_tl_types::{Cursor, Deserializable, RemoteCall, Serializable};
 
 /// An implementation of the [Mobile Transport Protocol] for plaintext
 /// (unencrypted) messages.
@@ -45,7 +45,7 @@ impl Mtp for Plain {
     /// the authorization key itself.
     ///
     /// [unencrypted messages]: https://core.telegram.org/mtproto/description#unencrypted-message
-    fn push(&mut self, buffer: &mut DequeBuffer<u8>, request: &[u8]) -> Option<MsgId> {
+    fn push<R: RemoteCall>(&mut self, buffer: &mut DequeBuffer<u8>, request: &R) -> Option<MsgId> {
         if !buffer.is_empty() {
             return None;
         }
@@ -57,8 +57,11 @@ impl Mtp for Plain {
         // no need to generate a valid `msg_id`, it seems. Just use `0`.
         0i64.serialize(buffer); // message_id
 
-        (request.len() as i32).serialize(buffer); // message_data_length
-        buffer.extend(request); // message_data
+        0i32.serialize(buffer); // message_data_length, set once the request is serialized
+        request.serialize(buffer); // message_data
+
+        let len = (buffer.len() - 20) as i32;
+        buffer[16..20].copy_from_slice(&len.to_le_bytes());
 
         Some(MsgId(0))
     }
@@ -118,6 +121,7 @@ impl Mtp for Plain {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use grammers_tl_types as tl;
 
     const REQUEST: &[u8] = b"Hey!";
 
@@ -126,7 +130,7 @@ mod tests {
         let mut buffer = DequeBuffer::with_capacity(0, 0);
         let mut mtp = Plain::new();
 
-        mtp.push(&mut buffer, REQUEST);
+        mtp.push(&mut buffer, &tl::Blob(REQUEST.to_vec()));
         mtp.finalize(&mut buffer);
         assert_eq!(&buffer[buffer.len() - REQUEST.len()..], REQUEST);
     }
@@ -136,7 +140,18 @@ mod tests {
         let mut buffer = DequeBuffer::with_capacity(0, 0);
         let mut mtp = Plain::new();
 
-        assert!(mtp.push(&mut buffer, REQUEST).is_some());
-        assert!(mtp.push(&mut buffer, REQUEST).is_none());
+        assert!(mtp.push(&mut buffer, &tl::Blob(REQUEST.to_vec())).is_some());
+        assert!(mtp.push(&mut buffer, &tl::Blob(REQUEST.to_vec())).is_none());
+    }
+
+    #[test]
+    fn ensure_request_length_is_set() {
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        let mut mtp = Plain::new();
+
+        let request = tl::functions::ReqPqMulti { nonce: [0; 16] };
+        mtp.push(&mut buffer, &request);
+        assert_eq!(&buffer[16..20], &20i32.to_le_bytes());
+        assert_eq!(&buffer[20..], &request.to_bytes()[..]);
     }
 }
@@ -0,0 +1,433 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Recording of the decrypted traffic going through a [`Mtp`], so that it can be inspected or
+//! replayed later on.
+//!
+//! Requests carrying credentials, such as login codes, passwords or bot tokens, are recorded
+//! without their parameters. Everything else is recorded as-is, including results and updates
+//! which may contain private data (the message with the login code sent by Telegram, or tokens
+//! to sign in again), so recordings must be kept as secret as the session itself.
+//!
+//! [`Mtp`]: super::Mtp
+use super::encrypted::UPDATE_IDS;
+use super::{Deserialization, RpcResult, RpcResultError};
+use crate::MsgId;
+use grammers_tl_types::{self as tl, Cursor, Deserializable, Identifiable, Serializable};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// Tags preceding each record when serialized.
+const REQUEST_TAG: u32 = 1;
+const RESULT_TAG: u32 = 2;
+const UPDATES_TAG: u32 = 3;
+
+/// Requests whose parameters carry credentials, and are recorded without them.
+const REDACTED_REQUESTS: [u32; 14] = [
+    tl::functions::auth::SendCode::CONSTRUCTOR_ID,
+    tl::functions::auth::SignIn::CONSTRUCTOR_ID,
+    tl::functions::auth::CheckPassword::CONSTRUCTOR_ID,
+    tl::functions::auth::ImportBotAuthorization::CONSTRUCTOR_ID,
+    tl::functions::auth::ImportAuthorization::CONSTRUCTOR_ID,
+    tl::functions::auth::ExportLoginToken::CONSTRUCTOR_ID,
+    tl::functions::auth::ImportLoginToken::CONSTRUCTOR_ID,
+    tl::functions::auth::RecoverPassword::CONSTRUCTOR_ID,
+    tl::functions::auth::CheckRecoveryPassword::CONSTRUCTOR_ID,
+    tl::functions::account::GetPasswordSettings::CONSTRUCTOR_ID,
+    tl::functions::account::UpdatePasswordSettings::CONSTRUCTOR_ID,
+    tl::functions::account::ConfirmPasswordEmail::CONSTRUCTOR_ID,
+    tl::functions::account::VerifyEmail::CONSTRUCTOR_ID,
+    tl::functions::account::SendVerifyEmailCode::CONSTRUCTOR_ID,
+];
+
+/// A single message in a recording.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Record {
+    /// A request pushed to be sent, along with the message identifier it was given. The body is
+    /// the serialized request, before any compression.
+    ///
+    /// The body of requests carrying credentials is only their constructor identifier.
+    Request { msg_id: MsgId, body: Vec<u8> },
+    /// The result of the request with the given message identifier. If the request failed, the
+    /// body is the serialized `rpc_error` instead.
+    Result { msg_id: MsgId, body: Vec<u8> },
+    /// Updates pushed by the server, not in response to any request.
+    Updates { body: Vec<u8> },
+}
+
+/// A hook to be notified of the requests pushed to, and the responses deserialized by, a
+/// [`Mtp`] as they happen.
+///
+/// Service messages used internally by the protocol are never recorded.
+///
+/// [`Mtp`]: super::Mtp
+pub trait Recorder: Send + Sync {
+    fn record(&self, record: Record);
+}
+
+/// A [`Recorder`] which keeps every record in memory, to be saved to a file when done.
+#[derive(Default)]
+pub struct Recording {
+    records: Mutex<Vec<Record>>,
+}
+
+/// Answers requests with the results found in a [`Recording`], without sending them anywhere.
+pub struct Replay {
+    // Records are taken out of their slot once replayed.
+    records: Vec<Option<Record>>,
+}
+
+impl Record {
+    /// The record of a request, leaving out the parameters of those carrying credentials.
+    pub(crate) fn request(msg_id: MsgId, body: &[u8]) -> Self {
+        let body = match u32::from_bytes(unwrap_query(body)) {
+            Ok(id) if REDACTED_REQUESTS.contains(&id) => id.to_bytes(),
+            _ => body.to_vec(),
+        };
+        Self::Request { msg_id, body }
+    }
+
+    /// Convert a deserialization result into its record, if it is worth recording.
+    ///
+    /// Updates that belong to a result are not recorded on their own, because the result
+    /// already contains them.
+    pub(crate) fn from_deserialization(result: &Deserialization) -> Option<Self> {
+        match result {
+            Deserialization::Update(body) => Some(Self::Updates { body: body.clone() }),
+            Deserialization::RpcResult(result) => Some(Self::Result {
+                msg_id: result.msg_id,
+                body: result.body.clone(),
+            }),
+            Deserialization::RpcError(RpcResultError { msg_id, error }) => Some(Self::Result {
+                msg_id: *msg_id,
+                body: tl::enums::RpcError::Error(error.clone()).to_bytes(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The records made so far, in the order they happened.
+    pub fn records(&self) -> Vec<Record> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Load a recording previously serialized with [`Recording::save`].
+    pub fn load(data: &[u8]) -> Result<Self, tl::deserialize::Error> {
+        let mut cursor = Cursor::from_slice(data);
+        let mut records = Vec::new();
+        while cursor.pos() < data.len() {
+            records.push(match u32::deserialize(&mut cursor)? {
+                REQUEST_TAG => Record::Request {
+                    msg_id: MsgId(i64::deserialize(&mut cursor)?),
+                    body: Vec::<u8>::deserialize(&mut cursor)?,
+                },
+                RESULT_TAG => Record::Result {
+                    msg_id: MsgId(i64::deserialize(&mut cursor)?),
+                    body: Vec::<u8>::deserialize(&mut cursor)?,
+                },
+                UPDATES_TAG => Record::Updates {
+                    body: Vec::<u8>::deserialize(&mut cursor)?,
+                },
+                id => return Err(tl::deserialize::Error::UnexpectedConstructor { id }),
+            });
+        }
+
+        Ok(Self {
+            records: Mutex::new(records),
+        })
+    }
+
+    /// Load a recording from a file.
+    pub fn load_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut data = Vec::new();
+        File::open(path.as_ref())?.read_to_end(&mut data)?;
+
+        Self::load(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    #[must_use]
+    pub fn save(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for record in self.records.lock().unwrap().iter() {
+            match record {
+                Record::Request { msg_id, body } => {
+                    REQUEST_TAG.serialize(&mut buffer);
+                    msg_id.0.serialize(&mut buffer);
+                    body.serialize(&mut buffer);
+                }
+                Record::Result { msg_id, body } => {
+                    RESULT_TAG.serialize(&mut buffer);
+                    msg_id.0.serialize(&mut buffer);
+                    body.serialize(&mut buffer);
+                }
+                Record::Updates { body } => {
+                    UPDATES_TAG.serialize(&mut buffer);
+                    body.serialize(&mut buffer);
+                }
+            }
+        }
+        buffer
+    }
+
+    /// Save the recording to a file, replacing its contents.
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = File::create(path.as_ref())?;
+        file.write_all(&self.save())?;
+        file.sync_data()
+    }
+}
+
+impl Replay {
+    pub fn new(recording: &Recording) -> Self {
+        Self {
+            records: recording.records().into_iter().map(Some).collect(),
+        }
+    }
+
+    /// Answer the request with the result recorded for the first request of the same type, even
+    /// if their parameters differ, as if it had just been deserialized. The updates recorded
+    /// after it, up until the next result, follow.
+    ///
+    /// Returns the message identifier the answer refers to, or `None` if there's no result left
+    /// for this type of request. Each recorded result is only used once.
+    pub fn answer(&mut self, request: &[u8]) -> Option<(MsgId, Vec<Deserialization>)> {
+        let constructor_id = u32::from_bytes(unwrap_query(request)).ok()?;
+        let (request, result) = self.records.iter().enumerate().find_map(|(i, record)| {
+            let msg_id = match record {
+                Some(Record::Request { msg_id, body })
+                    if u32::from_bytes(unwrap_query(body)) == Ok(constructor_id) =>
+                {
+                    msg_id
+                }
+                _ => return None,
+            };
+            let result = self.records.iter().position(
+                |record| matches!(record, Some(Record::Result { msg_id: id, .. }) if id == msg_id),
+            )?;
+            Some((i, result))
+        })?;
+
+        self.records[request] = None;
+        let Some(Record::Result { msg_id, body }) = self.records[result].take() else {
+            unreachable!();
+        };
+
+        let mut answer = Vec::new();
+        match u32::from_bytes(&body) {
+            Ok(tl::types::RpcError::CONSTRUCTOR_ID) => {
+                let tl::enums::RpcError::Error(error) =
+                    tl::enums::RpcError::from_bytes(&body).ok()?;
+                answer.push(Deserialization::RpcError(RpcResultError { msg_id, error }));
+            }
+            Ok(id) => {
+                if UPDATE_IDS.contains(&id) {
+                    answer.push(Deserialization::OwnUpdate(body.clone()));
+                }
+                answer.push(Deserialization::RpcResult(RpcResult { msg_id, body }));
+            }
+            Err(_) => answer.push(Deserialization::RpcResult(RpcResult { msg_id, body })),
+        }
+
+        let next_result = self.records[result..]
+            .iter()
+            .position(|record| matches!(record, Some(Record::Result { .. })))
+            .map_or(self.records.len(), |i| result + i);
+        for record in &mut self.records[..next_result] {
+            if let Some(Record::Updates { .. }) = record {
+                let Some(Record::Updates { body }) = record.take() else {
+                    unreachable!();
+                };
+                answer.push(Deserialization::Update(body));
+            }
+        }
+
+        Some((msg_id, answer))
+    }
+}
+
+/// Strip the wrappers that only change how a query is invoked, such as `invokeWithLayer`.
+fn unwrap_query(mut body: &[u8]) -> &[u8] {
+    loop {
+        let mut cursor = Cursor::from_slice(body);
+        let unwrapped = match u32::deserialize(&mut cursor) {
+            Ok(tl::functions::InvokeWithLayer::<tl::Blob>::CONSTRUCTOR_ID) => {
+                i32::deserialize(&mut cursor).is_ok()
+            }
+            Ok(tl::functions::InitConnection::<tl::Blob>::CONSTRUCTOR_ID) => {
+                skip_init_connection(&mut cursor).is_ok()
+            }
+            Ok(tl::functions::InvokeWithoutUpdates::<tl::Blob>::CONSTRUCTOR_ID) => true,
+            Ok(tl::functions::InvokeAfterMsg::<tl::Blob>::CONSTRUCTOR_ID) => {
+                i64::deserialize(&mut cursor).is_ok()
+            }
+            Ok(tl::functions::InvokeAfterMsgs::<tl::Blob>::CONSTRUCTOR_ID) => {
+                Vec::<i64>::deserialize(&mut cursor).is_ok()
+            }
+            _ => false,
+        };
+        if !unwrapped {
+            break body;
+        }
+        body = &body[cursor.pos()..];
+    }
+}
+
+/// Skip the parameters of `initConnection` up until its query.
+fn skip_init_connection(cursor: &mut Cursor) -> Result<(), tl::deserialize::Error> {
+    let flags = u32::deserialize(cursor)?;
+    i32::deserialize(cursor)?; // api_id
+    for _ in 0..6 {
+        // device_model, system_version, app_version, system_lang_code, lang_pack, lang_code
+        String::deserialize(cursor)?;
+    }
+    if flags & 1 != 0 {
+        tl::enums::InputClientProxy::deserialize(cursor)?;
+    }
+    if flags & 2 != 0 {
+        tl::enums::Jsonvalue::deserialize(cursor)?;
+    }
+    Ok(())
+}
+
+impl Recorder for Recording {
+    fn record(&self, record: Record) {
+        self.records.lock().unwrap().push(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let recording = Recording::new();
+        recording.record(Record::Request {
+            msg_id: MsgId(4),
+            body: vec![1, 2, 3, 4],
+        });
+        recording.record(Record::Updates { body: vec![5; 8] });
+        recording.record(Record::Result {
+            msg_id: MsgId(4),
+            body: vec![6; 300],
+        });
+
+        let loaded = Recording::load(&recording.save()).unwrap();
+        assert_eq!(loaded.records(), recording.records());
+    }
+
+    #[test]
+    fn load_invalid() {
+        assert_eq!(
+            Recording::load(&[9, 0, 0, 0]).err(),
+            Some(tl::deserialize::Error::UnexpectedConstructor { id: 9 })
+        );
+        assert_eq!(
+            Recording::load(&[1, 0, 0, 0, 4]).err(),
+            Some(tl::deserialize::Error::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn credentials_are_redacted() {
+        let sign_in = tl::functions::auth::SignIn {
+            phone_number: "+1234".into(),
+            phone_code_hash: "hash".into(),
+            phone_code: Some("12345".into()),
+            email_verification: None,
+        };
+        assert_eq!(
+            Record::request(MsgId(4), &sign_in.to_bytes()),
+            Record::Request {
+                msg_id: MsgId(4),
+                body: tl::functions::auth::SignIn::CONSTRUCTOR_ID.to_bytes(),
+            }
+        );
+
+        let get_config = tl::functions::help::GetConfig {}.to_bytes();
+        assert_eq!(
+            Record::request(MsgId(4), &get_config),
+            Record::Request {
+                msg_id: MsgId(4),
+                body: get_config,
+            }
+        );
+    }
+
+    #[test]
+    fn replay_answers_by_request_type() {
+        let get_config = tl::functions::help::GetConfig {}.to_bytes();
+        let wrapped = tl::functions::InvokeWithLayer {
+            layer: tl::LAYER,
+            query: tl::functions::InitConnection {
+                api_id: 1,
+                device_model: "device".into(),
+                system_version: "system".into(),
+                app_version: "app".into(),
+                system_lang_code: "en".into(),
+                lang_pack: "".into(),
+                lang_code: "en".into(),
+                proxy: None,
+                params: None,
+                query: tl::functions::help::GetConfig {},
+            },
+        }
+        .to_bytes();
+        let error = tl::types::RpcError {
+            error_code: 400,
+            error_message: "BAD".into(),
+        };
+
+        let recording = Recording::new();
+        recording.record(Record::Request {
+            msg_id: MsgId(4),
+            body: wrapped,
+        });
+        recording.record(Record::Request {
+            msg_id: MsgId(8),
+            body: get_config.clone(),
+        });
+        recording.record(Record::Result {
+            msg_id: MsgId(4),
+            body: vec![1; 4],
+        });
+        recording.record(Record::Updates { body: vec![2; 4] });
+        recording.record(Record::Result {
+            msg_id: MsgId(8),
+            body: tl::enums::RpcError::Error(error.clone()).to_bytes(),
+        });
+
+        let mut replay = Replay::new(&recording);
+        let (msg_id, answer) = replay.answer(&get_config).unwrap();
+        assert_eq!(msg_id, MsgId(4));
+        match &answer[..] {
+            [Deserialization::RpcResult(result), Deserialization::Update(update)] => {
+                assert_eq!(result.msg_id, MsgId(4));
+                assert_eq!(result.body, [1; 4]);
+                assert_eq!(update, &[2; 4]);
+            }
+            _ => panic!("wrong answer to the first request"),
+        }
+
+        let (msg_id, answer) = replay.answer(&get_config).unwrap();
+        assert_eq!(msg_id, MsgId(8));
+        match &answer[..] {
+            [Deserialization::RpcError(e)] => assert_eq!(e.error, error),
+            _ => panic!("wrong answer to the second request"),
+        }
+        assert!(replay.answer(&get_config).is_none());
+    }
+}
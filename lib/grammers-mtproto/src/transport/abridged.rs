@@ -63,7 +63,7 @@ impl Transport for Abridged {
         }
     }
 
-    fn unpack(&mut self, buffer: &[u8]) -> Result<UnpackedOffset, Error> {
+    fn unpack(&mut self, buffer: &mut [u8]) -> Result<UnpackedOffset, Error> {
         if buffer.is_empty() {
             return Err(Error::MissingBytes);
         }
@@ -87,7 +87,9 @@ impl Transport for Abridged {
             return Err(Error::MissingBytes);
         }
 
-        if header_len == 1 && len >= 4 {
+        // Transport errors are sent on their own, so longer packets are never errors even if
+        // their first word happens to be negative (such as the `auth_key_id` of some messages).
+        if header_len == 1 && len == 4 {
             let data = i32::from_le_bytes(buffer[1..5].try_into().unwrap());
             if data < 0 {
                 return Err(Error::BadStatus {
@@ -110,6 +112,11 @@ impl Transport for Abridged {
         log::info!("resetting sending of header in abridged transport");
         self.init = false;
     }
+
+    fn obfuscated_tag(&mut self) -> Option<[u8; 4]> {
+        self.init = true;
+        Some([0xef; 4])
+    }
 }
 
 #[cfg(test)]
@@ -160,7 +167,7 @@ mod tests {
         let mut transport = Abridged::new();
         let mut buffer = DequeBuffer::with_capacity(1, 0);
         buffer.extend([1]);
-        assert_eq!(transport.unpack(&buffer[..]), Err(Error::MissingBytes));
+        assert_eq!(transport.unpack(&mut buffer[..]), Err(Error::MissingBytes));
     }
 
     #[test]
@@ -169,7 +176,7 @@ mod tests {
         let orig = buffer.clone();
         transport.pack(&mut buffer);
         let n = 1; // init byte
-        let offset = transport.unpack(&buffer[n..][..]).unwrap();
+        let offset = transport.unpack(&mut buffer[n..]).unwrap();
         assert_eq!(&buffer[n..][offset.data_start..offset.data_end], &orig[..]);
     }
 
@@ -187,12 +194,12 @@ mod tests {
         transport.pack(&mut buffer);
         two_buffer.extend(&buffer[..]);
 
-        let offset = transport.unpack(&two_buffer[..]).unwrap();
+        let offset = transport.unpack(&mut two_buffer[..]).unwrap();
         assert_eq!(&buffer[offset.data_start..offset.data_end], &orig[..]);
         assert_eq!(offset.next_offset, single_size);
 
         let n = offset.next_offset;
-        let offset = transport.unpack(&two_buffer[n..]).unwrap();
+        let offset = transport.unpack(&mut two_buffer[n..]).unwrap();
         assert_eq!(&buffer[offset.data_start..offset.data_end], &orig[..]);
     }
 
@@ -202,7 +209,7 @@ mod tests {
         let orig = buffer.clone();
         transport.pack(&mut buffer);
         let n = 1; // init byte
-        let offset = transport.unpack(&buffer[n..]).unwrap();
+        let offset = transport.unpack(&mut buffer[n..]).unwrap();
         assert_eq!(&buffer[n..][offset.data_start..offset.data_end], &orig[..]);
     }
 
@@ -214,8 +221,20 @@ mod tests {
         buffer.extend(&(-404_i32).to_le_bytes());
 
         assert_eq!(
-            transport.unpack(&buffer[..]),
+            transport.unpack(&mut buffer[..]),
             Err(Error::BadStatus { status: 404 })
         );
     }
+
+    #[test]
+    fn unpack_negative_message() {
+        let mut transport = Abridged::new();
+        let mut buffer = DequeBuffer::with_capacity(9, 0);
+        buffer.push(2u8);
+        buffer.extend(&(-404_i32).to_le_bytes());
+        buffer.extend(&(1_i32).to_le_bytes());
+
+        let offset = transport.unpack(&mut buffer[..]).unwrap();
+        assert_eq!(&buffer[offset.data_start..offset.data_end], &buffer[1..]);
+    }
 }
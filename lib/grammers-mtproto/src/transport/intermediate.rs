@@ -49,13 +49,13 @@ impl Transport for Intermediate {
         }
     }
 
-    fn unpack(&mut self, buffer: &[u8]) -> Result<UnpackedOffset, Error> {
+    fn unpack(&mut self, buffer: &mut [u8]) -> Result<UnpackedOffset, Error> {
         if buffer.len() < 4 {
             return Err(Error::MissingBytes);
         }
 
         let len = i32::from_le_bytes(buffer[0..4].try_into().unwrap());
-        if (buffer.len() as i32) < len {
+        if (buffer.len() as i32) < 4 + len {
             return Err(Error::MissingBytes);
         }
 
@@ -82,6 +82,11 @@ impl Transport for Intermediate {
         log::info!("resetting sending of header in intermediate transport");
         self.init = false;
     }
+
+    fn obfuscated_tag(&mut self) -> Option<[u8; 4]> {
+        self.init = true;
+        Some([0xee; 4])
+    }
 }
 
 #[cfg(test)]
@@ -123,7 +128,21 @@ mod tests {
         let mut transport = Intermediate::new();
         let mut buffer = DequeBuffer::with_capacity(1, 0);
         buffer.extend([1]);
-        assert_eq!(transport.unpack(&buffer[..],), Err(Error::MissingBytes));
+        assert_eq!(transport.unpack(&mut buffer[..]), Err(Error::MissingBytes));
+    }
+
+    #[test]
+    fn unpack_incomplete() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        transport.pack(&mut buffer);
+        let n = 4; // init bytes
+
+        // The length prefix is not part of the length, so the last bytes are still missing.
+        let end = buffer.len() - 4;
+        assert_eq!(
+            transport.unpack(&mut buffer[n..end]),
+            Err(Error::MissingBytes)
+        );
     }
 
     #[test]
@@ -132,7 +151,7 @@ mod tests {
         let orig = buffer.clone();
         transport.pack(&mut buffer);
         let n = 4; // init bytes
-        let offset = transport.unpack(&buffer[n..]).unwrap();
+        let offset = transport.unpack(&mut buffer[n..]).unwrap();
         assert_eq!(&buffer[n..][offset.data_start..offset.data_end], &orig[..]);
     }
 
@@ -150,12 +169,12 @@ mod tests {
         transport.pack(&mut buffer);
         two_buffer.extend(&buffer[..]);
 
-        let offset = transport.unpack(&two_buffer[..]).unwrap();
+        let offset = transport.unpack(&mut two_buffer[..]).unwrap();
         assert_eq!(&buffer[offset.data_start..offset.data_end], &orig[..]);
         assert_eq!(offset.next_offset, single_size);
 
         let n = offset.next_offset;
-        let offset = transport.unpack(&two_buffer[n..]).unwrap();
+        let offset = transport.unpack(&mut two_buffer[n..]).unwrap();
         assert_eq!(&buffer[offset.data_start..offset.data_end], &orig[..]);
     }
 
@@ -167,7 +186,7 @@ mod tests {
         buffer.extend(&(-404_i32).to_le_bytes());
 
         assert_eq!(
-            transport.unpack(&buffer[..]),
+            transport.unpack(&mut buffer[..]),
             Err(Error::BadStatus { status: 404 })
         );
     }
@@ -14,11 +14,15 @@
 mod abridged;
 mod full;
 mod intermediate;
+mod obfuscated;
+mod padded_intermediate;
 
 pub use abridged::Abridged;
 pub use full::Full;
 use grammers_crypto::DequeBuffer;
 pub use intermediate::Intermediate;
+pub use obfuscated::Obfuscated;
+pub use padded_intermediate::PaddedIntermediate;
 use std::fmt;
 
 /// The error type reported by the different transports when something is wrong.
@@ -91,8 +95,39 @@ pub trait Transport {
     fn pack(&mut self, buffer: &mut DequeBuffer<u8>);
 
     /// Unpacks the input buffer in-place.
-    fn unpack(&mut self, buffer: &[u8]) -> Result<UnpackedOffset, Error>;
+    ///
+    /// After a successful call, the next call must be made with the buffer starting at
+    /// `next_offset`. After `Error::MissingBytes`, the next call must be made with the same
+    /// buffer, extended with more bytes. Certain transports rely on this to modify the buffer.
+    fn unpack(&mut self, buffer: &mut [u8]) -> Result<UnpackedOffset, Error>;
 
     /// Reset the state, as if a new instance was just created.
     fn reset(&mut self);
+
+    /// Returns the tag identifying this transport in the header of [`Obfuscated`] connections,
+    /// or `None` if the transport cannot be obfuscated.
+    ///
+    /// The tag replaces the header that the transport would otherwise send on its own, so after
+    /// calling this method, the transport must not send it until it is reset.
+    fn obfuscated_tag(&mut self) -> Option<[u8; 4]> {
+        None
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn pack(&mut self, buffer: &mut DequeBuffer<u8>) {
+        (**self).pack(buffer)
+    }
+
+    fn unpack(&mut self, buffer: &mut [u8]) -> Result<UnpackedOffset, Error> {
+        (**self).unpack(buffer)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn obfuscated_tag(&mut self) -> Option<[u8; 4]> {
+        (**self).obfuscated_tag()
+    }
 }
@@ -0,0 +1,320 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use super::{Error, Transport, UnpackedOffset};
+use getrandom::getrandom;
+use grammers_crypto::aes::Aes256Ctr;
+use grammers_crypto::DequeBuffer;
+use sha2::{Digest, Sha256};
+
+const HEADER_LEN: usize = 64;
+
+/// Values the first four bytes of the header must not take, or the connection could be
+/// mistaken for a different protocol (HTTP methods, TLS records, or non-obfuscated transports).
+const FORBIDDEN_PREFIXES: [[u8; 4]; 7] = [
+    *b"HEAD",
+    *b"POST",
+    *b"GET ",
+    *b"OPTI",
+    [0x16, 0x03, 0x01, 0x02],
+    [0xdd, 0xdd, 0xdd, 0xdd],
+    [0xee, 0xee, 0xee, 0xee],
+];
+
+/// A wrapper over another transport that encrypts all the data it sends and receives with
+/// AES-256-CTR, so that the traffic looks random to anyone inspecting it. This is an
+/// implementation of the [transport obfuscation].
+///
+/// * Overhead: none, other than that of the inner transport.
+/// * Minimum envelope length: that of the inner transport.
+/// * Maximum envelope length: that of the inner transport.
+///
+/// The very first packet is prefixed by a random 64-byte header, from which the keys are derived:
+///
+/// ```text
+/// +----...----+----...----+----...----+----+--+--+
+/// |  random   |    key    |    iv     | tag|dc|??|
+/// +----...----+----...----+----...----+----+--+--+
+///  ^^^^^^^^^^^ 8 bytes     ^^^^^^^^^^^ 16 bytes
+///              ^^^^^^^^^^^ 32 bytes
+/// ```
+///
+/// The inner transport must provide an [`obfuscated_tag`], which is sent instead of its own
+/// header. When connecting through a MTProxy, the proxy's secret is mixed into the keys.
+///
+/// [transport obfuscation]: https://core.telegram.org/mtproto/mtproto-transports#transport-obfuscation
+/// [`obfuscated_tag`]: Transport::obfuscated_tag
+pub struct Obfuscated<T: Transport> {
+    inner: T,
+    dc_id: i16,
+    secret: Option<[u8; 16]>,
+    header: Option<[u8; HEADER_LEN]>,
+    encryptor: Aes256Ctr,
+    decryptor: Aes256Ctr,
+    decrypted: usize,
+}
+
+impl<T: Transport> Obfuscated<T> {
+    /// Wrap the inner transport to connect to the datacenter with the given identifier.
+    ///
+    /// Panics if the inner transport cannot be obfuscated.
+    pub fn new(inner: T, dc_id: i16) -> Self {
+        Self::build(inner, dc_id, None)
+    }
+
+    /// Like [`Obfuscated::new`], but mixing the 16-byte secret of a MTProxy into the keys.
+    pub fn with_secret(inner: T, dc_id: i16, secret: [u8; 16]) -> Self {
+        Self::build(inner, dc_id, Some(secret))
+    }
+
+    fn build(mut inner: T, dc_id: i16, secret: Option<[u8; 16]>) -> Self {
+        let tag = inner
+            .obfuscated_tag()
+            .expect("inner transport cannot be obfuscated");
+
+        let (header, encryptor, decryptor) = generate_header(tag, dc_id, secret.as_ref());
+        Self {
+            inner,
+            dc_id,
+            secret,
+            header: Some(header),
+            encryptor,
+            decryptor,
+            decrypted: 0,
+        }
+    }
+}
+
+/// Generate a new random header, returning it along with the ciphers used to encrypt outgoing
+/// data and decrypt incoming data. The header is already encrypted where needed.
+fn generate_header(
+    tag: [u8; 4],
+    dc_id: i16,
+    secret: Option<&[u8; 16]>,
+) -> ([u8; HEADER_LEN], Aes256Ctr, Aes256Ctr) {
+    let mut header = [0; HEADER_LEN];
+    loop {
+        getrandom(&mut header).expect("failed to generate obfuscated header");
+        if header[0] != 0xef
+            && !FORBIDDEN_PREFIXES.contains(&header[0..4].try_into().unwrap())
+            && header[4..8] != [0; 4]
+        {
+            break;
+        }
+    }
+    header[56..60].copy_from_slice(&tag);
+    header[60..62].copy_from_slice(&dc_id.to_le_bytes());
+
+    let mut reversed = header;
+    reversed[8..56].reverse();
+
+    let mut encryptor = new_cipher(&header[8..40], &header[40..56], secret);
+    let decryptor = new_cipher(&reversed[8..40], &reversed[40..56], secret);
+
+    // Only the tag and what follows is sent encrypted.
+    let mut encrypted = header;
+    encryptor.apply(&mut encrypted);
+    header[56..].copy_from_slice(&encrypted[56..]);
+
+    (header, encryptor, decryptor)
+}
+
+fn new_cipher(key: &[u8], iv: &[u8], secret: Option<&[u8; 16]>) -> Aes256Ctr {
+    let key: [u8; 32] = match secret {
+        Some(secret) => {
+            let mut hasher = Sha256::new();
+            hasher.update(key);
+            hasher.update(secret);
+            hasher.finalize().into()
+        }
+        None => key.try_into().unwrap(),
+    };
+    Aes256Ctr::new(&key, iv.try_into().unwrap())
+}
+
+impl<T: Transport> Transport for Obfuscated<T> {
+    fn pack(&mut self, buffer: &mut DequeBuffer<u8>) {
+        self.inner.pack(buffer);
+        self.encryptor.apply(&mut buffer[..]);
+
+        if let Some(header) = self.header.take() {
+            buffer.extend_front(&header);
+        }
+    }
+
+    fn unpack(&mut self, buffer: &mut [u8]) -> Result<UnpackedOffset, Error> {
+        // The same bytes may be given again if they were not enough to unpack a packet,
+        // so only those that are new must be decrypted.
+        if self.decrypted < buffer.len() {
+            self.decryptor.apply(&mut buffer[self.decrypted..]);
+            self.decrypted = buffer.len();
+        }
+
+        let offset = self.inner.unpack(buffer)?;
+        self.decrypted -= offset.next_offset;
+        Ok(offset)
+    }
+
+    fn reset(&mut self) {
+        log::info!("resetting header and keys in obfuscated transport");
+        self.inner.reset();
+        let tag = self
+            .inner
+            .obfuscated_tag()
+            .expect("inner transport cannot be obfuscated");
+
+        let (header, encryptor, decryptor) = generate_header(tag, self.dc_id, self.secret.as_ref());
+        self.header = Some(header);
+        self.encryptor = encryptor;
+        self.decryptor = decryptor;
+        self.decrypted = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Intermediate;
+
+    /// Returns an obfuscated intermediate transport, and `n` bytes of input data for it.
+    fn setup_pack(n: usize) -> (Obfuscated<Intermediate>, DequeBuffer<u8>) {
+        let mut buffer = DequeBuffer::with_capacity(n, 0);
+        buffer.extend((0..n).map(|x| (x & 0xff) as u8));
+        (Obfuscated::new(Intermediate::new(), 2), buffer)
+    }
+
+    /// Returns the ciphers a server would use to decrypt and encrypt data from the given header.
+    fn server_ciphers(header: &[u8], secret: Option<&[u8; 16]>) -> (Aes256Ctr, Aes256Ctr) {
+        let mut reversed = header.to_vec();
+        reversed[8..56].reverse();
+        (
+            new_cipher(&header[8..40], &header[40..56], secret),
+            new_cipher(&reversed[8..40], &reversed[40..56], secret),
+        )
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_non_obfuscable() {
+        Obfuscated::new(crate::transport::Full::new(), 2);
+    }
+
+    #[test]
+    fn pack_header() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        transport.pack(&mut buffer);
+        assert_eq!(buffer.len(), HEADER_LEN + 4 + 128);
+
+        let (mut decryptor, _) = server_ciphers(&buffer[..HEADER_LEN], None);
+        let mut header = buffer[..HEADER_LEN].to_vec();
+        decryptor.apply(&mut header);
+        assert_eq!(&header[56..60], &[0xee, 0xee, 0xee, 0xee]);
+        assert_eq!(&header[60..62], &2_i16.to_le_bytes());
+
+        assert_ne!(header[0], 0xef);
+        assert!(!FORBIDDEN_PREFIXES.contains(&buffer[0..4].try_into().unwrap()));
+        assert_ne!(&buffer[4..8], &[0; 4]);
+    }
+
+    #[test]
+    fn pack_normal() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        let orig = buffer.clone();
+        transport.pack(&mut buffer);
+
+        let (mut decryptor, _) = server_ciphers(&buffer[..HEADER_LEN], None);
+        let mut data = buffer[..].to_vec();
+        decryptor.apply(&mut data);
+        assert_eq!(&data[HEADER_LEN..HEADER_LEN + 4], &128_i32.to_le_bytes());
+        assert_eq!(&data[HEADER_LEN + 4..], &orig[..]);
+
+        // The header is only sent once, and the stream continues.
+        let mut buffer = orig.clone();
+        transport.pack(&mut buffer);
+        let mut data = buffer[..].to_vec();
+        decryptor.apply(&mut data);
+        assert_eq!(&data[..4], &128_i32.to_le_bytes());
+        assert_eq!(&data[4..], &orig[..]);
+    }
+
+    #[test]
+    fn pack_with_secret() {
+        let secret = [0x42; 16];
+        let mut transport = Obfuscated::with_secret(Intermediate::new(), -2, secret);
+        let mut buffer = DequeBuffer::with_capacity(0, 0);
+        transport.pack(&mut buffer);
+
+        let (mut decryptor, _) = server_ciphers(&buffer[..HEADER_LEN], Some(&secret));
+        let mut data = buffer[..].to_vec();
+        decryptor.apply(&mut data);
+        assert_eq!(&data[56..60], &[0xee, 0xee, 0xee, 0xee]);
+        assert_eq!(&data[60..62], &(-2_i16).to_le_bytes());
+    }
+
+    #[test]
+    fn unpack_normal() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        let orig = buffer.clone();
+        transport.pack(&mut buffer);
+        let (_, mut encryptor) = server_ciphers(&buffer[..HEADER_LEN], None);
+
+        let mut response = 128_i32.to_le_bytes().to_vec();
+        response.extend(&orig[..]);
+        encryptor.apply(&mut response);
+
+        let offset = transport.unpack(&mut response).unwrap();
+        assert_eq!(&response[offset.data_start..offset.data_end], &orig[..]);
+    }
+
+    #[test]
+    fn unpack_chunked() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        let orig = buffer.clone();
+        transport.pack(&mut buffer);
+        let (_, mut encryptor) = server_ciphers(&buffer[..HEADER_LEN], None);
+
+        let mut response = Vec::new();
+        for _ in 0..2 {
+            response.extend(&128_i32.to_le_bytes());
+            response.extend(&orig[..]);
+        }
+        encryptor.apply(&mut response);
+
+        // Feed the bytes one by one, as if they arrived slowly from the network.
+        let mut received = Vec::new();
+        let mut start = 0;
+        let mut unpacked = 0;
+        for byte in response {
+            received.push(byte);
+            match transport.unpack(&mut received[start..]) {
+                Ok(offset) => {
+                    let data = &received[start..][offset.data_start..offset.data_end];
+                    assert_eq!(data, &orig[..]);
+                    start += offset.next_offset;
+                    unpacked += 1;
+                }
+                Err(Error::MissingBytes) => {}
+                Err(e) => panic!("unexpected error: {e}"),
+            }
+        }
+        assert_eq!(unpacked, 2);
+    }
+
+    #[test]
+    fn reset_regenerates_header() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        let orig = buffer.clone();
+        transport.pack(&mut buffer);
+        let first_header = buffer[..HEADER_LEN].to_vec();
+
+        transport.reset();
+        let mut buffer = orig.clone();
+        transport.pack(&mut buffer);
+        assert_eq!(buffer.len(), HEADER_LEN + 4 + 128);
+        assert_ne!(&buffer[..HEADER_LEN], &first_header[..]);
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use super::{Error, Transport, UnpackedOffset};
+use getrandom::getrandom;
+use grammers_crypto::DequeBuffer;
+
+/// A variant of the intermediate transport that appends random padding to each packet,
+/// so that the length of the packets doesn't reveal the length of the payload. This is an
+/// implementation of the [padded intermediate transport].
+///
+/// * Overhead: small-medium.
+/// * Minimum envelope length: 4 bytes.
+/// * Maximum envelope length: 19 bytes.
+///
+/// It serializes the input payload as follows:
+///
+/// ```text
+/// +----+----...----+----...----+
+/// | len|  payload  |  padding  |
+/// +----+----...----+----...----+
+///  ^^^^ 4 bytes     ^^^^^^^^^^^ 0 to 15 bytes
+/// ```
+///
+/// The length includes the padding. Because the padding length is not known, the unpacked data
+/// will contain the padding too, so it's up to the next layer to discard it.
+///
+/// This transport is required by MTProxy servers using secrets with the `dd` or `ee` prefix.
+///
+/// [padded intermediate transport]: https://core.telegram.org/mtproto/mtproto-transports#padded-intermediate
+pub struct PaddedIntermediate {
+    init: bool,
+}
+
+#[allow(clippy::new_without_default)]
+impl PaddedIntermediate {
+    pub fn new() -> Self {
+        Self { init: false }
+    }
+}
+
+impl Transport for PaddedIntermediate {
+    fn pack(&mut self, buffer: &mut DequeBuffer<u8>) {
+        let len = buffer.len();
+        assert_eq!(len % 4, 0);
+
+        let padding = {
+            let mut buffer = [0; 16];
+            getrandom(&mut buffer).expect("failed to generate random padding");
+            let len = (buffer[0] & 0x0f) as usize;
+            buffer[..len].to_vec()
+        };
+        buffer.extend(padding.iter().copied());
+
+        buffer.extend_front(&((len + padding.len()) as i32).to_le_bytes());
+
+        if !self.init {
+            buffer.extend_front(&0xdd_dd_dd_dd_u32.to_le_bytes());
+            self.init = true;
+        }
+    }
+
+    fn unpack(&mut self, buffer: &mut [u8]) -> Result<UnpackedOffset, Error> {
+        if buffer.len() < 4 {
+            return Err(Error::MissingBytes);
+        }
+
+        let len = i32::from_le_bytes(buffer[0..4].try_into().unwrap());
+        if len < 0 {
+            return Err(Error::BadLen { got: len });
+        }
+        if (buffer.len() as i32) < 4 + len {
+            return Err(Error::MissingBytes);
+        }
+
+        // Any valid message is at least 20 bytes long, so shorter packets must be
+        // transport errors, of which only the first four bytes matter (the rest is padding).
+        if len < 20 {
+            if len >= 4 {
+                let data = i32::from_le_bytes(buffer[4..8].try_into().unwrap());
+                return Err(Error::BadStatus {
+                    status: data.unsigned_abs(),
+                });
+            }
+            return Err(Error::BadLen { got: len });
+        }
+
+        let len = len as usize;
+
+        Ok(UnpackedOffset {
+            data_start: 4,
+            data_end: 4 + len,
+            next_offset: 4 + len,
+        })
+    }
+
+    fn reset(&mut self) {
+        log::info!("resetting sending of header in padded intermediate transport");
+        self.init = false;
+    }
+
+    fn obfuscated_tag(&mut self) -> Option<[u8; 4]> {
+        self.init = true;
+        Some([0xdd; 4])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a padded intermediate transport, and `n` bytes of input data for it.
+    fn setup_pack(n: usize) -> (PaddedIntermediate, DequeBuffer<u8>) {
+        let mut buffer = DequeBuffer::with_capacity(n, 0);
+        buffer.extend((0..n).map(|x| (x & 0xff) as u8));
+        (PaddedIntermediate::new(), buffer)
+    }
+
+    #[test]
+    fn pack_empty() {
+        let (mut transport, mut buffer) = setup_pack(0);
+        transport.pack(&mut buffer);
+        assert_eq!(&buffer[..4], &[0xdd, 0xdd, 0xdd, 0xdd]);
+
+        let len = i32::from_le_bytes(buffer[4..8].try_into().unwrap()) as usize;
+        assert!(len < 16);
+        assert_eq!(buffer.len(), 8 + len);
+    }
+
+    #[test]
+    #[should_panic]
+    fn pack_non_padded() {
+        let (mut transport, mut buffer) = setup_pack(7);
+        transport.pack(&mut buffer);
+    }
+
+    #[test]
+    fn pack_normal() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        let orig = buffer.clone();
+        transport.pack(&mut buffer);
+        assert_eq!(&buffer[..4], &[0xdd, 0xdd, 0xdd, 0xdd]);
+
+        let len = i32::from_le_bytes(buffer[4..8].try_into().unwrap()) as usize;
+        assert!((128..128 + 16).contains(&len));
+        assert_eq!(buffer.len(), 8 + len);
+        assert_eq!(&buffer[8..8 + 128], &orig[..]);
+    }
+
+    #[test]
+    fn pack_twice() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        let orig = buffer.clone();
+        transport.pack(&mut buffer);
+
+        let mut buffer = orig.clone();
+        transport.pack(&mut buffer);
+        let len = i32::from_le_bytes(buffer[0..4].try_into().unwrap()) as usize;
+        assert_eq!(buffer.len(), 4 + len);
+        assert_eq!(&buffer[4..4 + 128], &orig[..]);
+    }
+
+    #[test]
+    fn unpack_small() {
+        let mut transport = PaddedIntermediate::new();
+        let mut buffer = DequeBuffer::with_capacity(1, 0);
+        buffer.extend([1]);
+        assert_eq!(transport.unpack(&mut buffer[..]), Err(Error::MissingBytes));
+    }
+
+    #[test]
+    fn unpack_incomplete() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        transport.pack(&mut buffer);
+        let n = 4; // init bytes
+        let end = buffer.len() - 1;
+        assert_eq!(
+            transport.unpack(&mut buffer[n..end]),
+            Err(Error::MissingBytes)
+        );
+    }
+
+    #[test]
+    fn unpack_normal() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        let orig = buffer.clone();
+        transport.pack(&mut buffer);
+        let n = 4; // init bytes
+        let offset = transport.unpack(&mut buffer[n..]).unwrap();
+
+        // The padding is not known, so it is part of the data.
+        let data = &buffer[n..][offset.data_start..offset.data_end];
+        assert_eq!(&data[..128], &orig[..]);
+        assert_eq!(offset.next_offset, buffer.len() - n);
+    }
+
+    #[test]
+    fn unpack_two_at_once() {
+        let (mut transport, mut buffer) = setup_pack(128);
+        let orig = buffer.clone();
+
+        let mut two_buffer = DequeBuffer::with_capacity(0, 0);
+        transport.pack(&mut buffer);
+        two_buffer.extend(&buffer[4..]); // init bytes
+        let single_size = two_buffer.len();
+
+        buffer = orig.clone();
+        transport.pack(&mut buffer);
+        two_buffer.extend(&buffer[..]);
+
+        let offset = transport.unpack(&mut two_buffer[..]).unwrap();
+        assert_eq!(&two_buffer[offset.data_start..][..128], &orig[..]);
+        assert_eq!(offset.next_offset, single_size);
+
+        let n = offset.next_offset;
+        let offset = transport.unpack(&mut two_buffer[n..]).unwrap();
+        assert_eq!(&two_buffer[n..][offset.data_start..][..128], &orig[..]);
+        assert_eq!(n + offset.next_offset, two_buffer.len());
+    }
+
+    #[test]
+    fn unpack_bad_status() {
+        let mut transport = PaddedIntermediate::new();
+        let mut buffer = DequeBuffer::with_capacity(8, 0);
+        buffer.extend(&(4_i32).to_le_bytes());
+        buffer.extend(&(-404_i32).to_le_bytes());
+
+        assert_eq!(
+            transport.unpack(&mut buffer[..]),
+            Err(Error::BadStatus { status: 404 })
+        );
+    }
+
+    #[test]
+    fn unpack_bad_status_padded() {
+        let mut transport = PaddedIntermediate::new();
+        let mut buffer = DequeBuffer::with_capacity(16, 0);
+        buffer.extend(&(12_i32).to_le_bytes());
+        buffer.extend(&(-429_i32).to_le_bytes());
+        buffer.extend([0xff; 8]);
+
+        assert_eq!(
+            transport.unpack(&mut buffer[..]),
+            Err(Error::BadStatus { status: 429 })
+        );
+    }
+
+    #[test]
+    fn unpack_bad_status_min() {
+        let mut transport = PaddedIntermediate::new();
+        let mut buffer = DequeBuffer::with_capacity(8, 0);
+        buffer.extend(&(4_i32).to_le_bytes());
+        buffer.extend(&i32::MIN.to_le_bytes());
+
+        assert_eq!(
+            transport.unpack(&mut buffer[..]),
+            Err(Error::BadStatus { status: 1 << 31 })
+        );
+    }
+
+    #[test]
+    fn unpack_bad_len() {
+        let mut transport = PaddedIntermediate::new();
+        let mut buffer = DequeBuffer::with_capacity(8, 0);
+        buffer.extend(&(-1_i32).to_le_bytes());
+        buffer.extend([0; 4]);
+
+        assert_eq!(
+            transport.unpack(&mut buffer[..]),
+            Err(Error::BadLen { got: -1 })
+        );
+    }
+}
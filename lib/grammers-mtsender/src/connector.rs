@@ -0,0 +1,108 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A bidirectional stream over which the MTProto traffic is sent, such as a TCP or Unix socket,
+/// a TLS session, or an in-memory pipe.
+///
+/// This trait is implemented for all types meeting its bounds, so it never needs to be
+/// implemented manually.
+pub trait Stream: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> Stream for T {}
+
+/// The future returned by [`Connector::connect`].
+pub type ConnectFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Box<dyn Stream>, io::Error>> + Send + 'a>>;
+
+/// Opens the streams used by a sender, replacing the default TCP connections.
+///
+/// The connector is used both when the sender is first created and every time it needs to
+/// reconnect, so it must be able to produce a new stream each time it is called. It is given
+/// the address of the datacenter that the sender wants to reach, which the connector is free to
+/// ignore or map to a different destination.
+///
+/// Closures returning a future are connectors too:
+///
+/// ```
+/// use grammers_mtsender::{Connector, Stream};
+/// use std::net::SocketAddr;
+/// use tokio::net::TcpStream;
+///
+/// let connector = |addr: SocketAddr| async move {
+///     let stream = TcpStream::connect(addr).await?;
+///     Ok(Box::new(stream) as Box<dyn Stream>)
+/// };
+/// # fn assert_connector(_: impl Connector) {}
+/// # assert_connector(connector);
+/// ```
+pub trait Connector: Send + Sync {
+    /// Open a new stream to carry the traffic meant for the given address.
+    fn connect(&self, addr: SocketAddr) -> ConnectFuture<'_>;
+}
+
+impl<F, Fut> Connector for F
+where
+    F: Fn(SocketAddr) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Box<dyn Stream>, io::Error>> + Send + 'static,
+{
+    fn connect(&self, addr: SocketAddr) -> ConnectFuture<'_> {
+        Box::pin(self(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{block_on, pipe_connector};
+    use crate::{connect_via_connector_with_auth, FixedReconnect, UpdatesLike};
+    use grammers_mtproto::transport;
+    use grammers_tl_types::{self as tl, enums};
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn sender_uses_connector() {
+        block_on(async {
+            let (connector, mut servers) = pipe_connector();
+            let (mut sender, enqueuer) = connect_via_connector_with_auth(
+                transport::Intermediate::new(),
+                "127.0.0.1:443".parse().unwrap(),
+                [0; 256],
+                connector,
+                &FixedReconnect {
+                    attempts: 1,
+                    delay: Duration::ZERO,
+                },
+            )
+            .await
+            .unwrap();
+
+            let mut server = servers.try_recv().unwrap();
+            let _rx = enqueuer.enqueue(&tl::functions::Ping { ping_id: 1 });
+            sender.step().await.unwrap(); // receive the request
+            sender.step().await.unwrap(); // write the request
+
+            let mut header = [0; 4];
+            server.read_exact(&mut header).await.unwrap();
+            assert_eq!(header, [0xee; 4]);
+
+            // Dropping the server side should cause the connector to be used again.
+            drop(server);
+            let updates = sender.step().await.unwrap();
+            assert!(matches!(
+                updates[..],
+                [UpdatesLike::Updates(enums::Updates::TooLong)]
+            ));
+            assert!(servers.try_recv().is_ok());
+        });
+    }
+}
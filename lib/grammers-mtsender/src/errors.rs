@@ -15,6 +15,10 @@ pub enum ReadError {
     Io(io::Error),
     Transport(transport::Error),
     Deserialize(mtp::DeserializeError),
+    /// The server no longer knows about the authorization key that was used, so it cannot be
+    /// used to communicate with it anymore. A new key must be generated, and any login that was
+    /// associated with the previous key is lost.
+    AuthKeyLost,
 }
 
 impl std::error::Error for ReadError {}
@@ -29,6 +33,7 @@ impl Clone for ReadError {
             ),
             Self::Transport(e) => Self::Transport(e.clone()),
             Self::Deserialize(e) => Self::Deserialize(e.clone()),
+            Self::AuthKeyLost => Self::AuthKeyLost,
         }
     }
 }
@@ -39,6 +44,7 @@ impl fmt::Display for ReadError {
             Self::Io(err) => write!(f, "read error, IO failed: {err}"),
             Self::Transport(err) => write!(f, "read error, transport-level: {err}"),
             Self::Deserialize(err) => write!(f, "read error, bad response: {err}"),
+            Self::AuthKeyLost => write!(f, "read error, authorization key lost"),
         }
     }
 }
@@ -177,6 +183,9 @@ pub enum InvocationError {
 
     /// The error occured while reading the response.
     Read(ReadError),
+
+    /// The response did not arrive in time, and the request was cancelled.
+    Timeout,
 }
 
 impl std::error::Error for InvocationError {}
@@ -187,6 +196,7 @@ impl fmt::Display for InvocationError {
             Self::Rpc(err) => write!(f, "request error: {err}"),
             Self::Dropped => write!(f, "request error: dropped (cancelled)"),
             Self::Read(err) => write!(f, "request error: {err}"),
+            Self::Timeout => write!(f, "request error: timed out"),
         }
     }
 }
@@ -0,0 +1,204 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tunneling through HTTP proxies with the [`CONNECT`] method.
+//!
+//! [`CONNECT`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Methods/CONNECT
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// The maximum length of the response headers that we're willing to read from the proxy.
+const MAXIMUM_RESPONSE: usize = 8 * 1024;
+
+/// Ask the HTTP proxy on the other end of the stream to open a tunnel to the given address.
+///
+/// If `username` is not empty, basic authentication is used with the given credentials.
+///
+/// Once this succeeds, the stream can be used as if it was directly connected to `addr`.
+pub(crate) async fn connect(
+    stream: &mut TcpStream,
+    addr: &SocketAddr,
+    username: &str,
+    password: &str,
+) -> Result<(), io::Error> {
+    let mut request = format!("CONNECT {addr} HTTP/1.1\r\nHost: {addr}\r\n");
+    if !username.is_empty() {
+        let credentials = STANDARD.encode(format!("{username}:{password}"));
+        request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read one byte at a time, so that nothing after the headers is consumed.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() == MAXIMUM_RESPONSE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "http proxy sent a response that is too long",
+            ));
+        }
+        let byte = stream.read_u8().await?;
+        response.push(byte);
+    }
+
+    let status_line = response
+        .split(|&b| b == b'\r')
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .unwrap_or_default();
+
+    let status = match status_line.split(' ').collect::<Vec<_>>()[..] {
+        [version, status, ..] if version.starts_with("HTTP/1.") => status.parse::<u16>().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("http proxy sent an invalid response: {status_line:?}"),
+        )
+    })?;
+
+    match status {
+        200..=299 => Ok(()),
+        407 => Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("http proxy requires authentication: {status_line}"),
+        )),
+        _ => Err(io::Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("http proxy refused to connect: {status_line}"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::runtime;
+    use tokio::task::JoinHandle;
+
+    const TARGET: &str = "149.154.167.40:443";
+
+    /// Start a stand-in HTTP proxy that expects the given request, and answers with the given
+    /// response. On success, it echoes back whatever is sent through the tunnel.
+    async fn start_proxy(
+        expected_request: String,
+        response: &'static str,
+    ) -> (SocketAddr, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; expected_request.len()];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(String::from_utf8(request).unwrap(), expected_request);
+
+            stream.write_all(response.as_bytes()).await.unwrap();
+            if response.starts_with("HTTP/1.1 200") {
+                let mut data = [0; 4];
+                stream.read_exact(&mut data).await.unwrap();
+                stream.write_all(&data).await.unwrap();
+            }
+        });
+        (addr, handle)
+    }
+
+    fn run(future: impl std::future::Future<Output = ()>) {
+        runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn connect_without_auth() {
+        run(async {
+            let (proxy, handle) = start_proxy(
+                format!("CONNECT {TARGET} HTTP/1.1\r\nHost: {TARGET}\r\n\r\n"),
+                "HTTP/1.1 200 Connection established\r\nProxy-Agent: test\r\n\r\n",
+            )
+            .await;
+
+            let mut stream = TcpStream::connect(proxy).await.unwrap();
+            connect(&mut stream, &TARGET.parse().unwrap(), "", "")
+                .await
+                .unwrap();
+
+            stream.write_all(&[1, 2, 3, 4]).await.unwrap();
+            let mut data = [0; 4];
+            stream.read_exact(&mut data).await.unwrap();
+            assert_eq!(data, [1, 2, 3, 4]);
+            handle.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn connect_with_auth() {
+        run(async {
+            // base64("user:pass")
+            let (proxy, handle) = start_proxy(
+                format!(
+                    "CONNECT {TARGET} HTTP/1.1\r\nHost: {TARGET}\r\n\
+                     Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+                ),
+                "HTTP/1.1 200 OK\r\n\r\n",
+            )
+            .await;
+
+            let mut stream = TcpStream::connect(proxy).await.unwrap();
+            connect(&mut stream, &TARGET.parse().unwrap(), "user", "pass")
+                .await
+                .unwrap();
+            stream.write_all(&[1, 2, 3, 4]).await.unwrap();
+            handle.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn connect_auth_required() {
+        run(async {
+            let (proxy, handle) = start_proxy(
+                format!("CONNECT {TARGET} HTTP/1.1\r\nHost: {TARGET}\r\n\r\n"),
+                "HTTP/1.1 407 Proxy Authentication Required\r\n\r\n",
+            )
+            .await;
+
+            let mut stream = TcpStream::connect(proxy).await.unwrap();
+            let err = connect(&mut stream, &TARGET.parse().unwrap(), "", "")
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+            handle.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn connect_refused() {
+        run(async {
+            let (proxy, handle) = start_proxy(
+                format!("CONNECT {TARGET} HTTP/1.1\r\nHost: {TARGET}\r\n\r\n"),
+                "HTTP/1.0 403 Forbidden\r\n\r\n",
+            )
+            .await;
+
+            let mut stream = TcpStream::connect(proxy).await.unwrap();
+            let err = connect(&mut stream, &TARGET.parse().unwrap(), "", "")
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+            handle.await.unwrap();
+        });
+    }
+}
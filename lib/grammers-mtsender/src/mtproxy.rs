@@ -0,0 +1,475 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Support for connecting through [MTProxy] servers.
+//!
+//! [MTProxy]: https://core.telegram.org/mtproto/mtproto-transports#transport-obfuscation
+use getrandom::getrandom;
+use grammers_mtproto::transport::{Intermediate, Obfuscated, PaddedIntermediate, Transport};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+
+/// The length the TLS record with the client hello must have for MTProxy servers to accept it.
+const CLIENT_HELLO_LEN: usize = 517;
+
+/// Offset of the random value in both the client and server hello records.
+const RANDOM_OFFSET: usize = 11;
+
+/// Maximum payload of a single TLS application data record.
+const MAX_RECORD_DATA: usize = 16384;
+
+const CHANGE_CIPHER_SPEC: [u8; 6] = [0x14, 0x03, 0x03, 0x00, 0x01, 0x01];
+
+/// The kind of secret used by a MTProxy, which determines how to connect to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProxySecret {
+    /// A plain 16-byte secret, used with an obfuscated intermediate transport.
+    Simple([u8; 16]),
+    /// A secret prefixed with `dd`, used with an obfuscated padded intermediate transport.
+    Padded([u8; 16]),
+    /// A secret prefixed with `ee`, which additionally disguises the connection as TLS traffic
+    /// to the given domain.
+    FakeTls { secret: [u8; 16], domain: String },
+}
+
+/// A MTProxy server to connect through, instead of connecting to Telegram directly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MtProxy {
+    pub addr: SocketAddr,
+    pub secret: ProxySecret,
+}
+
+impl ProxySecret {
+    /// Parse the hex-encoded secret, as it appears in `tg://proxy` links.
+    pub fn parse(secret: &str) -> Result<Self, io::Error> {
+        let invalid = || io::Error::new(ErrorKind::InvalidInput, "invalid mtproxy secret");
+
+        let bytes = (0..secret.len())
+            .step_by(2)
+            .map(|i| {
+                secret
+                    .get(i..i + 2)
+                    .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                    .map(|hex| u8::from_str_radix(hex, 16).unwrap())
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+
+        match bytes.len() {
+            16 => Ok(Self::Simple(bytes.try_into().unwrap())),
+            17 if bytes[0] == 0xdd => Ok(Self::Padded(bytes[1..].try_into().unwrap())),
+            n if n > 17 && bytes[0] == 0xee => Ok(Self::FakeTls {
+                secret: bytes[1..17].try_into().unwrap(),
+                domain: String::from_utf8(bytes[17..].to_vec()).map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The 16 bytes of the secret used to derive the keys, without prefix or domain.
+    pub fn key(&self) -> &[u8; 16] {
+        match self {
+            Self::Simple(secret) | Self::Padded(secret) => secret,
+            Self::FakeTls { secret, .. } => secret,
+        }
+    }
+}
+
+impl MtProxy {
+    /// Create a new MTProxy configuration from its address and hex-encoded secret.
+    pub fn new(addr: SocketAddr, secret: &str) -> Result<Self, io::Error> {
+        Ok(Self {
+            addr,
+            secret: ProxySecret::parse(secret)?,
+        })
+    }
+
+    /// Create the transport needed to reach the datacenter with the given identifier through
+    /// this proxy. Media-only datacenters are identified by their negative identifier.
+    pub fn transport(&self, dc_id: i16) -> Obfuscated<Box<dyn Transport + Send + Sync>> {
+        let inner: Box<dyn Transport + Send + Sync> = match self.secret {
+            ProxySecret::Simple(_) => Box::new(Intermediate::new()),
+            ProxySecret::Padded(_) | ProxySecret::FakeTls { .. } => {
+                Box::new(PaddedIntermediate::new())
+            }
+        };
+        Obfuscated::with_secret(inner, dc_id, *self.secret.key())
+    }
+}
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// Build a TLS client hello to the given domain, signed with the secret.
+fn client_hello(secret: &[u8; 16], domain: &str) -> Result<Vec<u8>, io::Error> {
+    let mut random = [0; 64];
+    getrandom(&mut random).expect("failed to generate random client hello");
+    let (session_id, key_share) = random.split_at(32);
+
+    let mut extensions = Vec::with_capacity(CLIENT_HELLO_LEN);
+    let mut extension = |id: u16, data: &[u8]| {
+        extensions.extend(id.to_be_bytes());
+        extensions.extend((data.len() as u16).to_be_bytes());
+        extensions.extend(data);
+    };
+
+    let mut server_name = Vec::with_capacity(5 + domain.len());
+    server_name.extend((domain.len() as u16 + 3).to_be_bytes());
+    server_name.push(0x00);
+    server_name.extend((domain.len() as u16).to_be_bytes());
+    server_name.extend(domain.as_bytes());
+    extension(0x0000, &server_name);
+    extension(0x0017, &[]);
+    extension(0xff01, &[0x00]);
+    extension(0x000a, &[0x00, 0x06, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18]);
+    extension(0x000b, &[0x01, 0x00]);
+    extension(0x0023, &[]);
+    extension(0x0010, b"\x00\x0c\x02h2\x08http/1.1".as_slice());
+    extension(0x0005, &[0x01, 0x00, 0x00, 0x00, 0x00]);
+    extension(
+        0x000d,
+        &[
+            0x00, 0x10, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x03, 0x08, 0x05, 0x05, 0x01,
+            0x08, 0x06, 0x06, 0x01,
+        ],
+    );
+    extension(0x0012, &[]);
+    let mut key_shares = vec![0x00, 0x24, 0x00, 0x1d, 0x00, 0x20];
+    key_shares.extend(key_share);
+    extension(0x0033, &key_shares);
+    extension(0x002d, &[0x01, 0x01]);
+    extension(0x002b, &[0x04, 0x03, 0x04, 0x03, 0x03]);
+
+    let mut hello = Vec::with_capacity(CLIENT_HELLO_LEN);
+    hello.extend([0x16, 0x03, 0x01, 0x02, 0x00]); // record header
+    hello.extend([0x01, 0x00, 0x01, 0xfc]); // handshake header
+    hello.extend([0x03, 0x03]);
+    hello.extend([0; 32]); // random, filled later
+    hello.push(0x20);
+    hello.extend(session_id);
+    hello.extend([
+        0x00, 0x20, 0x0a, 0x0a, 0x13, 0x01, 0x13, 0x02, 0x13, 0x03, 0xc0, 0x2b, 0xc0, 0x2f, 0xc0,
+        0x2c, 0xc0, 0x30, 0xcc, 0xa9, 0xcc, 0xa8, 0xc0, 0x13, 0xc0, 0x14, 0x00, 0x9c, 0x00, 0x9d,
+        0x00, 0x2f, 0x00, 0x35,
+    ]);
+    hello.extend([0x01, 0x00]);
+
+    // Pad the hello with the padding extension (4 bytes of header) to reach the required length.
+    let len = hello.len() + 2 + extensions.len() + 4;
+    if len > CLIENT_HELLO_LEN {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "mtproxy domain is too long",
+        ));
+    }
+    let padding = CLIENT_HELLO_LEN - len;
+    extensions.extend(0x0015_u16.to_be_bytes());
+    extensions.extend((padding as u16).to_be_bytes());
+    extensions.resize(extensions.len() + padding, 0);
+
+    hello.extend((extensions.len() as u16).to_be_bytes());
+    hello.extend(extensions);
+    debug_assert_eq!(hello.len(), CLIENT_HELLO_LEN);
+
+    // The random value is the HMAC of the hello, with the current time mixed in.
+    let mut random = hmac_sha256(secret, &[&hello]);
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("system time is before epoch")
+        .as_secs() as u32;
+    random[28..]
+        .iter_mut()
+        .zip(now.to_le_bytes())
+        .for_each(|(r, t)| *r ^= t);
+    hello[RANDOM_OFFSET..RANDOM_OFFSET + 32].copy_from_slice(&random);
+
+    Ok(hello)
+}
+
+/// Read a whole TLS record of the given type, appending it to the buffer.
+async fn read_record(
+    stream: &mut TcpStream,
+    record_type: u8,
+    buffer: &mut Vec<u8>,
+) -> Result<(), io::Error> {
+    let mut header = [0; 5];
+    stream.read_exact(&mut header).await?;
+    if header[0] != record_type || header[1..3] != [0x03, 0x03] {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "mtproxy sent an unexpected tls record",
+        ));
+    }
+
+    let start = buffer.len();
+    let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+    buffer.extend(header);
+    buffer.resize(start + header.len() + len, 0);
+    stream
+        .read_exact(&mut buffer[start + header.len()..])
+        .await?;
+    Ok(())
+}
+
+/// A stream that disguises the data sent through it as a TLS connection, as required by
+/// MTProxy servers using secrets with the `ee` prefix.
+///
+/// All the data is wrapped in TLS application data records, but it is not actually encrypted
+/// by TLS, so it should always be obfuscated by the transport.
+pub struct FakeTlsStream {
+    stream: TcpStream,
+    /// Bytes of the header of the record being read, and how many of those have been read.
+    read_header: [u8; 5],
+    read_header_len: usize,
+    /// Bytes left to read from the payload of the current record.
+    read_remaining: usize,
+    /// Record being written, and how many bytes of the input it contains.
+    write_record: Vec<u8>,
+    write_accepted: usize,
+    ccs_sent: bool,
+}
+
+impl FakeTlsStream {
+    /// Perform the fake TLS handshake over the stream, verifying the server's response.
+    pub(crate) async fn connect(
+        mut stream: TcpStream,
+        secret: &[u8; 16],
+        domain: &str,
+    ) -> Result<Self, io::Error> {
+        let hello = client_hello(secret, domain)?;
+        stream.write_all(&hello).await?;
+
+        let mut response = Vec::new();
+        read_record(&mut stream, 0x16, &mut response).await?;
+        read_record(&mut stream, 0x14, &mut response).await?;
+        read_record(&mut stream, 0x17, &mut response).await?;
+
+        if response.len() < RANDOM_OFFSET + 32 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "mtproxy sent a server hello that is too short",
+            ));
+        }
+        let mut server_random = [0; 32];
+        server_random.copy_from_slice(&response[RANDOM_OFFSET..RANDOM_OFFSET + 32]);
+        response[RANDOM_OFFSET..RANDOM_OFFSET + 32].fill(0);
+
+        let client_random = &hello[RANDOM_OFFSET..RANDOM_OFFSET + 32];
+        if hmac_sha256(secret, &[client_random, &response]) != server_random {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "mtproxy sent a server hello that failed verification",
+            ));
+        }
+
+        Ok(Self {
+            stream,
+            read_header: [0; 5],
+            read_header_len: 0,
+            read_remaining: 0,
+            write_record: Vec::new(),
+            write_accepted: 0,
+            ccs_sent: false,
+        })
+    }
+}
+
+impl AsyncRead for FakeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.read_remaining == 0 {
+            let mut header = ReadBuf::new(&mut this.read_header[this.read_header_len..]);
+            ready!(Pin::new(&mut this.stream).poll_read(cx, &mut header))?;
+            let n = header.filled().len();
+            if n == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            this.read_header_len += n;
+            if this.read_header_len == this.read_header.len() {
+                if this.read_header[..3] != [0x17, 0x03, 0x03] {
+                    return Poll::Ready(Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "mtproxy sent an unexpected tls record",
+                    )));
+                }
+                this.read_header_len = 0;
+                this.read_remaining =
+                    u16::from_be_bytes([this.read_header[3], this.read_header[4]]) as usize;
+            }
+        }
+
+        let len = this.read_remaining.min(buf.remaining());
+        let mut payload = ReadBuf::new(buf.initialize_unfilled_to(len));
+        ready!(Pin::new(&mut this.stream).poll_read(cx, &mut payload))?;
+        let n = payload.filled().len();
+        buf.advance(n);
+        this.read_remaining -= n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for FakeTlsStream {
+    /// Writes are performed one whole record at a time. If the write is interrupted, it must be
+    /// retried with the same input, or the record that was already started would be corrupted.
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.write_record.is_empty() {
+            if !this.ccs_sent {
+                this.write_record.extend(CHANGE_CIPHER_SPEC);
+                this.ccs_sent = true;
+            }
+            let len = buf.len().min(MAX_RECORD_DATA);
+            this.write_record.extend([0x17, 0x03, 0x03]);
+            this.write_record.extend((len as u16).to_be_bytes());
+            this.write_record.extend(&buf[..len]);
+            this.write_accepted = len;
+        }
+
+        while !this.write_record.is_empty() {
+            let n = ready!(Pin::new(&mut this.stream).poll_write(cx, &this.write_record))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::Error::from(ErrorKind::WriteZero)));
+            }
+            this.write_record.drain(..n);
+        }
+
+        Poll::Ready(Ok(this.write_accepted))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::runtime;
+
+    const SECRET: [u8; 16] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+
+    #[test]
+    fn parse_secrets() {
+        let hex = "00112233445566778899aabbccddeeff";
+        assert_eq!(
+            ProxySecret::parse(hex).unwrap(),
+            ProxySecret::Simple(SECRET)
+        );
+        assert_eq!(
+            ProxySecret::parse(&format!("dd{hex}")).unwrap(),
+            ProxySecret::Padded(SECRET)
+        );
+        assert_eq!(
+            ProxySecret::parse(&format!("ee{hex}6578616d706c652e636f6d")).unwrap(),
+            ProxySecret::FakeTls {
+                secret: SECRET,
+                domain: "example.com".to_string()
+            }
+        );
+        assert!(ProxySecret::parse("0011").is_err());
+        assert!(ProxySecret::parse(&format!("aa{hex}")).is_err());
+        assert!(ProxySecret::parse(&format!("{hex}z")).is_err());
+    }
+
+    #[test]
+    fn client_hello_is_signed() {
+        let hello = client_hello(&SECRET, "example.com").unwrap();
+        assert_eq!(hello.len(), CLIENT_HELLO_LEN);
+        assert_eq!(&hello[..5], &[0x16, 0x03, 0x01, 0x02, 0x00]);
+        assert!(hello
+            .windows("example.com".len())
+            .any(|w| w == b"example.com"));
+
+        let mut unsigned = hello.clone();
+        unsigned[RANDOM_OFFSET..RANDOM_OFFSET + 32].fill(0);
+        let digest = hmac_sha256(&SECRET, &[&unsigned]);
+        assert_eq!(&hello[RANDOM_OFFSET..RANDOM_OFFSET + 28], &digest[..28]);
+    }
+
+    #[test]
+    fn fake_tls_roundtrip() {
+        let rt = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let server = tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut hello = vec![0; CLIENT_HELLO_LEN];
+                stream.read_exact(&mut hello).await.unwrap();
+                let client_random = hello[RANDOM_OFFSET..RANDOM_OFFSET + 32].to_vec();
+
+                let mut response = vec![0x16, 0x03, 0x03, 0x00, 0x30];
+                response.extend([0x02; 0x30]);
+                response.extend(CHANGE_CIPHER_SPEC);
+                response.extend([0x17, 0x03, 0x03, 0x00, 0x04, 1, 2, 3, 4]);
+                response[RANDOM_OFFSET..RANDOM_OFFSET + 32].fill(0);
+                let random = hmac_sha256(&SECRET, &[&client_random, &response]);
+                response[RANDOM_OFFSET..RANDOM_OFFSET + 32].copy_from_slice(&random);
+                stream.write_all(&response).await.unwrap();
+
+                let mut data = vec![0; CHANGE_CIPHER_SPEC.len() + 5 + 4];
+                stream.read_exact(&mut data).await.unwrap();
+                assert_eq!(&data[..6], &CHANGE_CIPHER_SPEC);
+                assert_eq!(&data[6..], &[0x17, 0x03, 0x03, 0x00, 0x04, 5, 6, 7, 8]);
+
+                stream
+                    .write_all(&[0x17, 0x03, 0x03, 0x00, 0x02, 9, 10])
+                    .await
+                    .unwrap();
+                stream
+                    .write_all(&[0x17, 0x03, 0x03, 0x00, 0x01, 11])
+                    .await
+                    .unwrap();
+            });
+
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut stream = FakeTlsStream::connect(stream, &SECRET, "example.com")
+                .await
+                .unwrap();
+            stream.write_all(&[5, 6, 7, 8]).await.unwrap();
+
+            let mut data = [0; 3];
+            stream.read_exact(&mut data).await.unwrap();
+            assert_eq!(data, [9, 10, 11]);
+            server.await.unwrap();
+        });
+    }
+}
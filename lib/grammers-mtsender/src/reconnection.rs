@@ -5,6 +5,8 @@
 // <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
+use getrandom::getrandom;
+use std::io;
 use std::ops::ControlFlow;
 use std::time::Duration;
 
@@ -14,15 +16,20 @@ use std::time::Duration;
 /// custom implementations for handling connection failures.
 ///
 /// the default implementation is **NoReconnect** which does not handle anything! there is also a `FixedReconnect`
-/// which sets a fixed attempt count and a duration
+/// which sets a fixed attempt count and a duration, and an `ExponentialReconnect` which waits longer after
+/// every failed attempt.
 ///
 /// note that this will return a `ControlFlow<(), Duration>` which tells the handler either `Break` the Connection Attempt *or*
 /// `Continue` After the Given `Duration`
 pub trait ReconnectionPolicy: Send + Sync {
     ///this function will indicate that the handler should attempt for a new *reconnection* or not.
     ///
-    /// it accepts a `attempts` which is the amount of reconnection tries that has been made already
-    fn should_retry(&self, attempts: usize) -> ControlFlow<(), Duration>;
+    /// it accepts a `attempts` which is the amount of reconnection tries that has been made already,
+    /// and the `error` that caused the connection (or the last attempt to reconnect) to fail.
+    ///
+    /// when the connection is first lost, this is called with `0` attempts to decide whether to reconnect
+    /// at all, and the first attempt is made right away regardless of the returned `Duration`.
+    fn should_retry(&self, attempts: usize, error: &io::Error) -> ControlFlow<(), Duration>;
 }
 
 /// the default implementation of the **ReconnectionPolicy**.
@@ -34,8 +41,53 @@ pub struct FixedReconnect {
     pub delay: Duration,
 }
 
+/// *Exponential* backoff with jitter implementation for the **ReconnectionPolicy** trait.
+///
+/// the delay starts at `initial_delay` and is multiplied by `factor` after every failed attempt, up to `max_delay`.
+/// a random fraction of up to `jitter` (between `0.0` and `1.0`) is then taken off the delay, so that many clients
+/// which lost their connection at the same time don't all try to reconnect at once.
+///
+/// a `factor` below `1.0` is treated as `1.0`, and a `jitter` outside of that range is clamped to it. if either
+/// is `NaN`, no growth or jitter is applied respectively.
+///
+/// because the policy must live for `'static`, it's easiest to keep it in a `static`:
+///
+/// ```
+/// use grammers_mtsender::ExponentialReconnect;
+/// use std::time::Duration;
+///
+/// static POLICY: ExponentialReconnect = ExponentialReconnect {
+///     attempts: 10,
+///     initial_delay: Duration::from_millis(100),
+///     max_delay: Duration::from_secs(30),
+///     factor: 2.0,
+///     jitter: 0.5,
+/// };
+/// ```
+pub struct ExponentialReconnect {
+    pub attempts: usize,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub factor: f64,
+    pub jitter: f64,
+}
+
+impl ExponentialReconnect {
+    /// the delay before the given attempt, before any jitter is applied.
+    fn base_delay(&self, attempts: usize) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(i32::MAX as usize) as i32;
+        let factor = if self.factor >= 1.0 { self.factor } else { 1.0 };
+        let delay = self.initial_delay.as_secs_f64() * factor.powi(exponent);
+        if delay.is_finite() && delay < self.max_delay.as_secs_f64() {
+            Duration::from_secs_f64(delay)
+        } else {
+            self.max_delay
+        }
+    }
+}
+
 impl ReconnectionPolicy for FixedReconnect {
-    fn should_retry(&self, attempts: usize) -> ControlFlow<(), Duration> {
+    fn should_retry(&self, attempts: usize, _: &io::Error) -> ControlFlow<(), Duration> {
         if attempts <= self.attempts {
             ControlFlow::Continue(self.delay)
         } else {
@@ -45,7 +97,111 @@ impl ReconnectionPolicy for FixedReconnect {
 }
 
 impl ReconnectionPolicy for NoReconnect {
-    fn should_retry(&self, _: usize) -> ControlFlow<(), Duration> {
+    fn should_retry(&self, _: usize, _: &io::Error) -> ControlFlow<(), Duration> {
         ControlFlow::Break(())
     }
 }
+
+impl ReconnectionPolicy for ExponentialReconnect {
+    fn should_retry(&self, attempts: usize, _: &io::Error) -> ControlFlow<(), Duration> {
+        if attempts > self.attempts {
+            return ControlFlow::Break(());
+        }
+
+        let mut random = [0; 4];
+        getrandom(&mut random).expect("failed to generate reconnection jitter");
+        let random = u32::from_le_bytes(random) as f64 / (u32::MAX as f64 + 1.0);
+
+        let jitter = if self.jitter.is_nan() {
+            0.0
+        } else {
+            self.jitter.clamp(0.0, 1.0)
+        };
+        let delay = self.base_delay(attempts);
+        ControlFlow::Continue(delay.mul_f64(1.0 - jitter * random))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: f64) -> ExponentialReconnect {
+        ExponentialReconnect {
+            attempts: 5,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            factor: 2.0,
+            jitter,
+        }
+    }
+
+    fn delay(policy: &ExponentialReconnect, attempts: usize) -> Duration {
+        let error = io::Error::from(io::ErrorKind::ConnectionReset);
+        match policy.should_retry(attempts, &error) {
+            ControlFlow::Continue(delay) => delay,
+            ControlFlow::Break(()) => panic!("policy gave up after {attempts} attempts"),
+        }
+    }
+
+    #[test]
+    fn exponential_grows_until_max() {
+        let policy = policy(0.0);
+        assert_eq!(delay(&policy, 1), Duration::from_millis(100));
+        assert_eq!(delay(&policy, 2), Duration::from_millis(200));
+        assert_eq!(delay(&policy, 3), Duration::from_millis(400));
+        assert_eq!(delay(&policy, 4), Duration::from_millis(800));
+        assert_eq!(delay(&policy, 5), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn exponential_gives_up() {
+        let policy = policy(0.0);
+        let error = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert!(policy.should_retry(0, &error).is_continue());
+        assert!(policy.should_retry(6, &error).is_break());
+        assert!(policy.should_retry(usize::MAX, &error).is_break());
+    }
+
+    #[test]
+    fn exponential_jitter_within_bounds() {
+        let policy = policy(0.5);
+        for attempts in 1..=5 {
+            let base = policy.base_delay(attempts);
+            for _ in 0..100 {
+                let delay = delay(&policy, attempts);
+                assert!(delay <= base);
+                assert!(delay >= base / 2);
+            }
+        }
+    }
+
+    #[test]
+    fn exponential_invalid_parameters() {
+        for (factor, jitter) in [
+            (-2.0, 0.0),
+            (0.5, 0.0),
+            (f64::NAN, f64::NAN),
+            (f64::INFINITY, -1.0),
+            (2.0, 5.0),
+            (2.0, f64::INFINITY),
+        ] {
+            let policy = ExponentialReconnect {
+                factor,
+                jitter,
+                ..policy(0.0)
+            };
+            for attempts in 1..=5 {
+                let delay = delay(&policy, attempts);
+                assert!(delay <= policy.max_delay);
+            }
+        }
+
+        // Delays never shrink, even if the factor would make them.
+        let policy = ExponentialReconnect {
+            factor: -2.0,
+            ..policy(0.0)
+        };
+        assert_eq!(delay(&policy, 2), Duration::from_millis(100));
+    }
+}
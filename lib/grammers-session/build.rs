@@ -12,7 +12,7 @@ use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::Path;
 
-const CURRENT_VERSION: i32 = 2;
+const CURRENT_VERSION: i32 = 3;
 
 fn main() -> std::io::Result<()> {
     let mut file = BufWriter::new(File::create(
@@ -26,7 +26,10 @@ fn main() -> std::io::Result<()> {
         user id:long dc:int bot:Bool = User;
         channelState channel_id:long pts:int = ChannelState;
         updateState pts:int qts:int date:int seq:int channels:Vector<ChannelState> = UpdateState;
-        session flags:# dcs:Vector<DataCenter> user:flags.0?User state:flags.1?UpdateState = Session;
+        peer flags:# id:long hash:flags.0?long ty:int = Peer;
+        dcOption flags:# id:int ipv4:flags.0?int ipv6:flags.1?int128 port:int media_only:flags.2?true cdn:flags.3?true test:flags.4?true static:flags.5?true this_port_only:flags.6?true tcpo_only:flags.7?true secret:flags.8?bytes = DcOption;
+        session flags:# dcs:Vector<DataCenter> user:flags.0?User state:flags.1?UpdateState peers:Vector<Peer> dc_options:Vector<DcOption> = Session;
+        sessionV2#a73eb8ce flags:# dcs:Vector<DataCenter> user:flags.0?User state:flags.1?UpdateState = Session;
         "#,
     )
     .map(Result::unwrap)
@@ -7,13 +7,15 @@
 // except according to those terms.
 use super::{PackedChat, PackedType};
 use grammers_tl_types as tl;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// In-memory chat cache, mapping peers to their respective access hashes.
 pub struct ChatHashCache {
     // As far as I've observed, user, chat and channel IDs cannot collide,
     // but it will be an interesting moment if they ever do.
     hash_map: HashMap<i64, (i64, PackedType)>,
+    // Peers which were inserted or changed since the last `take_unsaved`.
+    unsaved: HashSet<i64>,
     self_id: Option<i64>,
     self_bot: bool,
 }
@@ -22,6 +24,7 @@ impl ChatHashCache {
     pub fn new(self_user: Option<(i64, bool)>) -> Self {
         Self {
             hash_map: HashMap::new(),
+            unsaved: HashSet::new(),
             self_id: self_user.map(|user| user.0),
             self_bot: self_user.map(|user| user.1).unwrap_or(false),
         }
@@ -53,6 +56,30 @@ impl ChatHashCache {
         })
     }
 
+    /// Load previously-known peers, such as those stored in the session.
+    ///
+    /// These are not considered unsaved, since they are assumed to have been loaded from storage.
+    pub fn load(&mut self, peers: impl IntoIterator<Item = PackedChat>) {
+        self.hash_map.extend(
+            peers
+                .into_iter()
+                .filter_map(|peer| Some((peer.id, (peer.access_hash?, peer.ty)))),
+        );
+    }
+
+    /// Take all the peers which were inserted or changed since the last call to this method,
+    /// so that they can be persisted in the session.
+    pub fn take_unsaved(&mut self) -> Vec<PackedChat> {
+        let unsaved = std::mem::take(&mut self.unsaved);
+        unsaved.into_iter().filter_map(|id| self.get(id)).collect()
+    }
+
+    fn insert(&mut self, id: i64, hash: i64, ty: PackedType) {
+        if self.hash_map.insert(id, (hash, ty)) != Some((hash, ty)) {
+            self.unsaved.insert(id);
+        }
+    }
+
     #[inline]
     fn has(&self, id: i64) -> bool {
         self.hash_map.contains_key(&id)
@@ -140,7 +167,7 @@ impl ChatHashCache {
                     } else {
                         PackedType::User
                     };
-                    self.hash_map.insert(u.id, (hash, ty));
+                    self.insert(u.id, hash, ty);
                 }
                 _ => success &= self.hash_map.contains_key(&u.id),
             },
@@ -157,7 +184,7 @@ impl ChatHashCache {
                     } else {
                         PackedType::Broadcast
                     };
-                    self.hash_map.insert(c.id, (hash, ty));
+                    self.insert(c.id, hash, ty);
                 }
                 _ => success &= self.hash_map.contains_key(&c.id),
             },
@@ -167,7 +194,7 @@ impl ChatHashCache {
                 } else {
                     PackedType::Broadcast
                 };
-                self.hash_map.insert(c.id, (c.access_hash, ty));
+                self.insert(c.id, c.access_hash, ty);
             }
         });
 
@@ -0,0 +1,135 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use std::net::SocketAddr;
+
+/// A known address for one of Telegram's datacenters, as returned by `help.getConfig`.
+///
+/// The same datacenter will commonly have several options, such as an IPv4 and an IPv6 address,
+/// or separate addresses meant for media downloads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DcOption {
+    /// Datacenter identifier.
+    pub id: i32,
+    /// Address and port of the datacenter.
+    pub addr: SocketAddr,
+    /// Whether the address should only be used to download media.
+    pub media_only: bool,
+    /// Whether the address belongs to a CDN datacenter.
+    pub cdn: bool,
+    /// Whether the address belongs to the test servers.
+    pub test: bool,
+    /// Whether the address was statically assigned, and may be used to bootstrap connections.
+    pub r#static: bool,
+    /// Whether only the given port may be used to connect (as opposed to any port).
+    pub this_port_only: bool,
+    /// Whether only obfuscated TCP connections are allowed.
+    pub tcpo_only: bool,
+    /// Secret to use for MTProxy connections.
+    pub secret: Option<Vec<u8>>,
+}
+
+impl DcOption {
+    /// Pick the preferred option for the given datacenter out of the list.
+    ///
+    /// CDN datacenters are never chosen, since they don't accept normal connections. Neither are
+    /// options which only allow obfuscated TCP, since a plain client may be unable to use them.
+    /// Options matching the requested IP version are preferred, and media-only options are only
+    /// used if `media` is `true`, in which case they are preferred.
+    pub fn select(options: &[DcOption], id: i32, ipv6: bool, media: bool) -> Option<&DcOption> {
+        options
+            .iter()
+            .filter(|o| o.id == id && o.usable() && (media || !o.media_only))
+            .max_by_key(|o| (o.addr.is_ipv6() == ipv6, o.media_only == media))
+    }
+
+    fn usable(&self) -> bool {
+        !self.cdn && !self.tcpo_only
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+
+    fn option(id: i32, ipv6: bool, media_only: bool, cdn: bool) -> DcOption {
+        DcOption {
+            id,
+            addr: if ipv6 {
+                SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 443, 0, 0))
+            } else {
+                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 443))
+            },
+            media_only,
+            cdn,
+            test: false,
+            r#static: false,
+            this_port_only: false,
+            tcpo_only: false,
+            secret: None,
+        }
+    }
+
+    #[test]
+    fn check_select() {
+        let options = [
+            option(2, false, false, true),
+            option(2, true, false, false),
+            option(2, false, true, false),
+            option(2, false, false, false),
+            option(4, false, false, false),
+        ];
+
+        assert_eq!(
+            DcOption::select(&options, 2, false, false),
+            Some(&options[3])
+        );
+        assert_eq!(
+            DcOption::select(&options, 2, true, false),
+            Some(&options[1])
+        );
+        assert_eq!(
+            DcOption::select(&options, 2, false, true),
+            Some(&options[2])
+        );
+        assert_eq!(DcOption::select(&options, 3, false, false), None);
+    }
+
+    #[test]
+    fn check_select_skips_unusable() {
+        let mut options = [
+            option(2, false, false, true),
+            option(2, false, false, false),
+        ];
+        options[1].tcpo_only = true;
+
+        for option in &options {
+            assert_eq!(
+                DcOption::select(std::slice::from_ref(option), 2, false, false),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn check_select_allows_single_port_and_test() {
+        let mut options = [
+            option(2, false, false, false),
+            option(2, false, false, false),
+        ];
+        options[0].this_port_only = true;
+        options[1].test = true;
+
+        for option in &options {
+            assert_eq!(
+                DcOption::select(std::slice::from_ref(option), 2, false, false),
+                Some(option)
+            );
+        }
+    }
+}
@@ -5,4 +5,7 @@
 // <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
+// Not every generated accessor is used (such as those for the old session versions).
+#![allow(dead_code)]
+
 include!(concat!(env!("OUT_DIR"), "/generated.rs"));
@@ -9,177 +9,120 @@
 #![deny(unsafe_code)]
 
 mod chat;
+mod dc_option;
 mod generated;
 mod message_box;
+mod storages;
 
 pub use chat::{ChatHashCache, PackedChat, PackedType};
+pub use dc_option::DcOption;
 pub use generated::types::UpdateState;
 pub use generated::types::User;
 pub use generated::LAYER as VERSION;
 use generated::{enums, types};
-use grammers_tl_types::deserialize::Error as DeserializeError;
 pub use message_box::{channel_id, PrematureEndReason};
 pub use message_box::{Gap, MessageBox};
 use std::fmt;
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, Write};
-use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
-use std::path::Path;
-use std::sync::Mutex;
+use std::net::SocketAddr;
+pub use storages::{FileSession, MemorySession};
 
 // Needed for auto-generated definitions.
 use grammers_tl_types::{deserialize, Deserializable, Identifiable, Serializable};
 
-pub struct Session {
-    session: Mutex<types::Session>,
-}
-
-#[allow(clippy::new_without_default)]
-impl Session {
-    pub fn new() -> Self {
-        Self {
-            session: Mutex::new(types::Session {
-                dcs: Vec::new(),
-                user: None,
-                state: None,
-            }),
-        }
+/// Storage for all the data that must persist between different runs of a client.
+///
+/// This includes the authorization keys to each datacenter, the known datacenter addresses, the
+/// logged-in user, the update state and the access hashes of known peers.
+///
+/// Methods take `&self` because the session is shared by the client and its connections.
+/// Implementations are expected to use interior mutability and must not block for long.
+pub trait Session: Send + Sync {
+    /// Returns `true` if a user is logged in.
+    fn signed_in(&self) -> bool {
+        self.get_user().is_some()
     }
 
-    /// Load a previous session instance from a file,
-    /// creating one if it doesn't exist
-    pub fn load_file_or_create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let path = path.as_ref();
-        if !path.exists() {
-            File::create(path)?;
-            let session = Session::new();
-            session.save_to_file(path)?;
-            Ok(session)
-        } else {
-            Self::load_file(path)
-        }
-    }
+    /// Returns the authorization key for the given datacenter, if any.
+    fn dc_auth_key(&self, dc_id: i32) -> Option<[u8; 256]>;
 
-    /// Load a previous session instance from a file.
-    pub fn load_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let mut data = Vec::new();
-        File::open(path.as_ref())?.read_to_end(&mut data)?;
+    /// Returns the address stored along with the authorization key for the given datacenter,
+    /// if any.
+    fn dc_addr(&self, dc_id: i32) -> Option<SocketAddr>;
 
-        Self::load(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-    }
+    /// Store the address and authorization key for the given datacenter, replacing any previous.
+    fn insert_dc(&self, id: i32, addr: SocketAddr, auth: [u8; 256]);
 
-    pub fn load(data: &[u8]) -> Result<Self, Error> {
-        Ok(Self {
-            session: Mutex::new(
-                enums::Session::from_bytes(data)
-                    .map_err(|e| match e {
-                        DeserializeError::UnexpectedEof => Error::MalformedData,
-                        DeserializeError::UnexpectedConstructor { .. } => Error::UnsupportedVersion,
-                    })?
-                    .into(),
-            ),
-        })
-    }
+    /// Forget the address and authorization key for the given datacenter, if any.
+    fn remove_dc(&self, id: i32);
 
-    pub fn signed_in(&self) -> bool {
-        self.session.lock().unwrap().user.is_some()
-    }
+    /// Returns all the known datacenter addresses.
+    fn get_dc_options(&self) -> Vec<DcOption>;
 
-    pub fn dc_auth_key(&self, dc_id: i32) -> Option<[u8; 256]> {
-        self.session
-            .lock()
-            .unwrap()
-            .dcs
-            .iter()
-            .filter_map(|enums::DataCenter::Center(dc)| {
-                if dc.id == dc_id {
-                    if let Some(auth) = &dc.auth {
-                        let mut bytes = [0; 256];
-                        bytes.copy_from_slice(auth);
-                        Some(bytes)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-            .next()
-    }
+    /// Store the datacenter addresses, replacing all the previously-known ones.
+    fn set_dc_options(&self, options: &[DcOption]);
 
-    pub fn insert_dc(&self, id: i32, addr: SocketAddr, auth: [u8; 256]) {
-        let mut session = self.session.lock().unwrap();
-        if let Some(pos) = session
-            .dcs
-            .iter()
-            .position(|enums::DataCenter::Center(dc)| dc.id == id)
-        {
-            session.dcs.remove(pos);
-        }
+    /// Returns the stored user, if any.
+    fn get_user(&self) -> Option<User>;
 
-        let (ip_v4, ip_v6): (Option<&SocketAddrV4>, Option<&SocketAddrV6>) = match &addr {
-            SocketAddr::V4(ip_v4) => (Some(ip_v4), None),
-            SocketAddr::V6(ip_v6) => (None, Some(ip_v6)),
-        };
-
-        session.dcs.push(
-            types::DataCenter {
-                id,
-                ipv4: ip_v4.map(|addr| i32::from_le_bytes(addr.ip().octets())),
-                ipv6: ip_v6.map(|addr| addr.ip().octets()),
-                port: addr.port() as i32,
-                auth: Some(auth.into()),
-            }
-            .into(),
-        );
-    }
+    /// Store the logged-in user, along with the datacenter it belongs to.
+    fn set_user(&self, id: i64, dc: i32, bot: bool);
 
-    pub fn set_user(&self, id: i64, dc: i32, bot: bool) {
-        self.session.lock().unwrap().user = Some(User { id, dc, bot }.into())
-    }
+    /// Forget the logged-in user, if any.
+    fn clear_user(&self);
 
-    /// Returns the stored user
-    pub fn get_user(&self) -> Option<User> {
-        self.session
-            .lock()
-            .unwrap()
-            .user
-            .as_ref()
-            .map(|enums::User::User(user)| user.clone())
-    }
+    /// Returns the stored update state, if any.
+    fn get_state(&self) -> Option<UpdateState>;
+
+    /// Store the update state.
+    fn set_state(&self, state: UpdateState);
+
+    /// Returns all the peers for which their access hash is known.
+    fn get_peers(&self) -> Vec<PackedChat>;
+
+    /// Store the input peers, replacing any previous peer with the same identifier.
+    fn insert_peers(&self, peers: &[PackedChat]);
 
-    pub fn get_state(&self) -> Option<UpdateState> {
-        let session = self.session.lock().unwrap();
-        let enums::UpdateState::State(state) = session.state.clone()?;
-        Some(state)
+    /// Returns the datacenter of the logged-in user or, if there is none, the first datacenter
+    /// with an authorization key, such as one imported from a string session.
+    fn home_dc_id(&self) -> Option<i32> {
+        // Telegram's datacenters are numbered from 1 to 5.
+        self.get_user()
+            .map(|user| user.dc)
+            .or_else(|| (1..=5).find(|&id| self.dc_auth_key(id).is_some()))
     }
 
-    pub fn set_state(&self, state: UpdateState) {
-        self.session.lock().unwrap().state = Some(state.into())
+    /// Import the datacenter address and authorization key from the string produced by
+    /// Telethon's `StringSession.save`.
+    ///
+    /// Telethon does not store the logged-in user, so none is stored, and the datacenter becomes
+    /// the [home datacenter](Session::home_dc_id) until a user is.
+    fn import_telethon_string(&self, string: &str) -> Result<(), Error> {
+        storages::import_telethon_string(self, string)
     }
 
-    pub fn get_dcs(&self) -> Vec<types::DataCenter> {
-        self.session
-            .lock()
-            .unwrap()
-            .dcs
-            .iter()
-            .map(|enums::DataCenter::Center(dc)| dc.clone())
-            .collect()
+    /// Export the home datacenter as a string which can be loaded by Telethon's `StringSession`.
+    ///
+    /// Returns `None` if the home datacenter or its authorization key are not known.
+    fn export_telethon_string(&self) -> Option<String> {
+        storages::export_telethon_string(self)
     }
 
-    #[must_use]
-    pub fn save(&self) -> Vec<u8> {
-        enums::Session::Session(self.session.lock().unwrap().clone()).to_bytes()
+    /// Import the datacenter, authorization key and logged-in user from the string produced by
+    /// Pyrogram's `export_session_string`, including the formats used by older versions.
+    ///
+    /// The address of the datacenter is not part of the string, so the one Pyrogram would use
+    /// is stored.
+    fn import_pyrogram_string(&self, string: &str) -> Result<(), Error> {
+        storages::import_pyrogram_string(self, string)
     }
 
-    /// Saves the session to a file.
-    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
-        let mut file = OpenOptions::new().write(true).open(path.as_ref())?;
-        file.seek(io::SeekFrom::Start(0))?;
-        file.set_len(0)?;
-        file.write_all(&self.save())?;
-        file.sync_data()
+    /// Export the session as a string which can be loaded by Pyrogram as its `session_string`.
+    ///
+    /// Pyrogram also stores the API ID used to create the session, so it must be given.
+    ///
+    /// Returns `None` if there is no logged-in user, or its datacenter is not known.
+    fn export_pyrogram_string(&self, api_id: i32) -> Option<String> {
+        storages::export_pyrogram_string(self, api_id)
     }
 }
 
@@ -354,6 +354,16 @@ impl MessageBox {
 
 // "Normal" updates flow (processing and detection of gaps).
 impl MessageBox {
+    /// Handle the server notifying that it created a new session.
+    ///
+    /// Updates that occurred while the previous session was in use may never be pushed through
+    /// the socket, so this begins getting difference (as long as there is some state to get the
+    /// difference from).
+    pub fn new_session_created(&mut self) {
+        info!("new session created, getting difference in case updates were lost");
+        self.try_begin_get_diff(Entry::AccountWide);
+    }
+
     /// Make sure all peer hashes contained in the update are known by the client
     /// (either by checking if they were already known, or by extending the hash cache
     /// with those that were not known).
@@ -1006,3 +1016,39 @@ pub enum PrematureEndReason {
     TemporaryServerIssues,
     Banned,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> tl::enums::updates::State {
+        tl::types::updates::State {
+            pts: 10,
+            qts: 20,
+            date: 30,
+            seq: 40,
+            unread_count: 0,
+        }
+        .into()
+    }
+
+    #[test]
+    fn new_session_forces_difference() {
+        let mut message_box = MessageBox::new();
+        message_box.set_state(state());
+        assert!(message_box.get_difference().is_none());
+
+        message_box.new_session_created();
+        let request = message_box.get_difference().unwrap();
+        assert_eq!(request.pts, 10);
+        assert_eq!(request.qts, 20);
+        assert_eq!(request.date, 30);
+    }
+
+    #[test]
+    fn new_session_without_state_is_ignored() {
+        let mut message_box = MessageBox::new();
+        message_box.new_session_created();
+        assert!(message_box.get_difference().is_none());
+    }
+}
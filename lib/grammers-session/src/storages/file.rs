@@ -0,0 +1,386 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use crate::generated::{enums, types};
+use crate::{DcOption, Error, PackedChat, PackedType, Session, UpdateState, User};
+use grammers_tl_types::deserialize::Error as DeserializeError;
+use grammers_tl_types::{Deserializable, Serializable};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Session storage which can be serialized to bytes and saved to a file.
+///
+/// Nothing is written to disk until [`FileSession::save_to_file`] is called.
+///
+/// Sessions saved by older versions of the library can still be loaded, and will be saved in the
+/// current format.
+pub struct FileSession {
+    session: Mutex<types::Session>,
+}
+
+#[allow(clippy::new_without_default)]
+impl FileSession {
+    pub fn new() -> Self {
+        Self {
+            session: Mutex::new(types::Session {
+                dcs: Vec::new(),
+                user: None,
+                state: None,
+                peers: Vec::new(),
+                dc_options: Vec::new(),
+            }),
+        }
+    }
+
+    /// Load a previous session instance from a file,
+    /// creating one if it doesn't exist
+    pub fn load_file_or_create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            File::create(path)?;
+            let session = FileSession::new();
+            session.save_to_file(path)?;
+            Ok(session)
+        } else {
+            Self::load_file(path)
+        }
+    }
+
+    /// Load a previous session instance from a file.
+    pub fn load_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut data = Vec::new();
+        File::open(path.as_ref())?.read_to_end(&mut data)?;
+
+        Self::load(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn load(data: &[u8]) -> Result<Self, Error> {
+        let session = match enums::Session::from_bytes(data).map_err(|e| match e {
+            DeserializeError::UnexpectedEof => Error::MalformedData,
+            DeserializeError::UnexpectedConstructor { .. } => Error::UnsupportedVersion,
+        })? {
+            enums::Session::Session(session) => session,
+            // Version 2 did not store any peers nor datacenter options.
+            enums::Session::V2(types::SessionV2 { dcs, user, state }) => types::Session {
+                dcs,
+                user,
+                state,
+                peers: Vec::new(),
+                dc_options: Vec::new(),
+            },
+        };
+
+        Ok(Self {
+            session: Mutex::new(session),
+        })
+    }
+
+    pub fn get_dcs(&self) -> Vec<types::DataCenter> {
+        self.session
+            .lock()
+            .unwrap()
+            .dcs
+            .iter()
+            .map(|enums::DataCenter::Center(dc)| dc.clone())
+            .collect()
+    }
+
+    #[must_use]
+    pub fn save(&self) -> Vec<u8> {
+        enums::Session::Session(self.session.lock().unwrap().clone()).to_bytes()
+    }
+
+    /// Saves the session to a file.
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).open(path.as_ref())?;
+        file.seek(io::SeekFrom::Start(0))?;
+        file.set_len(0)?;
+        file.write_all(&self.save())?;
+        file.sync_data()
+    }
+}
+
+impl Session for FileSession {
+    fn dc_auth_key(&self, dc_id: i32) -> Option<[u8; 256]> {
+        self.session
+            .lock()
+            .unwrap()
+            .dcs
+            .iter()
+            .filter_map(|enums::DataCenter::Center(dc)| {
+                if dc.id == dc_id {
+                    if let Some(auth) = &dc.auth {
+                        let mut bytes = [0; 256];
+                        bytes.copy_from_slice(auth);
+                        Some(bytes)
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            })
+            .next()
+    }
+
+    fn dc_addr(&self, dc_id: i32) -> Option<SocketAddr> {
+        self.session
+            .lock()
+            .unwrap()
+            .dcs
+            .iter()
+            .find_map(|enums::DataCenter::Center(dc)| {
+                if dc.id != dc_id {
+                    return None;
+                }
+                match (dc.ipv4, dc.ipv6) {
+                    (Some(ipv4), _) => Some(SocketAddr::V4(SocketAddrV4::new(
+                        Ipv4Addr::from(ipv4.to_le_bytes()),
+                        dc.port as u16,
+                    ))),
+                    (None, Some(ipv6)) => Some(SocketAddr::V6(SocketAddrV6::new(
+                        Ipv6Addr::from(ipv6),
+                        dc.port as u16,
+                        0,
+                        0,
+                    ))),
+                    (None, None) => None,
+                }
+            })
+    }
+
+    fn insert_dc(&self, id: i32, addr: SocketAddr, auth: [u8; 256]) {
+        let mut session = self.session.lock().unwrap();
+        session
+            .dcs
+            .retain(|enums::DataCenter::Center(dc)| dc.id != id);
+
+        let (ip_v4, ip_v6): (Option<&SocketAddrV4>, Option<&SocketAddrV6>) = match &addr {
+            SocketAddr::V4(ip_v4) => (Some(ip_v4), None),
+            SocketAddr::V6(ip_v6) => (None, Some(ip_v6)),
+        };
+
+        session.dcs.push(
+            types::DataCenter {
+                id,
+                ipv4: ip_v4.map(|addr| i32::from_le_bytes(addr.ip().octets())),
+                ipv6: ip_v6.map(|addr| addr.ip().octets()),
+                port: addr.port() as i32,
+                auth: Some(auth.into()),
+            }
+            .into(),
+        );
+    }
+
+    fn remove_dc(&self, id: i32) {
+        self.session
+            .lock()
+            .unwrap()
+            .dcs
+            .retain(|enums::DataCenter::Center(dc)| dc.id != id);
+    }
+
+    fn get_dc_options(&self) -> Vec<DcOption> {
+        self.session
+            .lock()
+            .unwrap()
+            .dc_options
+            .iter()
+            .filter_map(|enums::DcOption::Option(option)| {
+                let addr = match (option.ipv4, option.ipv6) {
+                    (_, Some(ipv6)) => SocketAddr::V6(SocketAddrV6::new(
+                        Ipv6Addr::from(ipv6),
+                        option.port as u16,
+                        0,
+                        0,
+                    )),
+                    (Some(ipv4), None) => SocketAddr::V4(SocketAddrV4::new(
+                        Ipv4Addr::from(ipv4.to_le_bytes()),
+                        option.port as u16,
+                    )),
+                    (None, None) => return None,
+                };
+                Some(DcOption {
+                    id: option.id,
+                    addr,
+                    media_only: option.media_only,
+                    cdn: option.cdn,
+                    test: option.test,
+                    r#static: option.r#static,
+                    this_port_only: option.this_port_only,
+                    tcpo_only: option.tcpo_only,
+                    secret: option.secret.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn set_dc_options(&self, options: &[DcOption]) {
+        self.session.lock().unwrap().dc_options = options
+            .iter()
+            .map(|option| {
+                let (ipv4, ipv6) = match &option.addr {
+                    SocketAddr::V4(addr) => (Some(i32::from_le_bytes(addr.ip().octets())), None),
+                    SocketAddr::V6(addr) => (None, Some(addr.ip().octets())),
+                };
+                types::DcOption {
+                    id: option.id,
+                    ipv4,
+                    ipv6,
+                    port: option.addr.port() as i32,
+                    media_only: option.media_only,
+                    cdn: option.cdn,
+                    test: option.test,
+                    r#static: option.r#static,
+                    this_port_only: option.this_port_only,
+                    tcpo_only: option.tcpo_only,
+                    secret: option.secret.clone(),
+                }
+                .into()
+            })
+            .collect();
+    }
+
+    fn get_user(&self) -> Option<User> {
+        self.session
+            .lock()
+            .unwrap()
+            .user
+            .as_ref()
+            .map(|enums::User::User(user)| user.clone())
+    }
+
+    fn set_user(&self, id: i64, dc: i32, bot: bool) {
+        self.session.lock().unwrap().user = Some(User { id, dc, bot }.into())
+    }
+
+    fn clear_user(&self) {
+        self.session.lock().unwrap().user = None
+    }
+
+    fn get_state(&self) -> Option<UpdateState> {
+        let session = self.session.lock().unwrap();
+        let enums::UpdateState::State(state) = session.state.clone()?;
+        Some(state)
+    }
+
+    fn set_state(&self, state: UpdateState) {
+        self.session.lock().unwrap().state = Some(state.into())
+    }
+
+    fn get_peers(&self) -> Vec<PackedChat> {
+        self.session
+            .lock()
+            .unwrap()
+            .peers
+            .iter()
+            .filter_map(|enums::Peer::Peer(peer)| {
+                Some(PackedChat {
+                    ty: PackedType::try_from(u8::try_from(peer.ty).ok()?).ok()?,
+                    id: peer.id,
+                    access_hash: peer.hash,
+                })
+            })
+            .collect()
+    }
+
+    fn insert_peers(&self, peers: &[PackedChat]) {
+        let mut session = self.session.lock().unwrap();
+        let ids = peers.iter().map(|peer| peer.id).collect::<HashSet<_>>();
+        session
+            .peers
+            .retain(|enums::Peer::Peer(peer)| !ids.contains(&peer.id));
+
+        session.peers.extend(peers.iter().map(|peer| {
+            types::Peer {
+                id: peer.id,
+                hash: peer.access_hash,
+                ty: peer.ty as i32,
+            }
+            .into()
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_peers_persisted() {
+        let peer = PackedChat {
+            ty: PackedType::Megagroup,
+            id: 123,
+            access_hash: Some(456),
+        };
+
+        let session = FileSession::new();
+        session.insert_peers(&[peer]);
+        let session = FileSession::load(&session.save()).unwrap();
+        assert_eq!(session.get_peers(), vec![peer]);
+    }
+
+    #[test]
+    fn check_dc_options_persisted() {
+        let options = [
+            DcOption {
+                id: 2,
+                addr: "149.154.167.51:443".parse().unwrap(),
+                media_only: false,
+                cdn: false,
+                test: false,
+                r#static: true,
+                this_port_only: false,
+                tcpo_only: false,
+                secret: None,
+            },
+            DcOption {
+                id: 2,
+                addr: "[2001:67c:4e8:f002::a]:443".parse().unwrap(),
+                media_only: true,
+                cdn: false,
+                test: false,
+                r#static: false,
+                this_port_only: true,
+                tcpo_only: false,
+                secret: Some(vec![1, 2, 3]),
+            },
+        ];
+
+        let session = FileSession::new();
+        session.set_dc_options(&options);
+        let session = FileSession::load(&session.save()).unwrap();
+        assert_eq!(session.get_dc_options(), options);
+    }
+
+    #[test]
+    fn check_v2_migration() {
+        let user = User {
+            id: 1,
+            dc: 2,
+            bot: false,
+        };
+        let data = enums::Session::V2(types::SessionV2 {
+            dcs: Vec::new(),
+            user: Some(user.clone().into()),
+            state: None,
+        })
+        .to_bytes();
+
+        let session = FileSession::load(&data).unwrap();
+        assert_eq!(session.get_user(), Some(user));
+        assert!(session.get_peers().is_empty());
+        assert!(matches!(
+            enums::Session::from_bytes(&session.save()),
+            Ok(enums::Session::Session(_))
+        ));
+    }
+}
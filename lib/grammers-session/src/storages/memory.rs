@@ -0,0 +1,150 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use crate::{DcOption, PackedChat, Session, UpdateState, User};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+/// Session storage which only lives in memory.
+///
+/// All data is lost once the session is dropped, which makes it suitable for short-lived
+/// clients and tests, or as a cache in front of a different storage.
+#[derive(Default)]
+pub struct MemorySession {
+    data: Mutex<Data>,
+}
+
+#[derive(Default)]
+struct Data {
+    dcs: HashMap<i32, (SocketAddr, [u8; 256])>,
+    dc_options: Vec<DcOption>,
+    user: Option<User>,
+    state: Option<UpdateState>,
+    peers: HashMap<i64, PackedChat>,
+}
+
+impl MemorySession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The identifiers of the datacenters with an authorization key.
+    pub(super) fn dc_ids(&self) -> Vec<i32> {
+        self.data.lock().unwrap().dcs.keys().copied().collect()
+    }
+}
+
+impl Session for MemorySession {
+    fn dc_auth_key(&self, dc_id: i32) -> Option<[u8; 256]> {
+        self.data
+            .lock()
+            .unwrap()
+            .dcs
+            .get(&dc_id)
+            .map(|&(_, auth)| auth)
+    }
+
+    fn dc_addr(&self, dc_id: i32) -> Option<SocketAddr> {
+        self.data
+            .lock()
+            .unwrap()
+            .dcs
+            .get(&dc_id)
+            .map(|&(addr, _)| addr)
+    }
+
+    fn insert_dc(&self, id: i32, addr: SocketAddr, auth: [u8; 256]) {
+        self.data.lock().unwrap().dcs.insert(id, (addr, auth));
+    }
+
+    fn remove_dc(&self, id: i32) {
+        self.data.lock().unwrap().dcs.remove(&id);
+    }
+
+    fn get_dc_options(&self) -> Vec<DcOption> {
+        self.data.lock().unwrap().dc_options.clone()
+    }
+
+    fn set_dc_options(&self, options: &[DcOption]) {
+        self.data.lock().unwrap().dc_options = options.to_vec();
+    }
+
+    fn get_user(&self) -> Option<User> {
+        self.data.lock().unwrap().user.clone()
+    }
+
+    fn set_user(&self, id: i64, dc: i32, bot: bool) {
+        self.data.lock().unwrap().user = Some(User { id, dc, bot });
+    }
+
+    fn clear_user(&self) {
+        self.data.lock().unwrap().user = None;
+    }
+
+    fn get_state(&self) -> Option<UpdateState> {
+        self.data.lock().unwrap().state.clone()
+    }
+
+    fn set_state(&self, state: UpdateState) {
+        self.data.lock().unwrap().state = Some(state);
+    }
+
+    fn get_peers(&self) -> Vec<PackedChat> {
+        self.data.lock().unwrap().peers.values().copied().collect()
+    }
+
+    fn insert_peers(&self, peers: &[PackedChat]) {
+        let mut data = self.data.lock().unwrap();
+        data.peers.extend(peers.iter().map(|&peer| (peer.id, peer)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PackedType;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    #[test]
+    fn check_dc_roundtrip() {
+        let session = MemorySession::new();
+        let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(149, 154, 167, 51), 443));
+
+        assert_eq!(session.dc_auth_key(2), None);
+        session.insert_dc(2, addr, [1; 256]);
+        session.insert_dc(2, addr, [2; 256]);
+        assert_eq!(session.dc_auth_key(2), Some([2; 256]));
+        assert_eq!(session.dc_addr(2), Some(addr));
+        assert_eq!(session.dc_auth_key(4), None);
+        session.remove_dc(2);
+        assert_eq!(session.dc_auth_key(2), None);
+    }
+
+    #[test]
+    fn check_peers_replaced() {
+        let session = MemorySession::new();
+        let peer = PackedChat {
+            ty: PackedType::User,
+            id: 123,
+            access_hash: Some(456),
+        };
+
+        session.insert_peers(&[peer]);
+        session.insert_peers(&[PackedChat {
+            access_hash: Some(789),
+            ..peer
+        }]);
+        assert_eq!(
+            session.get_peers(),
+            vec![PackedChat {
+                access_hash: Some(789),
+                ..peer
+            }]
+        );
+    }
+}
@@ -0,0 +1,18 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Built-in implementations of the [`Session`](crate::Session) trait.
+mod file;
+mod memory;
+mod string;
+
+pub use file::FileSession;
+pub use memory::MemorySession;
+pub(crate) use string::{
+    export_pyrogram_string, export_telethon_string, import_pyrogram_string, import_telethon_string,
+};
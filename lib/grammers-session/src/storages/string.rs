@@ -0,0 +1,408 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Conversions of sessions to and from strings, which are easier to move around than files,
+//! including the string sessions used by Telethon and Pyrogram.
+use super::{FileSession, MemorySession};
+use crate::{DcOption, Error, Session};
+use base64::alphabet;
+use base64::engine::general_purpose::{
+    GeneralPurpose, GeneralPurposeConfig, URL_SAFE, URL_SAFE_NO_PAD,
+};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+
+// Strings produced by other libraries may or may not be padded.
+const URL_SAFE_ANY_PAD: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+const TELETHON_VERSION: &str = "1";
+
+// Pyrogram strings only store the datacenter identifier, so these are the addresses it uses.
+const PYROGRAM_PORT: u16 = 443;
+const PYROGRAM_DC_ADDRESSES: [Ipv4Addr; 5] = [
+    Ipv4Addr::new(149, 154, 175, 53),
+    Ipv4Addr::new(149, 154, 167, 51),
+    Ipv4Addr::new(149, 154, 175, 100),
+    Ipv4Addr::new(149, 154, 167, 91),
+    Ipv4Addr::new(91, 108, 56, 130),
+];
+const PYROGRAM_TEST_DC_ADDRESSES: [Ipv4Addr; 3] = [
+    Ipv4Addr::new(149, 154, 175, 10),
+    Ipv4Addr::new(149, 154, 167, 40),
+    Ipv4Addr::new(149, 154, 175, 117),
+];
+
+fn decode(string: &str) -> Result<Vec<u8>, Error> {
+    URL_SAFE_ANY_PAD
+        .decode(string)
+        .map_err(|_| Error::MalformedData)
+}
+
+fn auth_key(data: &[u8]) -> [u8; 256] {
+    let mut auth = [0; 256];
+    auth.copy_from_slice(data);
+    auth
+}
+
+/// Copy everything stored in the session `from` into `to`, including the authorization keys of
+/// the datacenters in `dc_ids`.
+fn copy_session(from: &dyn Session, dc_ids: impl IntoIterator<Item = i32>, to: &dyn Session) {
+    for id in dc_ids {
+        if let (Some(addr), Some(auth)) = (from.dc_addr(id), from.dc_auth_key(id)) {
+            to.insert_dc(id, addr, auth);
+        }
+    }
+    to.set_dc_options(&from.get_dc_options());
+    if let Some(user) = from.get_user() {
+        to.set_user(user.id, user.dc, user.bot);
+    }
+    if let Some(state) = from.get_state() {
+        to.set_state(state);
+    }
+    to.insert_peers(&from.get_peers());
+}
+
+impl FileSession {
+    /// Load a session previously saved with [`FileSession::save_to_string`] or
+    /// [`MemorySession::save_to_string`].
+    pub fn load_string(string: &str) -> Result<Self, Error> {
+        Self::load(&decode(string)?)
+    }
+
+    /// Save the session as an url-safe base64 string, containing the same data as
+    /// [`FileSession::save`].
+    #[must_use]
+    pub fn save_to_string(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.save())
+    }
+}
+
+impl MemorySession {
+    /// Load a session previously saved with [`MemorySession::save_to_string`] or
+    /// [`FileSession::save_to_string`].
+    pub fn load_string(string: &str) -> Result<Self, Error> {
+        let file = FileSession::load_string(string)?;
+        let session = Self::new();
+        copy_session(&file, file.get_dcs().into_iter().map(|dc| dc.id), &session);
+        Ok(session)
+    }
+
+    /// Save the session as an url-safe base64 string, in the same format as
+    /// [`FileSession::save_to_string`].
+    #[must_use]
+    pub fn save_to_string(&self) -> String {
+        let file = FileSession::new();
+        copy_session(self, self.dc_ids(), &file);
+        file.save_to_string()
+    }
+}
+
+pub(crate) fn import_telethon_string<S: Session + ?Sized>(
+    session: &S,
+    string: &str,
+) -> Result<(), Error> {
+    let data = decode(
+        string
+            .strip_prefix(TELETHON_VERSION)
+            .ok_or(Error::UnsupportedVersion)?,
+    )?;
+
+    // dc_id:u8 ip:(4|16) port:u16 auth_key:256, all big-endian.
+    let ip = match data.len() {
+        263 => IpAddr::from(<[u8; 4]>::try_from(&data[1..5]).unwrap()),
+        275 => IpAddr::from(<[u8; 16]>::try_from(&data[1..17]).unwrap()),
+        _ => return Err(Error::MalformedData),
+    };
+    let rest = &data[data.len() - 258..];
+
+    let dc_id = data[0] as i32;
+    let port = u16::from_be_bytes([rest[0], rest[1]]);
+
+    session.insert_dc(dc_id, SocketAddr::new(ip, port), auth_key(&rest[2..]));
+    Ok(())
+}
+
+pub(crate) fn export_telethon_string<S: Session + ?Sized>(session: &S) -> Option<String> {
+    let (dc_id, addr, auth) = home_dc(session)?;
+
+    let mut data = Vec::with_capacity(275);
+    data.push(dc_id as u8);
+    match addr.ip() {
+        IpAddr::V4(ip) => data.extend(ip.octets()),
+        IpAddr::V6(ip) => data.extend(ip.octets()),
+    }
+    data.extend(addr.port().to_be_bytes());
+    data.extend(auth);
+
+    Some(format!("{TELETHON_VERSION}{}", URL_SAFE.encode(data)))
+}
+
+pub(crate) fn import_pyrogram_string<S: Session + ?Sized>(
+    session: &S,
+    string: &str,
+) -> Result<(), Error> {
+    let data = decode(string)?;
+
+    let (dc_id, test_mode, auth, user_id, bot) = match data.len() {
+        // dc_id:u8 test_mode:bool auth_key:256 user_id:u32 is_bot:bool
+        263 => (
+            data[0],
+            data[1] != 0,
+            &data[2..258],
+            u32::from_be_bytes(data[258..262].try_into().unwrap()) as i64,
+            data[262] != 0,
+        ),
+        // dc_id:u8 test_mode:bool auth_key:256 user_id:u64 is_bot:bool
+        267 => (
+            data[0],
+            data[1] != 0,
+            &data[2..258],
+            i64::from_be_bytes(data[258..266].try_into().unwrap()),
+            data[266] != 0,
+        ),
+        // dc_id:u8 api_id:u32 test_mode:bool auth_key:256 user_id:u64 is_bot:bool
+        271 => (
+            data[0],
+            data[5] != 0,
+            &data[6..262],
+            i64::from_be_bytes(data[262..270].try_into().unwrap()),
+            data[270] != 0,
+        ),
+        _ => return Err(Error::MalformedData),
+    };
+
+    let addresses = if test_mode {
+        &PYROGRAM_TEST_DC_ADDRESSES[..]
+    } else {
+        &PYROGRAM_DC_ADDRESSES[..]
+    };
+    let ip = *addresses
+        .get((dc_id as usize).wrapping_sub(1))
+        .ok_or(Error::MalformedData)?;
+
+    session.insert_dc(
+        dc_id as i32,
+        SocketAddr::V4(SocketAddrV4::new(ip, PYROGRAM_PORT)),
+        auth_key(auth),
+    );
+    session.set_user(user_id, dc_id as i32, bot);
+    Ok(())
+}
+
+pub(crate) fn export_pyrogram_string<S: Session + ?Sized>(
+    session: &S,
+    api_id: i32,
+) -> Option<String> {
+    let user = session.get_user()?;
+    let (dc_id, addr, auth) = home_dc(session)?;
+    let test_mode = matches!(addr.ip(), IpAddr::V4(ip) if PYROGRAM_TEST_DC_ADDRESSES.contains(&ip));
+
+    let mut data = Vec::with_capacity(271);
+    data.push(dc_id as u8);
+    data.extend(api_id.to_be_bytes());
+    data.push(test_mode as u8);
+    data.extend(auth);
+    data.extend(user.id.to_be_bytes());
+    data.push(user.bot as u8);
+
+    Some(URL_SAFE_NO_PAD.encode(data))
+}
+
+/// The home datacenter, along with its address and authorization key.
+///
+/// Other libraries expect an IPv4 address, so if the stored one is IPv6, an IPv4 option for the
+/// same datacenter is used instead when one is known.
+fn home_dc<S: Session + ?Sized>(session: &S) -> Option<(i32, SocketAddr, [u8; 256])> {
+    let dc_id = session.home_dc_id()?;
+    let auth = session.dc_auth_key(dc_id)?;
+    let mut addr = session.dc_addr(dc_id)?;
+    if addr.is_ipv6() {
+        if let Some(option) = DcOption::select(&session.get_dc_options(), dc_id, false, false) {
+            if option.addr.is_ipv4() {
+                addr = option.addr;
+            }
+        }
+    }
+    Some((dc_id, addr, auth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PackedChat, PackedType, User};
+
+    // Produced by Telethon and Pyrogram for DC 2 with an authorization key of `0..=255`.
+    const TELETHON_STRING: &str = concat!(
+        "1ApWapzMBuwABAgMEBQYHCAkKCwwNDg8QERITFBUWFxgZGhscHR4fICEiIyQlJicoKSorLC0uLzAxMjM0NTY3",
+        "ODk6Ozw9Pj9AQUJDREVGR0hJSktMTU5PUFFSU1RVVldYWVpbXF1eX2BhYmNkZWZnaGlqa2xtbm9wcXJzdHV2",
+        "d3h5ent8fX5_gIGCg4SFhoeIiYqLjI2Oj5CRkpOUlZaXmJmam5ydnp-goaKjpKWmp6ipqqusra6vsLGys7S1",
+        "tre4ubq7vL2-v8DBwsPExcbHyMnKy8zNzs_Q0dLT1NXW19jZ2tvc3d7f4OHi4-Tl5ufo6err7O3u7_Dx8vP0",
+        "9fb3-Pn6-_z9_v8=",
+    );
+    const PYROGRAM_STRING: &str = concat!(
+        "AgAAAAEAAAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8gISIjJCUmJygpKissLS4vMDEyMzQ1Njc4",
+        "OTo7PD0-P0BBQkNERUZHSElKS0xNTk9QUVJTVFVWV1hZWltcXV5fYGFiY2RlZmdoaWprbG1ub3BxcnN0dXZ3",
+        "eHl6e3x9fn-AgYKDhIWGh4iJiouMjY6PkJGSk5SVlpeYmZqbnJ2en6ChoqOkpaanqKmqq6ytrq-wsbKztLW2",
+        "t7i5uru8vb6_wMHCw8TFxsfIycrLzM3Oz9DR0tPU1dbX2Nna29zd3t_g4eLj5OXm5-jp6uvs7e7v8PHy8_T1",
+        "9vf4-fr7_P3-_wAAAAAAAATSAA",
+    );
+    // The format used by older versions of Pyrogram, for DC 4 and a bot.
+    const OLD_PYROGRAM_STRING: &str = concat!(
+        "BAAAAQIDBAUGBwgJCgsMDQ4PEBESExQVFhcYGRobHB0eHyAhIiMkJSYnKCkqKywtLi8wMTIzNDU2Nzg5Ojs8",
+        "PT4_QEFCQ0RFRkdISUpLTE1OT1BRUlNUVVZXWFlaW1xdXl9gYWJjZGVmZ2hpamtsbW5vcHFyc3R1dnd4eXp7",
+        "fH1-f4CBgoOEhYaHiImKi4yNjo-QkZKTlJWWl5iZmpucnZ6foKGio6SlpqeoqaqrrK2ur7CxsrO0tba3uLm6",
+        "u7y9vr_AwcLDxMXGx8jJysvMzc7P0NHS09TV1tfY2drb3N3e3-Dh4uPk5ebn6Onq6-zt7u_w8fLz9PX29_j5",
+        "-vv8_f7_AAAE0gE",
+    );
+
+    fn key() -> [u8; 256] {
+        std::array::from_fn(|i| i as u8)
+    }
+
+    fn user(id: i64, dc: i32, bot: bool) -> User {
+        User { id, dc, bot }
+    }
+
+    #[test]
+    fn check_string_roundtrip() {
+        let peer = PackedChat {
+            ty: PackedType::User,
+            id: 123,
+            access_hash: Some(456),
+        };
+
+        let session = FileSession::new();
+        session.insert_dc(2, "149.154.167.51:443".parse().unwrap(), key());
+        session.set_user(1234, 2, false);
+        session.insert_peers(&[peer]);
+
+        let string = session.save_to_string();
+        assert!(!string.contains(['+', '/', '=']));
+
+        let session = FileSession::load_string(&string).unwrap();
+        assert_eq!(session.dc_auth_key(2), Some(key()));
+        assert_eq!(session.get_user(), Some(user(1234, 2, false)));
+        assert_eq!(session.get_peers(), vec![peer]);
+        assert!(matches!(
+            FileSession::load_string("not base64!"),
+            Err(Error::MalformedData)
+        ));
+
+        let session = MemorySession::load_string(&string).unwrap();
+        assert_eq!(session.dc_auth_key(2), Some(key()));
+        assert_eq!(session.dc_addr(2), "149.154.167.51:443".parse().ok());
+        assert_eq!(session.get_user(), Some(user(1234, 2, false)));
+        assert_eq!(session.get_peers(), vec![peer]);
+        assert_eq!(session.save_to_string(), string);
+    }
+
+    #[test]
+    fn check_telethon_string() {
+        let sessions: [Box<dyn Session>; 2] =
+            [Box::new(FileSession::new()), Box::new(MemorySession::new())];
+        for session in sessions {
+            session.import_telethon_string(TELETHON_STRING).unwrap();
+            assert_eq!(session.dc_auth_key(2), Some(key()));
+            assert_eq!(session.dc_addr(2), "149.154.167.51:443".parse().ok());
+            assert_eq!(session.get_user(), None);
+            assert_eq!(session.home_dc_id(), Some(2));
+            assert_eq!(
+                session.export_telethon_string().as_deref(),
+                Some(TELETHON_STRING)
+            );
+        }
+    }
+
+    #[test]
+    fn check_telethon_string_ipv6() {
+        let session = FileSession::new();
+        session.insert_dc(2, "[2001:67c:4e8:f002::a]:443".parse().unwrap(), key());
+        session.set_user(1234, 2, false);
+
+        let string = session.export_telethon_string().unwrap();
+        assert_eq!(string.len(), 369);
+
+        let session = FileSession::new();
+        session.import_telethon_string(&string).unwrap();
+        assert_eq!(session.dc_auth_key(2), Some(key()));
+        assert_eq!(
+            session.get_dcs()[0].ipv6,
+            Some(*b"\x20\x01\x06\x7c\x04\xe8\xf0\x02\0\0\0\0\0\0\0\x0a")
+        );
+    }
+
+    #[test]
+    fn check_telethon_string_prefers_ipv4() {
+        let session = MemorySession::new();
+        session.insert_dc(2, "[2001:67c:4e8:f002::a]:443".parse().unwrap(), key());
+        session.set_dc_options(&[DcOption {
+            id: 2,
+            addr: "149.154.167.51:443".parse().unwrap(),
+            media_only: false,
+            cdn: false,
+            test: false,
+            r#static: false,
+            this_port_only: false,
+            tcpo_only: false,
+            secret: None,
+        }]);
+
+        assert_eq!(
+            session.export_telethon_string().as_deref(),
+            Some(TELETHON_STRING)
+        );
+    }
+
+    #[test]
+    fn check_invalid_telethon_string() {
+        let session = FileSession::new();
+        assert!(matches!(
+            session.import_telethon_string(&TELETHON_STRING.replacen('1', "2", 1)),
+            Err(Error::UnsupportedVersion)
+        ));
+        assert!(matches!(
+            session.import_telethon_string(&TELETHON_STRING[..100]),
+            Err(Error::MalformedData)
+        ));
+        assert_eq!(session.export_telethon_string(), None);
+    }
+
+    #[test]
+    fn check_pyrogram_string() {
+        let sessions: [Box<dyn Session>; 2] =
+            [Box::new(FileSession::new()), Box::new(MemorySession::new())];
+        for session in sessions {
+            session.import_pyrogram_string(PYROGRAM_STRING).unwrap();
+            assert_eq!(session.dc_auth_key(2), Some(key()));
+            assert_eq!(session.dc_addr(2), "149.154.167.51:443".parse().ok());
+            assert_eq!(session.get_user(), Some(user(1234, 2, false)));
+            assert_eq!(
+                session.export_pyrogram_string(1).as_deref(),
+                Some(PYROGRAM_STRING)
+            );
+        }
+    }
+
+    #[test]
+    fn check_old_pyrogram_string() {
+        let session = FileSession::new();
+        session.import_pyrogram_string(OLD_PYROGRAM_STRING).unwrap();
+        assert_eq!(session.dc_auth_key(4), Some(key()));
+        assert_eq!(session.get_user(), Some(user(1234, 4, true)));
+
+        assert!(matches!(
+            FileSession::new().import_pyrogram_string(&OLD_PYROGRAM_STRING[..100]),
+            Err(Error::MalformedData)
+        ));
+        assert_eq!(FileSession::new().export_pyrogram_string(1), None);
+    }
+}
@@ -340,6 +340,24 @@ impl<T: Deserializable> Deserializable for crate::RawVec<T> {
     }
 }
 
+impl Deserializable for crate::Blob {
+    /// Deserializes the remaining data in the buffer as-is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use grammers_tl_types::{Blob, Deserializable};
+    ///
+    /// assert_eq!(Blob::from_bytes(&[]).unwrap().0, Vec::<u8>::new());
+    /// assert_eq!(Blob::from_bytes(&[0x7f, 0x0, 0x0, 0x0]).unwrap().0, vec![0x7f, 0x0, 0x0, 0x0]);
+    /// ```
+    fn deserialize(buf: Buffer) -> Result<Self> {
+        let mut data = Vec::new();
+        buf.read_to_end(&mut data)?;
+        Ok(Self(data))
+    }
+}
+
 impl Deserializable for String {
     /// Deserializes a UTF-8 string according to the following definition:
     ///
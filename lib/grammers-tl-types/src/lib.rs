@@ -126,3 +126,10 @@ pub trait RemoteCall: Serializable {
     /// connection.
     type Return: Deserializable;
 }
+
+/// A blob is a request that was already serialized, so its response is left unparsed too.
+///
+/// Useful to hand a request over to code that doesn't (or can't) know its type.
+impl RemoteCall for Blob {
+    type Return = Blob;
+}
@@ -214,6 +214,22 @@ impl<T: Serializable> Serializable for crate::RawVec<T> {
     }
 }
 
+impl Serializable for crate::Blob {
+    /// Serializes the blob as-is, without any length prefix or padding.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use grammers_tl_types::{Blob, Serializable};
+    ///
+    /// assert_eq!(Blob(vec![]).to_bytes(), []);
+    /// assert_eq!(Blob(vec![0x7f, 0x0, 0x0, 0x0]).to_bytes(), [0x7f, 0x0, 0x0, 0x0]);
+    /// ```
+    fn serialize(&self, buf: &mut impl Extend<u8>) {
+        buf.extend(self.0.iter().copied())
+    }
+}
+
 impl Serializable for String {
     /// Serializes a UTF-8 string according to the following definition:
     ///